@@ -6,18 +6,16 @@ use std::iter::range_inclusive;
 use std::sync::Mutex;
 
 use common::block_position::BlockPosition;
-use common::communicate::ClientId;
+use common::communicate::{ClientId, WorldInfo};
 use common::entity::EntityId;
 use common::lod::LODIndex;
 use common::surroundings_loader::SurroundingsLoader;
 use common::terrain_block;
 use common::terrain_block::TerrainBlock;
 
+use config::Config;
 use terrain_buffers;
 
-/// The distances at which LOD switches.
-pub const LOD_THRESHOLDS: [i32; 3] = [1, 8, 32];
-
 /// The main client state.
 pub struct Client {
   #[allow(missing_docs)]
@@ -26,6 +24,10 @@ pub struct Client {
   pub player_id: EntityId,
   #[allow(missing_docs)]
   pub player_position: Mutex<Point3<f32>>,
+  /// What the server told us about the world.
+  pub world: WorldInfo,
+  /// The distances at which LOD switches.
+  pub lod_thresholds: Vec<i32>,
   #[allow(missing_docs)]
   pub max_load_distance: i32,
   #[allow(missing_docs)]
@@ -36,8 +38,15 @@ pub struct Client {
 
 impl Client {
   #[allow(missing_docs)]
-  pub fn new(client_id: ClientId, player_id: EntityId, position: Point3<f32>) -> Client {
-    let mut load_distance = load_distance(terrain_buffers::POLYGON_BUDGET as i32);
+  pub fn new(
+    client_id: ClientId,
+    player_id: EntityId,
+    position: Point3<f32>,
+    world: WorldInfo,
+    config: &Config,
+  ) -> Client {
+    let polygon_budget = config.byte_budget / terrain_buffers::POLYGON_COST;
+    let mut load_distance = load_distance(&config.lod_thresholds, polygon_budget as i32);
 
     // TODO: Remove this once our RAM usage doesn't skyrocket with load distance.
     let max_load_distance = 80;
@@ -51,7 +60,7 @@ impl Client {
     let surroundings_loader = {
       SurroundingsLoader::new(
         max_load_distance,
-        LOD_THRESHOLDS.iter().map(|&x| x).collect(),
+        config.lod_thresholds.clone(),
       )
     };
 
@@ -59,6 +68,8 @@ impl Client {
       id: client_id,
       player_id: player_id,
       player_position: Mutex::new(position),
+      world: world,
+      lod_thresholds: config.lod_thresholds.clone(),
       max_load_distance: load_distance,
       surroundings_loader: Mutex::new(surroundings_loader),
       loaded_blocks: Mutex::new(HashMap::new()),
@@ -68,13 +79,13 @@ impl Client {
 
 unsafe impl Sync for Client {}
 
-fn load_distance(mut polygon_budget: i32) -> i32 {
+fn load_distance(lod_thresholds: &[i32], mut polygon_budget: i32) -> i32 {
   // TODO: This should try to account for VRAM not used on a per-poly basis.
 
   let mut load_distance = 0;
   let mut prev_threshold = 0;
   let mut prev_square = 0;
-  for (&threshold, &quality) in LOD_THRESHOLDS.iter().zip(terrain_block::EDGE_SAMPLES.iter()) {
+  for (&threshold, &quality) in lod_thresholds.iter().zip(terrain_block::EDGE_SAMPLES.iter()) {
     let polygons_per_block = (quality * quality * 4) as i32;
     for i in range_inclusive(prev_threshold, threshold) {
       let i = 2 * i + 1;
@@ -95,7 +106,7 @@ fn load_distance(mut polygon_budget: i32) -> i32 {
   loop {
     let square = width * width;
     // The "to infinity and beyond" quality.
-    let quality = terrain_block::EDGE_SAMPLES[LOD_THRESHOLDS.len()];
+    let quality = terrain_block::EDGE_SAMPLES[lod_thresholds.len()];
     let polygons_per_block = (quality * quality * 4) as i32;
     let polygons_in_layer = (square - prev_square) * polygons_per_block;
     polygon_budget -= polygons_in_layer;
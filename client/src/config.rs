@@ -0,0 +1,82 @@
+//! Client settings, read from a config file and overridden on the command line.
+
+use std::str::FromStr;
+
+use common::flags::{Flag, Settings};
+use common::terrain_block;
+
+/// Every setting the client accepts.
+pub static FLAGS: &'static [Flag] = &[
+  Flag { name: "lod_thresholds", help: "Comma-separated block distances at which terrain gets coarser" },
+  Flag { name: "byte_budget", help: "VRAM bytes to spend on terrain" },
+  Flag { name: "frames_per_second", help: "How often to redraw" },
+];
+
+/// Settings for the client.
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// Blocks further away than `lod_thresholds[i]` are drawn at a lower quality than LOD `i`.
+  pub lod_thresholds: Vec<i32>,
+  #[allow(missing_docs)]
+  pub byte_budget: usize,
+  #[allow(missing_docs)]
+  pub frames_per_second: u64,
+}
+
+impl Config {
+  /// The default settings.
+  pub fn new() -> Config {
+    Config {
+      lod_thresholds: vec!(1, 8, 32),
+      byte_budget: 64_000_000,
+      frames_per_second: 30,
+    }
+  }
+}
+
+fn positive<T: FromStr + PartialEq + Default>(key: &str, value: &str) -> Result<T, String> {
+  let x: T = try!(value.parse().map_err(|_| format!("Invalid value for {}: {:?}", key, value)));
+  if x == T::default() {
+    return Err(format!("{} must be positive", key))
+  }
+  Ok(x)
+}
+
+fn lod_thresholds(value: &str) -> Result<Vec<i32>, String> {
+  let mut thresholds = Vec::new();
+  for x in value.split(',') {
+    let x: i32 = try!(positive("lod_thresholds", x.trim()));
+    if thresholds.last().map_or(false, |&last| x <= last) {
+      return Err(format!("lod_thresholds must be increasing, not {:?}", value))
+    }
+    thresholds.push(x);
+  }
+  // There's one more LOD than there are thresholds.
+  if thresholds.len() >= terrain_block::EDGE_SAMPLES.len() {
+    return Err(format!("lod_thresholds can have at most {} entries", terrain_block::EDGE_SAMPLES.len() - 1))
+  }
+  Ok(thresholds)
+}
+
+impl Settings for Config {
+  fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+    match key {
+      "lod_thresholds" => self.lod_thresholds = try!(lod_thresholds(value)),
+      "byte_budget" => self.byte_budget = try!(positive(key, value)),
+      "frames_per_second" => self.frames_per_second = try!(positive(key, value)),
+      _ => return Err(format!("Unknown setting {:?}", key)),
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn lod_thresholds_are_checked() {
+  let mut config = Config::new();
+  config.set("lod_thresholds", "2, 16").unwrap();
+  assert_eq!(config.lod_thresholds, vec!(2, 16));
+  assert!(config.set("lod_thresholds", "16,2").is_err());
+  assert!(config.set("lod_thresholds", "1,2,3,4").is_err());
+  assert!(config.set("lod_thresholds", "0").is_err());
+  assert!(config.set("frames_per_second", "0").is_err());
+}
@@ -6,7 +6,7 @@ use common::communicate::TerrainBlockSend;
 use common::lod::LODIndex;
 use common::surroundings_loader::radius_between;
 
-use client::{Client, LOD_THRESHOLDS};
+use client::Client;
 use view_update::ClientToView;
 
 pub fn load_terrain_block<UpdateView>(
@@ -29,7 +29,7 @@ pub fn load_terrain_block<UpdateView>(
     return;
   }
 
-  let lod = lod_index(distance);
+  let lod = lod_index(&client.lod_thresholds, distance);
   if lod != block.lod.0 {
     debug!(
       "Not loading {:?}: given LOD {:?} is not the desired LOD {:?}.",
@@ -63,12 +63,12 @@ pub fn load_terrain_block<UpdateView>(
   }
 }
 
-pub fn lod_index(distance: i32) -> LODIndex {
+pub fn lod_index(lod_thresholds: &[i32], distance: i32) -> LODIndex {
   assert!(distance >= 0);
   let mut lod = 0;
   while
-    lod < LOD_THRESHOLDS.len()
-    && LOD_THRESHOLDS[lod] < distance
+    lod < lod_thresholds.len()
+    && lod_thresholds[lod] < distance
   {
     lod += 1;
   }
@@ -1,18 +1,22 @@
 use env_logger;
 use std::env;
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use common::communicate::{ClientToServer, ServerToClient};
+use common::flags;
 use common::serialize;
 use common::serialize::Copyable;
 use common::socket::{SendSocket, ReceiveSocket};
 
 use client::Client;
+use config;
+use config::Config;
 use update_thread::update_thread;
 use view_thread::view_thread;
+use wakeup::Wakeup;
 
 // TODO: This is duplicated in the server. Fix that.
 #[inline(always)]
@@ -32,12 +36,25 @@ fn main() {
 
   let mut args = env::args();
   args.next().unwrap();
+  let args: Vec<String> = args.collect();
+  let mut config = Config::new();
+  let positional =
+    flags::parse_and_apply(
+      &mut config,
+      &args,
+      "client [<listen url>] [<server url>] [<player name>]",
+      config::FLAGS,
+      3,
+      |_| None,
+    );
+  let mut args = positional.into_iter();
   let listen_url = args.next().unwrap_or(String::from("ipc:///tmp/client.ipc"));
   let server_url = args.next().unwrap_or(String::from("ipc:///tmp/server.ipc"));
-  assert!(args.next().is_none());
+  let player_name = args.next().unwrap_or(String::from("player"));
 
   info!("Sending to {}.", server_url);
   info!("Listening on {}.", listen_url);
+  info!("Settings: {:?}", config);
 
   let (server_send_thread_send, server_send_thread_recv) = channel();
   let (server_recv_thread_send, mut server_recv_thread_recv) = channel();
@@ -54,26 +71,30 @@ fn main() {
   let quit = Mutex::new(false);
   let quit = &quit;
 
+  // Wakes the update thread when there's something new for it to look at.
+  let wakeup = Arc::new(Wakeup::new());
+
   let _server_recv_thread = {
     let listen_url = listen_url.clone();
     let server_recv_thread_send = server_recv_thread_send.clone();
+    let wakeup = wakeup.clone();
     thread::spawn(move || {
       let mut listen_socket =
         ReceiveSocket::new(listen_url.clone().as_ref(), Some(Duration::from_secs(30)));
       loop {
         let msg = listen_socket.read();
         server_recv_thread_send.send(msg).unwrap();
+        wakeup.notify();
       }
     })
   };
 
-  let _server_send_thread = {
+  let server_send_thread = {
     thread::spawn(move || {
       let mut talk_socket =
         SendSocket::new(server_url.clone().as_ref(), Some(Duration::from_secs(30)));
-      loop {
-        let msg: Option<ClientToServer> = server_send_thread_recv.recv().unwrap();
-        let msg = serialize::encode(&msg.unwrap()).unwrap();
+      while let Some(msg) = server_send_thread_recv.recv().unwrap() {
+        let msg = serialize::encode(&msg).unwrap();
         talk_socket.write(msg.as_ref());
       }
     })
@@ -82,17 +103,25 @@ fn main() {
   // TODO: Consider using RPCs to solidify the request-response patterns.
   server_send_thread_send.send(Some(ClientToServer::Init(listen_url.clone()))).unwrap();
   let client;
+  let player_rotation;
   'init_loop:loop {
     match server_recv_thread_recv.recv().map(|s| serialize::decode(s.as_ref()).unwrap()) {
-      Ok(ServerToClient::LeaseId(client_id)) => {
-        server_send_thread_send.send(Some(ClientToServer::AddPlayer(client_id))).unwrap();
+      Ok(ServerToClient::LeaseId(client_id, Copyable(world))) => {
+        info!("Joining {:?}", world);
+        server_send_thread_send.send(
+          Some(ClientToServer::AddPlayer(client_id, player_name.clone()))
+        ).unwrap();
         let client_id = client_id.0;
         loop {
           match server_recv_thread_recv.recv().map(|s| serialize::decode(s.as_ref()).unwrap()) {
-            Ok(ServerToClient::PlayerAdded(Copyable(player_id), Copyable(position))) => {
-              client = Client::new(client_id, player_id, position);
+            Ok(ServerToClient::PlayerAdded(Copyable(player_id), Copyable(position), Copyable(rotation))) => {
+              client = Client::new(client_id, player_id, position, world, &config);
+              player_rotation = rotation;
               break 'init_loop;
             },
+            Ok(ServerToClient::PlayerRejected(reason)) => {
+              panic!("The server won't let us play: {}", reason);
+            },
             Ok(msg) => {
               // Ignore other messages in the meantime.
               info!("Ignoring: {:?}", msg);
@@ -120,9 +149,11 @@ fn main() {
       let view_thread_send = view_thread_send.clone();
       let server_send_thread_send = server_send_thread_send.clone();
       let terrain_blocks_send = terrain_blocks_send.clone();
+      let wakeup = &*wakeup;
       thread::scoped(move || {
         update_thread(
           quit,
+          wakeup,
           client,
           &mut || {
             try_recv(server_recv_thread_recv)
@@ -131,16 +162,22 @@ fn main() {
           &mut || { try_recv(terrain_blocks_recv) },
           &mut |up| { view_thread_send.send(up).unwrap() },
 	        &mut |up| { server_send_thread_send.send(Some(up)).unwrap() },
-          &mut |block| { terrain_blocks_send.send(block).unwrap() },
+          &mut |block| {
+            terrain_blocks_send.send(block).unwrap();
+            wakeup.notify();
+          },
         )
       })
     };
 
     let view_thread = {
       let server_send_thread_send = server_send_thread_send.clone();
+      let config = &config;
       thread::scoped(move || {
         view_thread(
           client.player_id,
+          player_rotation,
+          config,
           &mut || {
             match view_thread_recv.try_recv() {
               Ok(msg) => Some(msg),
@@ -160,7 +197,13 @@ fn main() {
 
     // View thread returned, so we got a quit event.
     *quit.lock().unwrap() = true;
+    wakeup.notify();
   }
+
+  // Let the server save our player, and make sure the message gets out before we exit.
+  server_send_thread_send.send(Some(ClientToServer::RemovePlayer(Copyable(client.player_id)))).unwrap();
+  server_send_thread_send.send(None).unwrap();
+  server_send_thread.join().unwrap();
 }
 
 #[test]
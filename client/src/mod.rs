@@ -30,6 +30,7 @@ extern crate yaglw;
 
 mod camera;
 mod client;
+mod config;
 mod fontloader;
 mod hud;
 mod light;
@@ -48,3 +49,4 @@ mod vertex;
 mod view;
 mod view_thread;
 mod view_update;
+mod wakeup;
@@ -9,9 +9,12 @@ use sdl2::video;
 use std::f32::consts::PI;
 use stopwatch::TimerSet;
 
+use common::brush;
+use common::brush::{Brush, Operation, Shape};
 use common::communicate::ClientToServer;
 use common::entity::EntityId;
 use common::communicate::ClientToServer::*;
+use common::material::Material;
 use common::serialize::Copyable;
 
 use view::View;
@@ -23,13 +26,14 @@ pub fn process_event<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut View,
   window: &mut video::Window,
+  brush: &mut Brush,
   event: Event,
 ) where UpdateServer: FnMut(ClientToServer)
 {
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       if !repeat {
-        key_press(timers, player_id, update_server, view, keycode);
+        key_press(timers, player_id, update_server, view, brush, keycode);
       }
     },
     Event::KeyUp{keycode, repeat, ..} => {
@@ -52,6 +56,7 @@ fn key_press<UpdateServer>(
   player_id: EntityId,
   update_server: &mut UpdateServer,
   view: &mut View,
+  brush: &mut Brush,
   key: KeyCode,
 ) where UpdateServer: FnMut(ClientToServer)
 {
@@ -88,11 +93,92 @@ fn key_press<UpdateServer>(
         update_server(RotatePlayer(Copyable(player_id), Copyable(Vector2::new(0.0, -PI / 12.0))));
         view.camera.rotate_vertical(-PI / 12.0);
       },
+      KeyCode::Q => {
+        let brush = with_operation(brush, Operation::Subtract);
+        update_server(ApplyBrush(Copyable(player_id), Copyable(brush.encode())));
+      },
+      KeyCode::E => {
+        let brush = with_operation(brush, Operation::Add);
+        update_server(ApplyBrush(Copyable(player_id), Copyable(brush.encode())));
+      },
+      KeyCode::R => {
+        let brush = with_operation(brush, Operation::Smooth);
+        update_server(ApplyBrush(Copyable(player_id), Copyable(brush.encode())));
+      },
+      KeyCode::B => {
+        brush.shape = next_shape(&brush.shape);
+        info!("Brush shape: {:?}", brush.shape);
+      },
+      KeyCode::Equals => {
+        brush.shape = resize(&brush.shape, 1.0);
+        info!("Brush shape: {:?}", brush.shape);
+      },
+      KeyCode::Minus => {
+        brush.shape = resize(&brush.shape, -1.0);
+        info!("Brush shape: {:?}", brush.shape);
+      },
+      KeyCode::M => {
+        brush.material = next_material(brush.material);
+        info!("Brush material: {:?}", brush.material);
+      },
+      KeyCode::Z => {
+        update_server(Undo(Copyable(player_id)));
+      },
+      KeyCode::Y => {
+        update_server(Redo(Copyable(player_id)));
+      },
       _ => {},
     }
   })
 }
 
+/// The brush the brush keys start out with.
+pub fn default_brush() -> Brush {
+  Brush {
+    shape: Shape::Sphere(3.0),
+    operation: Operation::Subtract,
+    material: Material::Dirt,
+  }
+}
+
+fn with_operation(brush: &Brush, operation: Operation) -> Brush {
+  Brush {
+    operation: operation,
+    .. *brush
+  }
+}
+
+/// The same kind of shape, reaching `size` from its center along every axis.
+fn sized(shape: &Shape, size: f32) -> Shape {
+  match *shape {
+    Shape::Sphere(_) => Shape::Sphere(size),
+    Shape::Cuboid(_) => Shape::Cuboid(Vector3::new(size, size, size)),
+    Shape::Cylinder(_, _) => Shape::Cylinder(size, size),
+  }
+}
+
+/// Cycle from sphere to cube to cylinder.
+fn next_shape(shape: &Shape) -> Shape {
+  let size = shape.half_widths().x;
+  match *shape {
+    Shape::Sphere(_) => Shape::Cuboid(Vector3::new(size, size, size)),
+    Shape::Cuboid(_) => Shape::Cylinder(size, size),
+    Shape::Cylinder(_, _) => Shape::Sphere(size),
+  }
+}
+
+/// Grow or shrink a shape, keeping it between 1 and `brush::MAX_SIZE`.
+fn resize(shape: &Shape, by: f32) -> Shape {
+  let size = (shape.half_widths().x + by).max(1.0).min(brush::MAX_SIZE);
+  sized(shape, size)
+}
+
+/// Cycle through the solid materials.
+fn next_material(material: Material) -> Material {
+  // Solid materials are numbered from 1 up, and `from_u8` stops after the last one.
+  Material::from_u8(material as u8 + 1).unwrap_or(Material::Stone)
+}
+
 fn mouse_press<UpdateServer>(
   timers: &TimerSet,
   player_id: EntityId,
@@ -102,11 +188,16 @@ fn mouse_press<UpdateServer>(
 {
   timers.time("event.mouse_press", || {
     match mouse_btn {
+      Mouse::Left => {
+        update_server(
+          ClientToServer::AddVoxel(Copyable(player_id))
+        );
+      },
       Mouse::Right => {
         update_server(
           ClientToServer::RemoveVoxel(Copyable(player_id))
         );
-      }
+      },
       _ => {},
     }
   })
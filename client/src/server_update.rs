@@ -31,13 +31,13 @@ pub fn apply_server_update<UpdateView, UpdateServer, QueueBlock>(
   QueueBlock: FnMut(TerrainBlockSend),
 {
   match update {
-    ServerToClient::LeaseId(_) => {
+    ServerToClient::LeaseId(_, _) => {
       warn!("Client ID has already been leased.");
     },
     ServerToClient::Ping(Copyable(())) => {
       update_server(ClientToServer::Ping(Copyable(client.id)));
     },
-    ServerToClient::PlayerAdded(Copyable(id), _) => {
+    ServerToClient::PlayerAdded(Copyable(id), _, _) => {
       warn!("Unexpected PlayerAdded event: {:?}.", id);
     },
     ServerToClient::UpdatePlayer(Copyable(player_id), Copyable(bounds)) => {
@@ -55,6 +55,12 @@ pub fn apply_server_update<UpdateView, UpdateServer, QueueBlock>(
       *client.player_position.lock().unwrap() = position;
       update_view(ClientToView::MoveCamera(position));
     },
+    ServerToClient::PlayerRejected(reason) => {
+      warn!("Unexpected PlayerRejected event: {}.", reason);
+    },
+    ServerToClient::RemovePlayer(Copyable(player_id)) => {
+      update_view(ClientToView::RemovePlayer(player_id));
+    },
     ServerToClient::UpdateMob(Copyable(id), Copyable(bounds)) => {
       let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
       update_view(ClientToView::UpdateMob(id, mesh));
@@ -97,6 +103,11 @@ pub fn apply_server_update<UpdateView, UpdateServer, QueueBlock>(
     ServerToClient::UpdateBlock(block) => {
       queue_block(block);
     },
+    ServerToClient::VoxelRemoved(Copyable(player_id), Copyable(material)) => {
+      if player_id == client.player_id {
+        info!("Dug up {:?}", material);
+      }
+    },
   }
 }
 
@@ -53,6 +53,7 @@ impl<'a> TerrainShader<'a> {
         uniform vec3 ambient_light;
 
         uniform samplerBuffer positions;
+        uniform samplerBuffer materials;
 
         flat in int face_id;
         in vec3 world_position;
@@ -60,45 +61,52 @@ impl<'a> TerrainShader<'a> {
 
         out vec4 frag_color;
 
-        float perlin(const float x, const float y) {{
-          float amplitude = 1;
-          float frequency = 1.0 / 64.0;
-          float persistence = 0.8;
-          const float lacunarity = 2.4;
-          const int octaves = 2;
-
-          float r = 0.0;
-          float max_crest = 0.0;
-
-          for (int o = 0; o < octaves; ++o) {{
-            float f = frequency * 2 * 3.14;
-            r += amplitude * (sin((o+x) * f) + sin((o+y) * f)) / 2;
-
-            max_crest += amplitude;
-            amplitude *= persistence;
-            frequency *= lacunarity;
-          }}
-
-          // Scale to [-1, 1]. N.B. There is no clamping.
-          r /= max_crest;
-
-          return r;
-        }}
+        // These should match the discriminants of `Material`.
+        const int STONE = 1;
+        const int DIRT = 2;
+        const int GRASS = 3;
+        const int SAND = 4;
+        const int SNOW = 5;
+        const int WOOD = 6;
+        const int LEAVES = 7;
+        const int SLATE = 8;
+        const int GRANITE = 9;
+        const int COAL = 10;
+        const int IRON = 11;
+        const int GOLD = 12;
 
         void main() {{
-          int color_id = face_id * 3;
+          int material = int(texelFetch(materials, face_id).r);
 
           vec4 base_color;
-
-          float p = perlin(world_position.x, world_position.z);
-          // shift, scale, clamp to [0, 1]
-          p = (p + 1) / 2;
-          p = clamp(p, 0, 1);
-
-          base_color.r = (1 - p) / 2;
-          base_color.g = (3/2 + p) / 5;
-          base_color.b = (1 - p) / 5;
-          base_color.a = 1.0;
+          if (material == STONE) {{
+            base_color = vec4(0.5, 0.5, 0.5, 1.0);
+          }} else if (material == DIRT) {{
+            base_color = vec4(0.4, 0.3, 0.1, 1.0);
+          }} else if (material == GRASS) {{
+            base_color = vec4(0.3, 0.5, 0.1, 1.0);
+          }} else if (material == SAND) {{
+            base_color = vec4(0.8, 0.75, 0.5, 1.0);
+          }} else if (material == SNOW) {{
+            base_color = vec4(0.95, 0.95, 1.0, 1.0);
+          }} else if (material == WOOD) {{
+            base_color = vec4(0.35, 0.2, 0.1, 1.0);
+          }} else if (material == LEAVES) {{
+            base_color = vec4(0.1, 0.4, 0.1, 1.0);
+          }} else if (material == SLATE) {{
+            base_color = vec4(0.3, 0.32, 0.36, 1.0);
+          }} else if (material == GRANITE) {{
+            base_color = vec4(0.6, 0.5, 0.48, 1.0);
+          }} else if (material == COAL) {{
+            base_color = vec4(0.12, 0.12, 0.12, 1.0);
+          }} else if (material == IRON) {{
+            base_color = vec4(0.6, 0.4, 0.3, 1.0);
+          }} else if (material == GOLD) {{
+            base_color = vec4(0.9, 0.75, 0.2, 1.0);
+          }} else {{
+            // Something's wrong; make it stand out.
+            base_color = vec4(1.0, 0.0, 1.0, 1.0);
+          }}
 
           // vector from here to the light
           vec3 light_path = light.position - world_position;
@@ -2,6 +2,7 @@
 
 use common::entity::EntityId;
 use common::id_allocator::IdAllocator;
+use common::material::Material;
 use common::terrain_block::Triangle;
 use gl;
 use gl::types::*;
@@ -17,10 +18,8 @@ const VERTICES_PER_TRIANGLE: u32 = 3;
 #[cfg(test)]
 use std::mem;
 
-// VRAM bytes
-pub const BYTE_BUDGET: usize = 64_000_000;
+// VRAM bytes per polygon
 pub const POLYGON_COST: usize = 100;
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
 
 /// Struct for loading/unloading/maintaining terrain data in VRAM.
 pub struct TerrainBuffers<'a> {
@@ -35,6 +34,7 @@ pub struct TerrainBuffers<'a> {
 
   vertex_positions: BufferTexture<'a, Triangle<Point3<GLfloat>>>,
   normals: BufferTexture<'a, Triangle<Vector3<GLfloat>>>,
+  materials: BufferTexture<'a, GLfloat>,
 }
 
 #[test]
@@ -51,6 +51,7 @@ impl<'a> TerrainBuffers<'a> {
   #[allow(missing_docs)]
   pub fn new<'b:'a>(
     gl: &'a mut GLContext,
+    polygon_budget: usize,
   ) -> TerrainBuffers<'b> {
     TerrainBuffers {
       id_to_index: HashMap::new(),
@@ -61,8 +62,9 @@ impl<'a> TerrainBuffers<'a> {
         empty_array
       },
       length: 0,
-      vertex_positions: BufferTexture::new(gl, gl::R32F, POLYGON_BUDGET),
-      normals: BufferTexture::new(gl, gl::R32F, POLYGON_BUDGET),
+      vertex_positions: BufferTexture::new(gl, gl::R32F, polygon_budget),
+      normals: BufferTexture::new(gl, gl::R32F, polygon_budget),
+      materials: BufferTexture::new(gl, gl::R32F, polygon_budget),
     }
   }
 
@@ -88,6 +90,7 @@ impl<'a> TerrainBuffers<'a> {
 
     bind("positions", self.vertex_positions.handle.gl_id);
     bind("normals", self.normals.handle.gl_id);
+    bind("materials", self.materials.handle.gl_id);
   }
 
   /// Add a series of entites into VRAM.
@@ -96,10 +99,12 @@ impl<'a> TerrainBuffers<'a> {
     gl: &mut GLContext,
     vertices: &[Triangle<Point3<GLfloat>>],
     normals: &[Triangle<Vector3<GLfloat>>],
+    materials: &[Material],
     ids: &[EntityId],
   ) -> bool {
     assert_eq!(vertices.len(), ids.len());
     assert_eq!(normals.len(), ids.len());
+    assert_eq!(materials.len(), ids.len());
 
     self.vertex_positions.buffer.byte_buffer.bind(gl);
     let success = self.vertex_positions.buffer.push(gl, vertices);
@@ -111,6 +116,11 @@ impl<'a> TerrainBuffers<'a> {
     let success = self.normals.buffer.push(gl, normals);
     assert!(success);
 
+    let materials: Vec<GLfloat> = materials.iter().map(|&m| m as u32 as GLfloat).collect();
+    self.materials.buffer.byte_buffer.bind(gl);
+    let success = self.materials.buffer.push(gl, materials.as_ref());
+    assert!(success);
+
     for &id in ids.iter() {
       self.id_to_index.insert(id, self.index_to_id.len());
       self.index_to_id.push(id);
@@ -141,6 +151,9 @@ impl<'a> TerrainBuffers<'a> {
 
     self.normals.buffer.byte_buffer.bind(gl);
     self.normals.buffer.swap_remove(gl, idx, 1);
+
+    self.materials.buffer.byte_buffer.bind(gl);
+    self.materials.buffer.swap_remove(gl, idx, 1);
   }
 
   /// Draw the terrain.
@@ -1,5 +1,4 @@
 use std::sync::Mutex;
-use std::thread;
 use time;
 
 use common::block_position::BlockPosition;
@@ -12,9 +11,13 @@ use client::Client;
 use load_terrain::{load_terrain_block, lod_index};
 use server_update::apply_server_update;
 use view_update::ClientToView;
+use wakeup::Wakeup;
 
+/// Sleeps on `wakeup` whenever there's nothing to do, so anything that queues work for this thread
+/// (including setting `quit`) should `notify` it afterwards.
 pub fn update_thread<RecvServer, RecvBlock, UpdateView, UpdateServer, QueueBlock>(
   quit: &Mutex<bool>,
+  wakeup: &Wakeup,
   client: &Client,
   recv_server: &mut RecvServer,
   recv_block: &mut RecvBlock,
@@ -52,13 +55,14 @@ pub fn update_thread<RecvServer, RecvBlock, UpdateView, UpdateServer, QueueBlock
           |lod_change| {
             match lod_change {
               LODChange::Load(block_position, distance) => {
-                let lod = lod_index(distance);
+                let lod = lod_index(&client.lod_thresholds, distance);
                 let loaded_lod =
                   client.loaded_blocks
                   .lock().unwrap()
                   .get(&block_position)
                   .map(|&(_, lod)| lod);
-                if loaded_lod != Some(lod) {
+                // There's nothing outside the world to load.
+                if loaded_lod != Some(lod) && client.world.contains(&block_position) {
                   update_server(
                     ClientToServer::RequestBlock(
                       Copyable(client.id),
@@ -96,7 +100,7 @@ pub fn update_thread<RecvServer, RecvBlock, UpdateView, UpdateServer, QueueBlock
           block,
         );
       } else {
-        thread::yield_now();
+        wakeup.wait_until(surroundings_timer.next());
       }
     }
   }
@@ -2,7 +2,6 @@
 
 use cgmath;
 use cgmath::Vector2;
-use std::f32::consts::PI;
 use yaglw::gl_context::GLContext;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
 use yaglw::texture::{Texture2D, TextureUnit};
@@ -52,12 +51,12 @@ pub struct View<'a> {
 
 impl<'a> View<'a> {
   #[allow(missing_docs)]
-  pub fn new(mut gl: GLContext, window_size: Vector2<i32>) -> View<'a> {
+  pub fn new(mut gl: GLContext, window_size: Vector2<i32>, polygon_budget: usize) -> View<'a> {
     let mut texture_unit_alloc = IdAllocator::new();
 
     let mut shaders = Shaders::new(&mut gl, window_size);
 
-    let terrain_buffers = TerrainBuffers::new(&mut gl);
+    let terrain_buffers = TerrainBuffers::new(&mut gl, polygon_budget);
     terrain_buffers.bind_glsl_uniforms(
       &mut gl,
       &mut texture_unit_alloc,
@@ -145,8 +144,6 @@ impl<'a> View<'a> {
         let mut camera = Camera::unit();
         // Initialize the projection matrix.
         camera.fov = cgmath::perspective(fovy, aspect, 0.1, 2048.0);
-        // TODO: This should use player rotation from the server.
-        camera.rotate_lateral(PI / 2.0);
         camera
       },
     }
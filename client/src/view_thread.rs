@@ -15,17 +15,19 @@ use common::communicate::ClientToServer;
 use common::entity::EntityId;
 use common::interval_timer::IntervalTimer;
 
+use config::Config;
 use hud::make_hud;
-use process_event::process_event;
+use process_event::{default_brush, process_event};
 use render::render;
+use terrain_buffers;
 use view::View;
 use view_update::{ClientToView, apply_client_to_view};
 
-pub const FRAMES_PER_SECOND: u64 = 30;
-
 #[allow(missing_docs)]
 pub fn view_thread<Recv, UpdateServer>(
   player_id: EntityId,
+  player_rotation: Vector2<f32>,
+  config: &Config,
   recv: &mut Recv,
   update_server: &mut UpdateServer,
 ) where
@@ -74,7 +76,9 @@ pub fn view_thread<Recv, UpdateServer>(
     Vector2::new(w, h)
   };
 
-  let mut view = View::new(gl, window_size);
+  let mut view = View::new(gl, window_size, config.byte_budget / terrain_buffers::POLYGON_COST);
+  view.camera.rotate_lateral(player_rotation.x);
+  view.camera.rotate_vertical(player_rotation.y);
 
   make_hud(&mut view);
 
@@ -82,10 +86,11 @@ pub fn view_thread<Recv, UpdateServer>(
   {
     let now = time::precise_time_ns();
     let nanoseconds_per_second = 1000000000;
-    render_timer = IntervalTimer::new(nanoseconds_per_second / FRAMES_PER_SECOND, now);
+    render_timer = IntervalTimer::new(nanoseconds_per_second / config.frames_per_second, now);
   }
 
   let mut has_focus = true;
+  let mut brush = default_brush();
 
   'game_loop:loop {
     'event_loop:loop {
@@ -122,6 +127,7 @@ pub fn view_thread<Recv, UpdateServer>(
               update_server,
               &mut view,
               &mut window,
+              &mut brush,
               event,
             );
           }
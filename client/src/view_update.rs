@@ -21,6 +21,8 @@ pub enum ClientToView {
 
   /// Update a player mesh.
   UpdatePlayer(EntityId, [ColoredVertex; VERTICES_PER_PLAYER]),
+  /// Remove a player mesh.
+  RemovePlayer(EntityId),
   /// Update a mob mesh.
   UpdateMob(EntityId, [ColoredVertex; VERTICES_PER_MOB]),
 
@@ -53,6 +55,9 @@ pub fn apply_client_to_view(up: ClientToView, view: &mut View) {
     ClientToView::UpdatePlayer(id, triangles) => {
       view.player_buffers.insert(&mut view.gl, id, &triangles);
     },
+    ClientToView::RemovePlayer(id) => {
+      view.player_buffers.swap_remove(&mut view.gl, id);
+    },
     ClientToView::SetPointLight(light) => {
       set_point_light(
         &mut view.shaders.terrain_shader.shader,
@@ -76,6 +81,7 @@ pub fn apply_client_to_view(up: ClientToView, view: &mut View) {
 
         block.vertex_coordinates.as_ref(),
         block.normals.as_ref(),
+        block.materials.as_ref(),
         block.ids.as_ref(),
       );
     },
@@ -0,0 +1,43 @@
+//! A flag that lets a thread sleep until another thread has work for it.
+
+use std::sync::{Mutex, Condvar};
+use time;
+
+/// Threads producing work call `notify`; the consuming thread calls `wait_until` when it's idle.
+/// Notifications aren't lost if they arrive while the consumer isn't waiting.
+pub struct Wakeup {
+  pending: Mutex<bool>,
+  condvar: Condvar,
+}
+
+impl Wakeup {
+  #[allow(missing_docs)]
+  pub fn new() -> Wakeup {
+    Wakeup {
+      pending: Mutex::new(false),
+      condvar: Condvar::new(),
+    }
+  }
+
+  /// Wake the waiting thread, or make its next `wait_until` return immediately.
+  pub fn notify(&self) {
+    *self.pending.lock().unwrap() = true;
+    self.condvar.notify_all();
+  }
+
+  /// Block until `notify` is called or `deadline` (in `time::precise_time_ns` units) passes.
+  pub fn wait_until(&self, deadline: u64) {
+    let mut pending = self.pending.lock().unwrap();
+    while !*pending {
+      let now = time::precise_time_ns();
+      if now >= deadline {
+        return;
+      }
+      // Round up so we don't wake just short of the deadline and spin.
+      let timeout_ms = (deadline - now + 999_999) / 1_000_000;
+      let (p, _) = self.condvar.wait_timeout_ms(pending, timeout_ms as u32).unwrap();
+      pending = p;
+    }
+    *pending = false;
+  }
+}
@@ -0,0 +1,183 @@
+//! Shapes and operations for editing terrain in bulk.
+
+use cgmath::{Aabb3, Point, Point3, Vector, Vector3};
+
+use material::Material;
+
+/// How far any brush's shape can reach from its center, along any axis.
+pub const MAX_SIZE: f32 = 16.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A shape, centered around the origin.
+pub enum Shape {
+  /// A ball with the given radius.
+  Sphere(f32),
+  /// An axis-aligned box with the given half-widths.
+  Cuboid(Vector3<f32>),
+  /// A y-aligned cylinder with the given radius and half-height.
+  Cylinder(f32, f32),
+}
+
+impl Shape {
+  /// Is `p` (relative to the shape's center) inside the shape?
+  pub fn contains(&self, p: &Vector3<f32>) -> bool {
+    match *self {
+      Shape::Sphere(radius) => {
+        p.dot(p) <= radius * radius
+      },
+      Shape::Cuboid(half_widths) => {
+        true
+        && p.x.abs() <= half_widths.x
+        && p.y.abs() <= half_widths.y
+        && p.z.abs() <= half_widths.z
+      },
+      Shape::Cylinder(radius, half_height) => {
+        p.y.abs() <= half_height && p.x*p.x + p.z*p.z <= radius * radius
+      },
+    }
+  }
+
+  /// How far the shape reaches from its center along each axis.
+  pub fn half_widths(&self) -> Vector3<f32> {
+    match *self {
+      Shape::Sphere(radius) => Vector3::new(radius, radius, radius),
+      Shape::Cuboid(half_widths) => half_widths,
+      Shape::Cylinder(radius, half_height) => Vector3::new(radius, half_height, radius),
+    }
+  }
+
+  /// Are the shape's dimensions all positive, and no bigger than `MAX_SIZE`?
+  pub fn is_valid(&self) -> bool {
+    let half_widths = self.half_widths();
+    [half_widths.x, half_widths.y, half_widths.z].iter().all(|&w| w > 0.0 && w <= MAX_SIZE)
+  }
+
+  /// A box containing the shape when it's centered at `center`.
+  pub fn bounds(&self, center: &Point3<f32>) -> Aabb3<f32> {
+    let half_widths = self.half_widths();
+    Aabb3::new(center.sub_v(&half_widths), center.add_v(&half_widths))
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// What a brush does to the terrain inside its shape.
+pub enum Operation {
+  /// Fill the shape in.
+  Add,
+  /// Carve the shape out.
+  Subtract,
+  /// Round off the terrain inside the shape.
+  Smooth,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A terrain edit that can be applied anywhere in the world.
+pub struct Brush {
+  #[allow(missing_docs)]
+  pub shape: Shape,
+  #[allow(missing_docs)]
+  pub operation: Operation,
+  /// What `Operation::Add` fills the shape with.
+  pub material: Material,
+}
+
+impl Brush {
+  /// The brush's wire format.
+  pub fn encode(&self) -> EncodedBrush {
+    let (shape, dimensions) =
+      match self.shape {
+        Shape::Sphere(radius) => (0, [radius, 0.0, 0.0]),
+        Shape::Cuboid(half_widths) => (1, [half_widths.x, half_widths.y, half_widths.z]),
+        Shape::Cylinder(radius, half_height) => (2, [radius, half_height, 0.0]),
+      };
+    let operation =
+      match self.operation {
+        Operation::Add => 0,
+        Operation::Subtract => 1,
+        Operation::Smooth => 2,
+      };
+    EncodedBrush {
+      shape: shape,
+      dimensions: dimensions,
+      operation: operation,
+      material: self.material as u8,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A brush as it's sent between client and server: plain tags and numbers, which any bytes are
+/// valid for, and which `decode` checks before they're trusted.
+pub struct EncodedBrush {
+  /// 0 for a sphere, 1 for a cuboid, or 2 for a cylinder.
+  pub shape: u8,
+  /// The shape's dimensions, in the order its `Shape` variant has them. Unused ones are 0.
+  pub dimensions: [f32; 3],
+  /// 0 to add, 1 to subtract, or 2 to smooth.
+  pub operation: u8,
+  #[allow(missing_docs)]
+  pub material: u8,
+}
+
+impl EncodedBrush {
+  /// The brush this encodes, or `None` if any of it is invalid, including shapes that aren't
+  /// `Shape::is_valid`.
+  pub fn decode(&self) -> Option<Brush> {
+    let d = self.dimensions;
+    let shape =
+      match self.shape {
+        0 => Shape::Sphere(d[0]),
+        1 => Shape::Cuboid(Vector3::new(d[0], d[1], d[2])),
+        2 => Shape::Cylinder(d[0], d[1]),
+        _ => return None,
+      };
+    if !shape.is_valid() {
+      return None
+    }
+    let operation =
+      match self.operation {
+        0 => Operation::Add,
+        1 => Operation::Subtract,
+        2 => Operation::Smooth,
+        _ => return None,
+      };
+    Material::from_u8(self.material).map(|material| {
+      Brush {
+        shape: shape,
+        operation: operation,
+        material: material,
+      }
+    })
+  }
+}
+
+#[test]
+fn cylinder_contains() {
+  let shape = Shape::Cylinder(1.0, 2.0);
+  assert!(shape.contains(&Vector3::new(0.5, 1.5, 0.5)));
+  assert!(!shape.contains(&Vector3::new(0.0, 2.5, 0.0)));
+  assert!(!shape.contains(&Vector3::new(0.8, 0.0, 0.8)));
+}
+
+#[test]
+fn encoded_brushes_are_checked() {
+  let brush =
+    Brush {
+      shape: Shape::Cylinder(2.0, 3.0),
+      operation: Operation::Smooth,
+      material: Material::Sand,
+    };
+  assert_eq!(brush.encode().decode(), Some(brush));
+
+  let encoded = brush.encode();
+  let mut bad = [encoded; 6];
+  bad[0].shape = 3;
+  bad[1].operation = 200;
+  bad[2].material = 255;
+  bad[3].dimensions[0] = ::std::f32::NAN;
+  bad[4].dimensions[1] = 1e9;
+  bad[5].dimensions[1] = -1.0;
+  for encoded in bad.iter() {
+    assert_eq!(encoded.decode(), None);
+  }
+}
@@ -5,9 +5,12 @@ use std::default::Default;
 use std::ops::Add;
 
 use block_position::BlockPosition;
+use brush::EncodedBrush;
 use entity::EntityId;
 use lod::LODIndex;
+use material::Material;
 use serialize::{Copyable, Flatten, MemStream, EOF};
+use terrain_block;
 use terrain_block::TerrainBlock;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
@@ -29,6 +32,29 @@ impl Add<u32> for ClientId {
   }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The parts of the server's world configuration that clients need to know.
+pub struct WorldInfo {
+  /// The seed the terrain was generated from.
+  pub seed: u32,
+  /// How far the world extends from the origin along x and z, in world units.
+  pub width: u32,
+  /// How many times a second the server updates the world.
+  pub updates_per_second: u32,
+}
+
+impl WorldInfo {
+  /// Is any of this block inside the world's extent?
+  pub fn contains(&self, block: &BlockPosition) -> bool {
+    let low = block.to_world_position();
+    let width = self.width as f32;
+    let block_width = terrain_block::WIDTH as f32;
+    true
+    && low.x + block_width > -width && low.x < width
+    && low.z + block_width > -width && low.z < width
+  }
+}
+
 #[derive(Debug, Clone)]
 /// TerrainBlock plus identifying info, e.g. for transmission between server and client.
 pub struct TerrainBlockSend {
@@ -49,8 +75,9 @@ pub enum ClientToServer {
   Init(String),
   /// Ping
   Ping(Copyable<ClientId>),
-  /// Ask the server to create a new player.
-  AddPlayer(Copyable<ClientId>),
+  /// Ask the server to create a player with a given name,
+  /// picking up where they left off if they've played before.
+  AddPlayer(Copyable<ClientId>, String),
   /// Add a vector the player's acceleration.
   Walk(Copyable<EntityId>, Copyable<Vector3<f32>>),
   /// Rotate the player by some amount.
@@ -63,6 +90,16 @@ pub enum ClientToServer {
   RequestBlock(Copyable<ClientId>, Copyable<BlockPosition>, Copyable<LODIndex>),
   /// Remove the voxel the given player's looking at.
   RemoveVoxel(Copyable<EntityId>),
+  /// Place a voxel against the face the given player's looking at.
+  AddVoxel(Copyable<EntityId>),
+  /// Apply a brush centered where the given player's looking.
+  ApplyBrush(Copyable<EntityId>, Copyable<EncodedBrush>),
+  /// Undo the given player's latest terrain edit.
+  Undo(Copyable<EntityId>),
+  /// Redo the given player's latest undone terrain edit.
+  Redo(Copyable<EntityId>),
+  /// Save and remove a player whose client is going away.
+  RemovePlayer(Copyable<EntityId>),
 }
 
 flatten_enum_impl!(
@@ -70,27 +107,37 @@ flatten_enum_impl!(
   Copyable<u8>,
   (Init, Copyable(0), Copyable(0), x),
   (Ping, Copyable(1), Copyable(1), x),
-  (AddPlayer, Copyable(2), Copyable(2), x),
+  (AddPlayer, Copyable(2), Copyable(2), x, y),
   (Walk, Copyable(3), Copyable(3), x, y),
   (RotatePlayer, Copyable(4), Copyable(4), x, y),
   (StartJump, Copyable(5), Copyable(5), x),
   (StopJump, Copyable(6), Copyable(6), x),
   (RequestBlock, Copyable(7), Copyable(7), x, y, z),
   (RemoveVoxel, Copyable(8), Copyable(8), x),
+  (AddVoxel, Copyable(9), Copyable(9), x),
+  (ApplyBrush, Copyable(10), Copyable(10), x, y),
+  (Undo, Copyable(11), Copyable(11), x),
+  (Redo, Copyable(12), Copyable(12), x),
+  (RemovePlayer, Copyable(13), Copyable(13), x),
 );
 
 #[derive(Debug, Clone)]
 /// Messages the server sends to the client.
 pub enum ServerToClient {
-  /// Provide the client a unique id to tag its messages.
-  LeaseId(Copyable<ClientId>),
+  /// Provide the client a unique id to tag its messages, and tell it about the world.
+  LeaseId(Copyable<ClientId>, Copyable<WorldInfo>),
   /// Ping
   Ping(Copyable<()>),
 
-  /// Complete an AddPlayer request.
-  PlayerAdded(Copyable<EntityId>, Copyable<Point3<f32>>),
+  /// Complete an AddPlayer request with the player's position,
+  /// and their lateral and vertical rotation in radians.
+  PlayerAdded(Copyable<EntityId>, Copyable<Point3<f32>>, Copyable<Vector2<f32>>),
+  /// Refuse an AddPlayer request, saying why.
+  PlayerRejected(String),
   /// Update a player's position.
   UpdatePlayer(Copyable<EntityId>, Copyable<Aabb3<f32>>),
+  /// A player has left.
+  RemovePlayer(Copyable<EntityId>),
 
   /// Update the client's view of a mob with a given mesh.
   UpdateMob(Copyable<EntityId>, Copyable<Aabb3<f32>>),
@@ -100,16 +147,21 @@ pub enum ServerToClient {
 
   /// Provide a block of terrain to a client.
   UpdateBlock(TerrainBlockSend),
+  /// A player removed a voxel made of some material.
+  VoxelRemoved(Copyable<EntityId>, Copyable<Material>),
 }
 
 flatten_enum_impl!(
   ServerToClient,
   Copyable<u8>,
-  (LeaseId, Copyable(0), Copyable(0), x),
+  (LeaseId, Copyable(0), Copyable(0), x, y),
   (Ping, Copyable(1), Copyable(1), x),
-  (PlayerAdded, Copyable(2), Copyable(2), x, y),
+  (PlayerAdded, Copyable(2), Copyable(2), x, y, z),
   (UpdatePlayer, Copyable(3), Copyable(3), x, y),
   (UpdateMob, Copyable(4), Copyable(4), x, y),
   (UpdateSun, Copyable(5), Copyable(5), x),
   (UpdateBlock, Copyable(6), Copyable(6), x),
+  (VoxelRemoved, Copyable(7), Copyable(7), x, y),
+  (PlayerRejected, Copyable(8), Copyable(8), x),
+  (RemovePlayer, Copyable(9), Copyable(9), x),
 );
@@ -0,0 +1,220 @@
+//! Command-line flags and config files, shared by the server and client.
+//!
+//! Settings are given as `--name value` or `--name=value` on the command line, or as
+//! `name = value` lines in a config file (blank lines and lines starting with `#` are ignored).
+//! Dashes and underscores in names are interchangeable. Flags override the config file.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process;
+
+/// A named setting.
+pub struct Flag {
+  #[allow(missing_docs)]
+  pub name: &'static str,
+  /// A one-line description for `--help`.
+  pub help: &'static str,
+}
+
+/// Something settings can be applied to.
+pub trait Settings {
+  /// Change one setting, checking that the new value makes sense.
+  fn set(&mut self, name: &str, value: &str) -> Result<(), String>;
+}
+
+/// A parsed command line.
+pub struct Args {
+  /// Arguments that aren't flags, in order.
+  pub positional: Vec<String>,
+  /// `(name, value)` pairs, in the order they were given.
+  pub settings: Vec<(String, String)>,
+  /// The file given with `--config`, if any.
+  pub config: Option<String>,
+  /// Whether `--help` was given.
+  pub help: bool,
+}
+
+fn canonical_name(name: &str) -> String {
+  name.replace("-", "_")
+}
+
+/// Split the command line (without the program name) into flags and positional arguments.
+pub fn parse(args: &[String], flags: &[Flag]) -> Result<Args, String> {
+  let mut parsed = Args {
+    positional: Vec::new(),
+    settings: Vec::new(),
+    config: None,
+    help: false,
+  };
+
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with("--") {
+      parsed.positional.push(arg.clone());
+      continue
+    }
+
+    let mut parts = arg[2..].splitn(2, '=');
+    let name = canonical_name(parts.next().unwrap());
+    let inline_value = parts.next().map(|s| s.to_owned());
+
+    if name == "help" {
+      parsed.help = true;
+      continue
+    }
+
+    if name != "config" && !flags.iter().any(|flag| flag.name == name) {
+      return Err(format!("Unknown flag --{}", name))
+    }
+
+    let value =
+      match inline_value {
+        Some(value) => value,
+        None =>
+          match args.next() {
+            Some(value) => value.clone(),
+            None => return Err(format!("--{} needs a value", name)),
+          },
+      };
+
+    if name == "config" {
+      parsed.config = Some(value);
+    } else {
+      parsed.settings.push((name, value));
+    }
+  }
+
+  Ok(parsed)
+}
+
+/// Apply every setting in a config file.
+pub fn apply_file<S: Settings>(settings: &mut S, path: &Path) -> Result<(), String> {
+  let file = try!(File::open(path).map_err(|e| format!("Couldn't open {:?}: {}", path, e)));
+  for (i, line) in BufReader::new(file).lines().enumerate() {
+    let line = try!(line.map_err(|e| format!("Couldn't read {:?}: {}", path, e)));
+    let line = line.trim();
+    if line.is_empty() || line.starts_with("#") {
+      continue
+    }
+
+    let mut parts = line.splitn(2, '=');
+    let result =
+      match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) => settings.set(&canonical_name(name.trim()), value.trim()),
+        _ => Err(format!("Expected name = value, not {:?}", line)),
+      };
+    try!(result.map_err(|e| format!("{:?}, line {}: {}", path, i + 1, e)));
+  }
+  Ok(())
+}
+
+impl Args {
+  /// Apply the config file, then the flags on top of it. If no `--config` was given,
+  /// `default_config` is used instead, if it exists.
+  pub fn apply<S: Settings>(&self, settings: &mut S, default_config: Option<&Path>) -> Result<(), String> {
+    match self.config {
+      Some(ref path) => try!(apply_file(settings, Path::new(path))),
+      None => {
+        match default_config {
+          Some(path) if path.exists() => try!(apply_file(settings, path)),
+          _ => {},
+        }
+      },
+    }
+
+    for &(ref name, ref value) in self.settings.iter() {
+      try!(settings.set(name, value).map_err(|e| format!("--{}: {}", name, e)));
+    }
+
+    Ok(())
+  }
+}
+
+/// A description of the command line and every flag.
+pub fn usage(synopsis: &str, flags: &[Flag]) -> String {
+  let mut usage = format!("Usage: {} [--config <file>] [--<name> <value>...]\n\n", synopsis);
+  let width = flags.iter().map(|flag| flag.name.len()).max().unwrap_or(0);
+  for flag in flags.iter() {
+    usage.push_str(&format!("  --{:2$}  {}\n", flag.name, flag.help, width));
+  }
+  usage.push_str(&format!("  --{:1$}  Read settings from a file first\n", "config", width));
+  usage.push_str(&format!("  --{:1$}  Print this message\n", "help", width));
+  usage
+}
+
+/// Print an error and the usage, then exit.
+pub fn fail(error: &str, synopsis: &str, flags: &[Flag]) -> ! {
+  let mut stderr = io::stderr();
+  writeln!(stderr, "{}\n\n{}", error, usage(synopsis, flags)).unwrap();
+  process::exit(2);
+}
+
+/// Parse the command line and apply it to `settings`. `--help` prints the usage and exits, as
+/// does any invalid argument. Returns the positional arguments.
+pub fn parse_and_apply<S, DefaultConfig>(
+  settings: &mut S,
+  args: &[String],
+  synopsis: &str,
+  flags: &[Flag],
+  max_positional: usize,
+  default_config: DefaultConfig,
+) -> Vec<String> where
+  S: Settings,
+  DefaultConfig: FnOnce(&[String]) -> Option<String>,
+{
+  let args =
+    match parse(args, flags) {
+      Ok(args) => args,
+      Err(e) => fail(&e, synopsis, flags),
+    };
+
+  if args.help {
+    print!("{}", usage(synopsis, flags));
+    process::exit(0);
+  }
+
+  if args.positional.len() > max_positional {
+    fail(&format!("Too many arguments: {:?}", args.positional), synopsis, flags);
+  }
+
+  let default_config = default_config(&args.positional);
+  if let Err(e) = args.apply(settings, default_config.as_ref().map(|path| Path::new(path))) {
+    fail(&e, synopsis, flags);
+  }
+
+  args.positional
+}
+
+#[test]
+fn flags_override_in_order() {
+  struct Last(Vec<(String, String)>);
+  impl Settings for Last {
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+      if value.is_empty() {
+        return Err(String::from("empty"))
+      }
+      self.0.push((name.to_owned(), value.to_owned()));
+      Ok(())
+    }
+  }
+
+  let flags = [Flag { name: "a_b", help: "" }, Flag { name: "c", help: "" }];
+  let args: Vec<String> =
+    ["x", "--a-b", "1", "--c=2", "y", "--config", "f"].iter().map(|s| s.to_string()).collect();
+  let parsed = parse(&args, &flags).unwrap();
+  assert_eq!(parsed.positional, vec!("x".to_string(), "y".to_string()));
+  assert_eq!(parsed.config, Some("f".to_string()));
+
+  let mut last = Last(Vec::new());
+  parsed.apply(&mut last, None).unwrap_err();
+  assert!(last.0.is_empty());
+
+  let parsed = parse(&args[..5], &flags).unwrap();
+  parsed.apply(&mut last, None).unwrap();
+  assert_eq!(last.0, vec!(("a_b".to_string(), "1".to_string()), ("c".to_string(), "2".to_string())));
+
+  assert!(parse(&["--d".to_string()], &flags).is_err());
+  assert!(parse(&["--c".to_string()], &flags).is_err());
+}
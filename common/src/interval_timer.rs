@@ -26,6 +26,12 @@ impl IntervalTimer {
       r
     }
   }
+
+  #[inline]
+  /// The time at which the next interval will have elapsed.
+  pub fn next(&self) -> u64 {
+    self.next
+  }
 }
 
 #[test]
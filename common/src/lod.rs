@@ -71,6 +71,19 @@ impl LODMap {
     })
   }
 
+  /// Every position `owner` has a handle on.
+  pub fn owned_by(&self, owner: OwnerId) -> Vec<BlockPosition> {
+    self.loaded.iter()
+      .filter(|&(_, bls)| bls.owner_lods.iter().any(|&(o, _)| o == owner))
+      .map(|(position, _)| *position)
+      .collect()
+  }
+
+  /// Find out what LOD is actually loaded at `position`, if anything is.
+  pub fn loaded_lod(&self, position: &BlockPosition) -> Option<LOD> {
+    self.loaded.get(position).map(|bls| bls.loaded_lod)
+  }
+
   // TODO: Can probably get rid of the LODChange returns; we only assert with em.
 
   /// Acquire/update an owner's handle in `position`.
@@ -0,0 +1,60 @@
+//! What terrain is made of.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// The material of a piece of terrain.
+/// The discriminants are what the client's terrain shader sees.
+pub enum Material {
+  /// Not terrain at all.
+  Empty = 0,
+  #[allow(missing_docs)]
+  Stone = 1,
+  #[allow(missing_docs)]
+  Dirt = 2,
+  #[allow(missing_docs)]
+  Grass = 3,
+  #[allow(missing_docs)]
+  Sand = 4,
+  #[allow(missing_docs)]
+  Snow = 5,
+  #[allow(missing_docs)]
+  Wood = 6,
+  #[allow(missing_docs)]
+  Leaves = 7,
+  #[allow(missing_docs)]
+  Slate = 8,
+  #[allow(missing_docs)]
+  Granite = 9,
+  #[allow(missing_docs)]
+  Coal = 10,
+  #[allow(missing_docs)]
+  Iron = 11,
+  #[allow(missing_docs)]
+  Gold = 12,
+}
+
+impl Material {
+  /// The material with a given discriminant, if there is one.
+  pub fn from_u8(x: u8) -> Option<Material> {
+    match x {
+      0 => Some(Material::Empty),
+      1 => Some(Material::Stone),
+      2 => Some(Material::Dirt),
+      3 => Some(Material::Grass),
+      4 => Some(Material::Sand),
+      5 => Some(Material::Snow),
+      6 => Some(Material::Wood),
+      7 => Some(Material::Leaves),
+      8 => Some(Material::Slate),
+      9 => Some(Material::Granite),
+      10 => Some(Material::Coal),
+      11 => Some(Material::Iron),
+      12 => Some(Material::Gold),
+      _ => None,
+    }
+  }
+
+  /// Is this material solid, i.e. inside the terrain?
+  pub fn is_solid(&self) -> bool {
+    *self != Material::Empty
+  }
+}
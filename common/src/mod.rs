@@ -21,13 +21,16 @@ extern crate time;
 extern crate serialize as _serialize;
 
 pub mod block_position;
+pub mod brush;
 pub mod color;
 pub mod communicate;
 pub mod cube_shell;
 pub mod entity;
+pub mod flags;
 pub mod id_allocator;
 pub mod interval_timer;
 pub mod lod;
+pub mod material;
 pub mod range_abs;
 pub mod socket;
 pub mod surroundings_iter;
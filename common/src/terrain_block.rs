@@ -3,6 +3,7 @@
 use cgmath::{Point3, Vector3, Aabb3};
 
 use entity::EntityId;
+use material::Material;
 use serialize::{Flatten, MemStream, EOF};
 
 // TODO: Move the server-only parts to the server, like BLOCK_WIDTH and sample_info.
@@ -63,6 +64,8 @@ pub struct TerrainBlock {
   pub vertex_coordinates: Vec<Triangle<Point3<f32>>>,
   /// Vertex normals. These should be normalized!
   pub normals: Vec<Triangle<Vector3<f32>>>,
+  /// What each triangle is made of.
+  pub materials: Vec<Material>,
   /// Entity IDs for each triangle.
   pub ids: Vec<EntityId>,
   // TODO: Change this back to a HashMap once initial capacity is zero for those.
@@ -76,6 +79,7 @@ impl TerrainBlock {
     TerrainBlock {
       vertex_coordinates: Vec::new(),
       normals: Vec::new(),
+      materials: Vec::new(),
 
       ids: Vec::new(),
       bounds: Vec::new(),
@@ -83,4 +87,4 @@ impl TerrainBlock {
   }
 }
 
-flatten_struct_impl!(TerrainBlock, vertex_coordinates, normals, ids, bounds);
+flatten_struct_impl!(TerrainBlock, vertex_coordinates, normals, materials, ids, bounds);
@@ -1,28 +1,35 @@
-use cgmath::{Point, Point3, Vector3, Aabb3};
+use cgmath::{Point, Point3, Vector2, Vector3, Aabb3, Ray3};
 use std::convert::AsRef;
 use std::f32::consts::PI;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time::Duration;
+use stopwatch::TimerSet;
 
 use common::communicate::{ClientToServer, ServerToClient};
+use common::material::Material;
 use common::serialize;
 use common::serialize::Copyable;
 use common::socket::SendSocket;
 
 use player::Player;
+use player_store;
 use server::{Client, Server};
-use terrain;
+use terrain::raycast;
 use terrain::voxel;
 use terrain::voxel::Voxel;
 use update_gaia::{ServerToGaia, LoadReason};
 
+/// How far away players can dig or build.
+const MAX_REACH: f32 = 16.0;
+
 fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
   bounds.min.add_v(&bounds.max.to_vec()).mul_s(1.0 / 2.0)
 }
 
 #[inline]
 pub fn apply_client_update<UpdateGaia>(
+  timers: &TimerSet,
   server: &Server,
   update_gaia: &mut UpdateGaia,
   update: ClientToServer,
@@ -46,7 +53,9 @@ pub fn apply_client_update<UpdateGaia>(
       };
 
       let client_id = server.client_allocator.lock().unwrap().allocate();
-      to_client_send.send(Some(ServerToClient::LeaseId(Copyable(client_id)))).unwrap();
+      to_client_send.send(
+        Some(ServerToClient::LeaseId(Copyable(client_id), Copyable(server.world_info)))
+      ).unwrap();
 
       let client =
         Client {
@@ -63,32 +72,93 @@ pub fn apply_client_update<UpdateGaia>(
         .send(Some(ServerToClient::Ping(Copyable(()))))
         .unwrap();
     },
-    ClientToServer::AddPlayer(Copyable(client_id)) => {
+    ClientToServer::AddPlayer(Copyable(client_id), name) => {
+      // Hold this until the player's added, so nobody else can join with the same name meanwhile.
+      let mut players = server.players.lock().unwrap();
+
+      let rejection =
+        match player_store::check_name(&name) {
+          Err(e) => Some(e),
+          // Two players with the same name would overwrite each other's saves.
+          Ok(()) if players.values().any(|player| player.name == name) =>
+            Some(format!("{:?} is already playing", name)),
+          Ok(()) => None,
+        };
+      if let Some(rejection) = rejection {
+        warn!("Rejecting player {:?}: {}", name, rejection);
+        let clients = server.clients.lock().unwrap();
+        let client = clients.get(&client_id).unwrap();
+        client.sender.send(Some(ServerToClient::PlayerRejected(rejection))).unwrap();
+        return
+      }
+
       let mut player =
         Player::new(
           server.id_allocator.lock().unwrap().allocate(),
+          client_id,
+          name,
           &server.owner_allocator,
         );
 
-      let min = Point3::new(0.0, terrain::AMPLITUDE as f32, 4.0);
-      let max = min.add_v(&Vector3::new(1.0, 2.0, 1.0));
-      let bounds = Aabb3::new(min, max);
-      server.physics.lock().unwrap().insert_misc(player.entity_id, bounds.clone());
+      let saved =
+        match server.player_store.lock().unwrap().load(&player.name) {
+          Ok(saved) => saved,
+          Err(e) => {
+            warn!("Couldn't load player {:?}; starting them over: {}", player.name, e);
+            None
+          },
+        };
 
-      player.position = center(&bounds);
-      player.rotate_lateral(PI / 2.0);
+      let bounds =
+        match saved {
+          Some(saved) => {
+            info!("Restoring player {:?}.", player.name);
+            player.restore(&saved);
+            let half_size = Vector3::new(0.5, 1.0, 0.5);
+            Aabb3::new(player.position.sub_v(&half_size), player.position.add_v(&half_size))
+          },
+          None => {
+            let ground = server.terrain_loader.lock().unwrap().terrain.ground_height(0, 4);
+            let min = Point3::new(0.0, ground, 4.0);
+            let max = min.add_v(&Vector3::new(1.0, 2.0, 1.0));
+            let bounds = Aabb3::new(min, max);
+            player.position = center(&bounds);
+            player.rotate_lateral(PI / 2.0);
+            bounds
+          },
+        };
+      server.physics.lock().unwrap().insert_misc(player.entity_id, bounds);
 
       let id = player.entity_id;
       let pos = player.position;
+      let rotation = Vector2::new(player.lateral_rotation, player.vertical_rotation);
 
-      server.players.lock().unwrap().insert(id, player);
+      players.insert(id, player);
 
       let clients = server.clients.lock().unwrap();
       let client = clients.get(&client_id).unwrap();
       client.sender.send(
-        Some(ServerToClient::PlayerAdded(Copyable(id), Copyable(pos)))
+        Some(ServerToClient::PlayerAdded(Copyable(id), Copyable(pos), Copyable(rotation)))
       ).unwrap();
     },
+    ClientToServer::RemovePlayer(Copyable(player_id)) => {
+      let player = server.players.lock().unwrap().remove(&player_id);
+      match player {
+        None => warn!("Tried to remove nonexistent player {:?}.", player_id),
+        Some(player) => {
+          info!("Saving player {:?}.", player.name);
+          server.save_player(&player);
+          server.physics.lock().unwrap().remove_misc(player_id);
+          server.terrain_loader.lock().unwrap().terrain.history.forget(player_id);
+          player.release_surroundings(timers, server);
+
+          let clients = server.clients.lock().unwrap();
+          for client in clients.values() {
+            client.sender.send(Some(ServerToClient::RemovePlayer(Copyable(player_id)))).unwrap();
+          }
+        },
+      }
+    },
     ClientToServer::StartJump(Copyable(player_id)) => {
       let mut players = server.players.lock().unwrap();
       let player = players.get_mut(&player_id).unwrap();
@@ -122,31 +192,80 @@ pub fn apply_client_update<UpdateGaia>(
       update_gaia(ServerToGaia::Load(position, lod, LoadReason::ForClient(client_id)));
     },
     ClientToServer::RemoveVoxel(Copyable(player_id)) => {
-      let ray;
-      {
-        let players = server.players.lock().unwrap();
-        let player = players.get(&player_id).unwrap();
-        ray = player.forward_ray();
-      }
-
-      let bounds: Option<voxel::Bounds>;
-      {
-        let terrain_loader = server.terrain_loader.lock().unwrap();
-        bounds =
-          terrain_loader.terrain.voxels.cast_ray(
-            &ray,
-            &mut |bounds, voxel| {
-              match voxel {
-                &Voxel::Volume(false) => None,
-                _ => Some(bounds),
-              }
-            }
-          );
-      }
+      let ray = server.players.lock().unwrap().get(&player_id).unwrap().forward_ray();
+      first_voxel_hit(server, &ray).map(|hit| {
+        update_gaia(ServerToGaia::RemoveVoxel(player_id, hit.bounds));
+      });
+    },
+    ClientToServer::AddVoxel(Copyable(player_id)) => {
+      let ray = server.players.lock().unwrap().get(&player_id).unwrap().forward_ray();
+      let bounds =
+        first_voxel_hit(server, &ray)
+        .and_then(|hit| {
+          hit.face.map(|face| {
+            let face = face.normal();
+            let bounds = hit.bounds;
+            voxel::Bounds::new(bounds.x + face.x, bounds.y + face.y, bounds.z + face.z, bounds.lg_size)
+          })
+        });
 
       bounds.map(|bounds| {
-        update_gaia(ServerToGaia::RemoveVoxel(bounds));
+        // Don't bury anyone.
+        let size = bounds.size();
+        let low = Point3::new(bounds.x as f32, bounds.y as f32, bounds.z as f32).mul_s(size);
+        let voxel_box = Aabb3::new(low, low.add_v(&Vector3::new(size, size, size)));
+        let occupied = {
+          let players = server.players.lock().unwrap();
+          let physics = server.physics.lock().unwrap();
+          players.keys().any(|id| {
+            let bounds = physics.get_bounds(*id).unwrap();
+            true
+            && bounds.min.x < voxel_box.max.x && voxel_box.min.x < bounds.max.x
+            && bounds.min.y < voxel_box.max.y && voxel_box.min.y < bounds.max.y
+            && bounds.min.z < voxel_box.max.z && voxel_box.min.z < bounds.max.z
+          })
+        };
+
+        if !occupied {
+          // TODO: Let players choose what to build with.
+          update_gaia(ServerToGaia::AddVoxel(player_id, bounds, Material::Dirt));
+        }
+      });
+    },
+    ClientToServer::ApplyBrush(Copyable(player_id), Copyable(brush)) => {
+      let brush =
+        match brush.decode() {
+          Some(brush) => brush,
+          None => {
+            warn!("Ignoring an invalid brush from {:?}: {:?}", player_id, brush);
+            return
+          },
+        };
+      let ray = server.players.lock().unwrap().get(&player_id).unwrap().forward_ray();
+      first_voxel_hit(server, &ray).map(|hit| {
+        update_gaia(ServerToGaia::ApplyBrush(player_id, brush, hit.point));
       });
     },
+    ClientToServer::Undo(Copyable(player_id)) => {
+      update_gaia(ServerToGaia::Undo(player_id));
+    },
+    ClientToServer::Redo(Copyable(player_id)) => {
+      update_gaia(ServerToGaia::Redo(player_id));
+    },
   };
 }
+
+/// Find the first non-empty voxel along a ray, within a player's reach.
+fn first_voxel_hit(server: &Server, ray: &Ray3<f32>) -> Option<raycast::Hit> {
+  let mut terrain_loader = server.terrain_loader.lock().unwrap();
+  terrain_loader.terrain.voxels.cast_ray(
+    ray,
+    MAX_REACH,
+    &mut |hit, voxel| {
+      match voxel {
+        &Voxel::Volume(Material::Empty) => None,
+        _ => Some(*hit),
+      }
+    }
+  )
+}
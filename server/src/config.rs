@@ -0,0 +1,176 @@
+//! Server settings, read from the world's `config` file and overridden on the command line.
+//!
+//! The generation settings and the world's width only matter when a world is first created;
+//! after that, the ones it was created with are used.
+
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+use common::communicate::WorldInfo;
+use common::flags::{Flag, Settings};
+
+use terrain::density;
+use terrain::density::Density;
+use terrain::strata;
+use terrain::world;
+
+/// Every setting the server accepts.
+pub static FLAGS: &'static [Flag] = &[
+  Flag { name: "seed", help: "Seed for terrain generation" },
+  Flag { name: "amplitude", help: "Roughly how far the land rises and falls" },
+  Flag { name: "octaves", help: "Number of noise octaves in the terrain" },
+  Flag { name: "frequency", help: "Frequency of the lowest terrain octave" },
+  Flag { name: "persistence", help: "Amplitude multiplier between octaves" },
+  Flag { name: "lacunarity", help: "Frequency multiplier between octaves" },
+  Flag { name: "density", help: "File with the density function terrain is generated from" },
+  Flag { name: "biomes", help: "Whether to shape the terrain with biomes (true or false)" },
+  Flag { name: "caves", help: "Whether to carve caves out of the terrain (true or false)" },
+  Flag { name: "trees", help: "Whether to grow trees on the terrain (true or false)" },
+  Flag { name: "strata", help: "Whether to layer the rock and put ore in it (true or false)" },
+  Flag { name: "coal_rarity", help: "How rare coal is, from 0 (common) to 1 (none)" },
+  Flag { name: "coal_vein_size", help: "Roughly how wide veins of coal are" },
+  Flag { name: "iron_rarity", help: "How rare iron is, from 0 (common) to 1 (none)" },
+  Flag { name: "iron_vein_size", help: "Roughly how wide veins of iron are" },
+  Flag { name: "gold_rarity", help: "How rare gold is, from 0 (common) to 1 (none)" },
+  Flag { name: "gold_vein_size", help: "Roughly how wide veins of gold are" },
+  Flag { name: "world_width", help: "How far the world extends along x and z" },
+  Flag { name: "updates_per_second", help: "World updates per second" },
+  Flag { name: "sun_tick_ns", help: "Nanoseconds per 1/65536th of a day" },
+  Flag { name: "max_jump_fuel", help: "How many updates a jump can accelerate for" },
+];
+
+/// Settings for the server and its world.
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// What the terrain is generated from, and how far the world extends.
+  pub params: world::Params,
+  #[allow(missing_docs)]
+  pub updates_per_second: u64,
+  /// How long the sun takes to move 1/65536th of the way around its cycle.
+  pub sun_tick_ns: u64,
+  #[allow(missing_docs)]
+  pub max_jump_fuel: u32,
+}
+
+impl Config {
+  /// The default settings.
+  pub fn new() -> Config {
+    Config {
+      params: world::Params::new(0),
+      updates_per_second: 30,
+      sun_tick_ns: 5000000,
+      max_jump_fuel: 4,
+    }
+  }
+
+  /// The settings clients need to know.
+  pub fn world_info(&self) -> WorldInfo {
+    WorldInfo {
+      seed: self.params.seed,
+      width: self.params.width,
+      updates_per_second: self.updates_per_second as u32,
+    }
+  }
+}
+
+/// Read and check a density function file.
+fn read_density(path: &str, params: &world::Params) -> Result<String, String> {
+  let mut source = String::new();
+  try!(
+    File::open(path)
+    .and_then(|mut file| file.read_to_string(&mut source))
+    .map_err(|e| format!("Couldn't read {:?}: {}", path, e))
+  );
+  try!(Density::parse(&source, params).map_err(|e| format!("{:?}: {}", path, e)));
+  Ok(density::normalize(&source))
+}
+
+impl Settings for Config {
+  fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+    fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+      value.parse().map_err(|_| format!("Invalid value for {}: {:?}", key, value))
+    }
+
+    fn positive<T: FromStr + PartialEq + Default>(key: &str, value: &str) -> Result<T, String> {
+      let x: T = try!(parse(key, value));
+      if x == T::default() {
+        return Err(format!("{} must be positive", key))
+      }
+      Ok(x)
+    }
+
+    fn finite_positive(key: &str, value: &str) -> Result<f64, String> {
+      let x: f64 = try!(parse(key, value));
+      if !(x.is_finite() && x > 0.0) {
+        return Err(format!("{} must be positive", key))
+      }
+      Ok(x)
+    }
+
+    for (ore, vein) in strata::ORES.iter().zip(self.params.ores.iter_mut()) {
+      if key == format!("{}_rarity", ore.name) {
+        let rarity: f32 = try!(parse(key, value));
+        if !(rarity >= 0.0 && rarity <= 1.0) {
+          return Err(format!("{} must be between 0 and 1", key))
+        }
+        vein.rarity = rarity;
+        return Ok(())
+      }
+      if key == format!("{}_vein_size", ore.name) {
+        let size: f32 = try!(parse(key, value));
+        if !(size > 0.0) {
+          return Err(format!("{} must be positive", key))
+        }
+        vein.size = size;
+        return Ok(())
+      }
+    }
+
+    match key {
+      "seed" => self.params.seed = try!(parse(key, value)),
+      "amplitude" => self.params.amplitude = try!(finite_positive(key, value)),
+      "octaves" => self.params.octaves = try!(positive(key, value)),
+      "frequency" => self.params.frequency = try!(finite_positive(key, value)),
+      "persistence" => self.params.persistence = try!(finite_positive(key, value)),
+      "lacunarity" => self.params.lacunarity = try!(finite_positive(key, value)),
+      "density" => self.params.density = try!(read_density(value, &self.params)),
+      "biomes" => self.params.biomes = try!(parse(key, value)),
+      "caves" => self.params.caves = try!(parse(key, value)),
+      "trees" => self.params.trees = try!(parse(key, value)),
+      "strata" => self.params.strata = try!(parse(key, value)),
+      "world_width" => self.params.width = try!(positive(key, value)),
+      "updates_per_second" => self.updates_per_second = try!(positive(key, value)),
+      "sun_tick_ns" => self.sun_tick_ns = try!(positive(key, value)),
+      "max_jump_fuel" => self.max_jump_fuel = try!(parse(key, value)),
+      _ => return Err(format!("Unknown setting {:?}", key)),
+    }
+
+    Ok(())
+  }
+}
+
+#[test]
+fn apply_settings() {
+  let mut config = Config::new();
+  config.set("seed", "42").unwrap();
+  config.set("frequency", "0.5").unwrap();
+  assert_eq!(config.params.seed, 42);
+  assert_eq!(config.params.frequency, 0.5);
+  assert!(config.set("seed", "-1").is_err());
+  assert!(config.set("updates_per_second", "0").is_err());
+  assert!(config.set("colour", "blue").is_err());
+  config.set("gold_rarity", "0.9").unwrap();
+  assert_eq!(config.params.ores[2].rarity, 0.9);
+  assert!(config.set("gold_rarity", "2").is_err());
+  assert!(config.set("octaves", "0").is_err());
+  assert!(config.set("octaves", "1.5").is_err());
+  assert!(config.set("frequency", "0").is_err());
+  assert!(config.set("lacunarity", "inf").is_err());
+  assert!(config.set("persistence", "NaN").is_err());
+  assert!(config.set("amplitude", "-1").is_err());
+  assert_eq!(config.params.frequency, 0.5);
+  for flag in FLAGS.iter() {
+    assert!(config.set(flag.name, "1").err() != Some(format!("Unknown setting {:?}", flag.name)));
+  }
+}
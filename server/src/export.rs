@@ -0,0 +1,142 @@
+//! Write generated terrain out as a mesh, for inspecting or diffing in other tools.
+
+use cgmath::{Point3, Vector3};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::iter::range_inclusive;
+use std::path::Path;
+use std::sync::Mutex;
+use stopwatch::TimerSet;
+
+use common::block_position::BlockPosition;
+use common::id_allocator::IdAllocator;
+use common::lod::LODIndex;
+use common::terrain_block;
+use common::terrain_block::Triangle;
+
+use terrain::Terrain;
+
+/// A mesh file format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+  /// Wavefront OBJ.
+  Obj,
+  /// ASCII Stanford PLY.
+  Ply,
+}
+
+impl Format {
+  /// Guess the format from a file extension.
+  pub fn from_path(path: &Path) -> Option<Format> {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("obj") => Some(Format::Obj),
+      Some("ply") => Some(Format::Ply),
+      _ => None,
+    }
+  }
+}
+
+/// Triangle vertices, each paired with its normal.
+pub type Mesh = Vec<(Triangle<Point3<f32>>, Triangle<Vector3<f32>>)>;
+
+/// Generate every block from `low` to `high` (inclusive) at a given LOD, and collect their triangles.
+pub fn generate(
+  timers: &TimerSet,
+  terrain: &mut Terrain,
+  low: &BlockPosition,
+  high: &BlockPosition,
+  lod: LODIndex,
+) -> Mesh {
+  let id_allocator = Mutex::new(IdAllocator::new());
+  let mut mesh = Vec::new();
+  let (low, high) = (low.as_pnt(), high.as_pnt());
+  for x in range_inclusive(low.x, high.x) {
+  for y in range_inclusive(low.y, high.y) {
+  for z in range_inclusive(low.z, high.z) {
+    let block = terrain.load(timers, &id_allocator, &BlockPosition::new(x, y, z), lod);
+    mesh.extend(block.vertex_coordinates.iter().cloned().zip(block.normals.iter().cloned()));
+  }}}
+  mesh
+}
+
+/// Write `mesh` out in a given format.
+pub fn write<W: Write>(w: &mut W, format: Format, mesh: &Mesh) -> io::Result<()> {
+  match format {
+    Format::Obj => write_obj(w, mesh),
+    Format::Ply => write_ply(w, mesh),
+  }
+}
+
+fn write_obj<W: Write>(w: &mut W, mesh: &Mesh) -> io::Result<()> {
+  for &(ref vertices, ref normals) in mesh.iter() {
+    for v in [vertices.v1, vertices.v2, vertices.v3].iter() {
+      try!(writeln!(w, "v {} {} {}", v.x, v.y, v.z));
+    }
+    for n in [normals.v1, normals.v2, normals.v3].iter() {
+      try!(writeln!(w, "vn {} {} {}", n.x, n.y, n.z));
+    }
+  }
+  // OBJ indices are 1-based.
+  for i in 0..mesh.len() {
+    let i = 3 * i + 1;
+    try!(writeln!(w, "f {}//{} {}//{} {}//{}", i, i, i + 1, i + 1, i + 2, i + 2));
+  }
+  Ok(())
+}
+
+fn write_ply<W: Write>(w: &mut W, mesh: &Mesh) -> io::Result<()> {
+  try!(writeln!(w, "ply"));
+  try!(writeln!(w, "format ascii 1.0"));
+  try!(writeln!(w, "element vertex {}", 3 * mesh.len()));
+  for property in ["x", "y", "z", "nx", "ny", "nz"].iter() {
+    try!(writeln!(w, "property float {}", property));
+  }
+  try!(writeln!(w, "element face {}", mesh.len()));
+  try!(writeln!(w, "property list uchar int vertex_indices"));
+  try!(writeln!(w, "end_header"));
+  for &(ref vertices, ref normals) in mesh.iter() {
+    for &(v, n) in [(vertices.v1, normals.v1), (vertices.v2, normals.v2), (vertices.v3, normals.v3)].iter() {
+      try!(writeln!(w, "{} {} {} {} {} {}", v.x, v.y, v.z, n.x, n.y, n.z));
+    }
+  }
+  for i in 0..mesh.len() {
+    try!(writeln!(w, "3 {} {} {}", 3 * i, 3 * i + 1, 3 * i + 2));
+  }
+  Ok(())
+}
+
+/// Generate a region of terrain and write it to `path`, in the format its extension names.
+pub fn export(
+  timers: &TimerSet,
+  terrain: &mut Terrain,
+  low: &BlockPosition,
+  high: &BlockPosition,
+  lod: LODIndex,
+  path: &Path,
+) -> io::Result<()> {
+  let format =
+    try!(Format::from_path(path).ok_or(
+      io::Error::new(io::ErrorKind::Other, "mesh files should end in .obj or .ply")
+    ));
+  assert!((lod.0 as usize) < terrain_block::LG_SAMPLE_SIZE.len(), "No such LOD: {:?}", lod);
+
+  let mesh = timers.time("export.generate", || generate(timers, terrain, low, high, lod));
+  info!("Writing {} triangles to {:?}.", mesh.len(), path);
+  let mut file = BufWriter::new(try!(File::create(path)));
+  write(&mut file, format, &mesh)
+}
+
+#[test]
+fn obj_indices_are_one_based() {
+  use common::terrain_block::tri;
+
+  let v = Point3::new(0.0, 1.0, 2.0);
+  let n = Vector3::new(0.0, 1.0, 0.0);
+  let mesh = vec!((tri(v, v, v), tri(n, n, n)));
+  let mut bytes = Vec::new();
+  write(&mut bytes, Format::Obj, &mesh).unwrap();
+  let obj = String::from_utf8(bytes).unwrap();
+  assert_eq!(obj.lines().last(), Some("f 1//1 2//2 3//3"));
+  assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 3);
+}
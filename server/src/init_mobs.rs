@@ -5,7 +5,6 @@ use common::surroundings_loader::SurroundingsLoader;
 
 use mob;
 use server::Server;
-use terrain;
 
 fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
   bounds.min.add_v(&bounds.max.to_vec()).mul_s(0.5)
@@ -88,9 +87,10 @@ pub fn init_mobs(
     }
   }
 
+  let ground = server.terrain_loader.lock().unwrap().terrain.ground_height(0, -1);
   add_mob(
     server,
-    Point3::new(0.0, terrain::AMPLITUDE as f32, -1.0),
+    Point3::new(0.0, ground, -1.0),
     mob_behavior,
   );
 }
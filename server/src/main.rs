@@ -1,16 +1,31 @@
 use env_logger;
 use std::convert::AsRef;
 use std::env;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use std::sync::Mutex;
 use std::thread;
 use stopwatch::TimerSet;
 use time;
 
+use common::block_position::BlockPosition;
+use common::flags;
+use common::lod::LODIndex;
 use common::serialize as binary;
 use common::socket::ReceiveSocket;
+use common::terrain_block;
+
+use terrain::Terrain;
+use terrain::world::World;
 
 use client_recv_thread::apply_client_update;
+use config;
+use config::Config;
+use export::export;
+use map;
+use player_store::PlayerStore;
+use pregen::pregen;
 use server::Server;
 use update_gaia::update_gaia;
 use update_world::update_world;
@@ -52,18 +67,156 @@ impl<T> MapToBool<T> for Option<T> {
   }
 }
 
+fn config_path(world_path: &str) -> String {
+  format!("{}/config", world_path)
+}
+
+/// Open the world, updating `config` with the parameters it was created with.
+fn open_terrain(world_path: &str, config: &mut Config) -> Terrain {
+  let (world, params) = World::open(Path::new(world_path), &config.params).unwrap();
+  info!("Using world {} generated with {:?}.", world_path, params);
+  config.params = params;
+  Terrain::new(&config.params, Some(world))
+}
+
+/// A subcommand's arguments: exactly `count` positional ones, the first of which is the world,
+/// and the settings from that world's config file and the flags.
+fn subcommand_args(args: &[String], synopsis: &str, count: usize) -> (Vec<String>, Config) {
+  let mut config = Config::new();
+  let positional =
+    flags::parse_and_apply(
+      &mut config,
+      args,
+      synopsis,
+      config::FLAGS,
+      count,
+      |positional| positional.get(0).map(|world| config_path(world)),
+    );
+  if positional.len() != count {
+    flags::fail(&format!("Expected {} arguments, not {}", count, positional.len()), synopsis, config::FLAGS);
+  }
+  (positional, config)
+}
+
+/// The `i`th positional argument as a number, or exit if it isn't one.
+fn number_arg<T: FromStr>(args: &[String], i: usize, synopsis: &str) -> T {
+  match args[i].parse() {
+    Ok(x) => x,
+    Err(_) => flags::fail(&format!("Expected a number, not {:?}", args[i]), synopsis, config::FLAGS),
+  }
+}
+
+const EXPORT_SYNOPSIS: &'static str =
+  "server export <world> <lod> <x0> <y0> <z0> <x1> <y1> <z1> <file.obj|file.ply>";
+
+/// Write the blocks from (x0, y0, z0) to (x1, y1, z1) at a given LOD out to a mesh file.
+fn export_main(args: &[String]) {
+  let (args, mut config) = subcommand_args(args, EXPORT_SYNOPSIS, 9);
+  let lod: u32 = number_arg(&args, 1, EXPORT_SYNOPSIS);
+  if lod as usize >= terrain_block::LG_SAMPLE_SIZE.len() {
+    let error = format!("The LOD must be less than {}, not {}", terrain_block::LG_SAMPLE_SIZE.len(), lod);
+    flags::fail(&error, EXPORT_SYNOPSIS, config::FLAGS);
+  }
+  let low =
+    BlockPosition::new(
+      number_arg(&args, 2, EXPORT_SYNOPSIS),
+      number_arg(&args, 3, EXPORT_SYNOPSIS),
+      number_arg(&args, 4, EXPORT_SYNOPSIS),
+    );
+  let high =
+    BlockPosition::new(
+      number_arg(&args, 5, EXPORT_SYNOPSIS),
+      number_arg(&args, 6, EXPORT_SYNOPSIS),
+      number_arg(&args, 7, EXPORT_SYNOPSIS),
+    );
+
+  let mut terrain = open_terrain(&args[0], &mut config);
+  let timers = TimerSet::new();
+  export(&timers, &mut terrain, &low, &high, LODIndex(lod), Path::new(&args[8])).unwrap();
+  timers.print();
+}
+
+const MAP_SYNOPSIS: &'static str = "server map <world> <x0> <z0> <x1> <z1> <scale> <file.ppm>";
+
+/// Render a top-down map of the columns from (x0, z0) to (x1, z1), one pixel every `scale` units.
+fn map_main(args: &[String]) {
+  let (args, mut config) = subcommand_args(args, MAP_SYNOPSIS, 7);
+  let x0 = number_arg(&args, 1, MAP_SYNOPSIS);
+  let z0 = number_arg(&args, 2, MAP_SYNOPSIS);
+  let x1 = number_arg(&args, 3, MAP_SYNOPSIS);
+  let z1 = number_arg(&args, 4, MAP_SYNOPSIS);
+  let scale = number_arg(&args, 5, MAP_SYNOPSIS);
+  // Check before opening the world, which can take a while.
+  if let Err(e) = map::check_area(x0, z0, x1, z1, scale) {
+    flags::fail(&e, MAP_SYNOPSIS, config::FLAGS);
+  }
+
+  let mut terrain = open_terrain(&args[0], &mut config);
+  if let Err(e) = map::render_to(&mut terrain, x0, z0, x1, z1, scale, Path::new(&args[6])) {
+    panic!("Couldn't write the map to {:?}: {}", args[6], e);
+  }
+}
+
+const PREGEN_SYNOPSIS: &'static str = "server pregen <world> <x> <y> <z> <radius>";
+
+/// Generate and cache every block within `radius` blocks of block (x, y, z), at every LOD.
+fn pregen_main(args: &[String]) {
+  let (args, mut config) = subcommand_args(args, PREGEN_SYNOPSIS, 5);
+  let center =
+    BlockPosition::new(
+      number_arg(&args, 1, PREGEN_SYNOPSIS),
+      number_arg(&args, 2, PREGEN_SYNOPSIS),
+      number_arg(&args, 3, PREGEN_SYNOPSIS),
+    );
+  let radius: i32 = number_arg(&args, 4, PREGEN_SYNOPSIS);
+  if radius < 0 {
+    flags::fail(&format!("The radius can't be negative, not {}", radius), PREGEN_SYNOPSIS, config::FLAGS);
+  }
+
+  let mut terrain = open_terrain(&args[0], &mut config);
+  let timers = TimerSet::new();
+  pregen(&timers, &mut terrain, &center, radius).unwrap();
+  // Edits can be replayed from the journal while opening the world; don't leave them there.
+  terrain.save();
+  timers.print();
+}
+
 #[main]
 fn main() {
   env_logger::init().unwrap();
 
   let mut args = env::args();
   args.next().unwrap();
+  let args: Vec<String> = args.collect();
+  match args.get(0).map(|s| &s[..]) {
+    Some("export") => return export_main(&args[1..]),
+    Some("map") => return map_main(&args[1..]),
+    Some("pregen") => return pregen_main(&args[1..]),
+    _ => {},
+  }
+
+  let mut config = Config::new();
+  let positional =
+    flags::parse_and_apply(
+      &mut config,
+      &args,
+      "server [<listen url>] [<world directory>]",
+      config::FLAGS,
+      2,
+      |positional| Some(config_path(positional.get(1).map(|s| &s[..]).unwrap_or("world"))),
+    );
+  let mut positional = positional.into_iter();
   let listen_url
-    = args.next().unwrap_or(String::from("ipc:///tmp/server.ipc"));
-  assert!(args.next().is_none());
+    = positional.next().unwrap_or(String::from("ipc:///tmp/server.ipc"));
+  let world_path
+    = positional.next().unwrap_or(String::from("world"));
 
   info!("Listening on {}.", listen_url);
 
+  let terrain = open_terrain(&world_path, &mut config);
+  info!("Settings: {:?}", config);
+  let player_store = PlayerStore::open(&Path::new(&world_path).join("players")).unwrap();
+
   let (listen_thread_send, listen_thread_recv) = channel();
   let (gaia_thread_send, gaia_thread_recv) = channel();
 
@@ -81,7 +234,7 @@ fn main() {
     })
   };
 
-  let server = Server::new();
+  let server = Server::new(terrain, player_store, &config);
   let server = &server;
 
   // Add a thread that performs several actions repeatedly in a prioritized order:
@@ -122,11 +275,24 @@ fn main() {
             false
           }
         },
+        {
+          if server.save_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+            timers.time("terrain.save", || {
+              server.terrain_loader.lock().unwrap().terrain.save();
+            });
+            timers.time("players.save", || {
+              server.save_players();
+            });
+            true
+          } else {
+            false
+          }
+        },
         {
           listen_thread_recv.lock().unwrap().try_recv_opt()
             .map_to_bool(|up| {
               let up = binary::decode(up.as_ref()).unwrap();
-              apply_client_update(server, &mut |block| { gaia_thread_send.send(block).unwrap() }, up)
+              apply_client_update(timers, server, &mut |block| { gaia_thread_send.send(block).unwrap() }, up)
             })
         },
         {
@@ -0,0 +1,152 @@
+//! Render a top-down map of the world, without a GPU.
+
+use cgmath::{EuclideanVector, Vector, Vector3};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use common::material::Material;
+
+use terrain::Terrain;
+
+/// Where the light comes from when shading the map.
+const LIGHT_DIRECTION: [f32; 3] = [-1.0, 2.0, -1.0];
+/// How bright faces pointing away from the light are.
+const AMBIENT: f32 = 0.4;
+
+/// An RGB image.
+pub struct Image {
+  #[allow(missing_docs)]
+  pub width: usize,
+  #[allow(missing_docs)]
+  pub height: usize,
+  /// Row-major.
+  pub pixels: Vec<[u8; 3]>,
+}
+
+/// Roughly the colors the client draws each material with.
+fn color(material: Material) -> [f32; 3] {
+  match material {
+    Material::Empty => [0.0, 0.0, 0.0],
+    Material::Stone => [0.5, 0.5, 0.5],
+    Material::Dirt => [0.4, 0.3, 0.1],
+    Material::Grass => [0.3, 0.5, 0.1],
+    Material::Sand => [0.8, 0.75, 0.5],
+    Material::Snow => [0.95, 0.95, 1.0],
+    Material::Wood => [0.35, 0.2, 0.1],
+    Material::Leaves => [0.1, 0.4, 0.1],
+    Material::Slate => [0.3, 0.32, 0.36],
+    Material::Granite => [0.6, 0.5, 0.48],
+    Material::Coal => [0.12, 0.12, 0.12],
+    Material::Iron => [0.6, 0.4, 0.3],
+    Material::Gold => [0.9, 0.75, 0.2],
+  }
+}
+
+/// Check that (x0, z0) to (x1, z1) is an area that can be mapped with one pixel every `scale`
+/// world units.
+pub fn check_area(x0: i32, z0: i32, x1: i32, z1: i32, scale: i32) -> Result<(), String> {
+  if scale <= 0 {
+    return Err(format!("The map scale must be positive, not {}", scale))
+  }
+  if x1 < x0 || z1 < z0 {
+    return Err(format!("({}, {}) has to be above and left of ({}, {})", x0, z0, x1, z1))
+  }
+  Ok(())
+}
+
+/// Map the columns from (x0, z0) to (x1, z1), one pixel every `scale` world units.
+/// Each pixel is colored by the material at the top of its column, shaded by the slope of the
+/// surface there, and brightened by its height. x increases to the right and z down the image.
+pub fn render(terrain: &mut Terrain, x0: i32, z0: i32, x1: i32, z1: i32, scale: i32) -> Image {
+  check_area(x0, z0, x1, z1, scale).unwrap();
+  let width = ((x1 - x0) / scale + 1) as usize;
+  let height = ((z1 - z0) / scale + 1) as usize;
+  let (low, high) = terrain.surface_range();
+  let (low, high) = (low.floor() as i32, high.ceil() as i32);
+
+  let mut surface = Vec::with_capacity(width * height);
+  for j in 0..height {
+    for i in 0..width {
+      let x = x0 + i as i32 * scale;
+      let z = z0 + j as i32 * scale;
+      surface.push(terrain.surface(x, z, low, high));
+    }
+  }
+
+  let light = Vector3::new(LIGHT_DIRECTION[0], LIGHT_DIRECTION[1], LIGHT_DIRECTION[2]).normalize();
+  let height_at = |i: usize, j: usize| {
+    surface[j * width + i].map(|(y, _)| y as f32).unwrap_or(low as f32)
+  };
+
+  let mut pixels = Vec::with_capacity(width * height);
+  for j in 0..height {
+    for i in 0..width {
+      let material =
+        match surface[j * width + i] {
+          None => {
+            pixels.push([0, 0, 0]);
+            continue
+          },
+          Some((_, material)) => material,
+        };
+
+      // Central differences, clamped at the edges of the map.
+      let (left, right) = (if i > 0 {i - 1} else {i}, if i + 1 < width {i + 1} else {i});
+      let (up, down) = (if j > 0 {j - 1} else {j}, if j + 1 < height {j + 1} else {j});
+      let dx = (height_at(right, j) - height_at(left, j)) / ((right - left) as f32 * scale as f32).max(1.0);
+      let dz = (height_at(i, down) - height_at(i, up)) / ((down - up) as f32 * scale as f32).max(1.0);
+      let normal = Vector3::new(-dx, 1.0, -dz).normalize();
+
+      let lighting = AMBIENT + (1.0 - AMBIENT) * normal.dot(&light).max(0.0);
+      // Higher ground is brighter.
+      let elevation = 0.75 + 0.5 * (height_at(i, j) - low as f32) / (high - low) as f32;
+      let base = color(material);
+      let channel = |c: f32| (c * lighting * elevation * 255.0).max(0.0).min(255.0) as u8;
+      pixels.push([channel(base[0]), channel(base[1]), channel(base[2])]);
+    }
+  }
+
+  Image {
+    width: width,
+    height: height,
+    pixels: pixels,
+  }
+}
+
+/// Write an image as a binary PPM.
+pub fn write_ppm<W: Write>(w: &mut W, image: &Image) -> io::Result<()> {
+  try!(write!(w, "P6\n{} {}\n255\n", image.width, image.height));
+  for pixel in image.pixels.iter() {
+    try!(w.write_all(pixel));
+  }
+  Ok(())
+}
+
+/// Render a map and write it to `path` as a PPM.
+pub fn render_to(
+  terrain: &mut Terrain,
+  x0: i32, z0: i32, x1: i32, z1: i32,
+  scale: i32,
+  path: &Path,
+) -> io::Result<()> {
+  try!(check_area(x0, z0, x1, z1, scale).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+  let image = render(terrain, x0, z0, x1, z1, scale);
+  info!("Writing a {}x{} map to {:?}.", image.width, image.height, path);
+  let mut file = BufWriter::new(try!(File::create(path)));
+  write_ppm(&mut file, &image)
+}
+
+#[test]
+fn ppm_header() {
+  let image =
+    Image {
+      width: 2,
+      height: 1,
+      pixels: vec!([1, 2, 3], [4, 5, 6]),
+    };
+  let mut bytes = Vec::new();
+  write_ppm(&mut bytes, &image).unwrap();
+  assert_eq!(bytes, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+}
@@ -23,13 +23,18 @@ extern crate test;
 extern crate time;
 
 mod client_recv_thread;
+mod config;
+mod export;
 mod in_progress_terrain;
 mod init_mobs;
 mod main;
+mod map;
 mod mob;
 mod octree;
 mod physics;
 mod player;
+mod player_store;
+mod pregen;
 mod server;
 mod sun;
 mod terrain_loader;
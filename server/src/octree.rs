@@ -245,6 +245,33 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
     }
   }
 
+  // Call `f` on every object overlapping the bounds provided in this/child trees,
+  // except ones equal to `self_v`. Objects that span several cells may be reported
+  // more than once, and the bounds passed to `f` may be only part of the object.
+  pub fn intersect_all<F>(&self, bounds: &Aabb3<f32>, self_v: Option<V>, f: &mut F)
+    where F: FnMut(&Aabb3<f32>, V)
+  {
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => {
+        for &(ref bs, v) in vs.iter() {
+          if Some(v) != self_v && aabb_overlap(bounds, bs) {
+            f(bs, v);
+          }
+        }
+      },
+      OctreeContents::Branch(ref b) => {
+        let mid = middle(&self.bounds, self.dimension);
+        let (low_bounds, high_bounds) = split(mid, self.dimension, bounds.clone());
+        if let Some(bs) = low_bounds {
+          b.low_tree.intersect_all(&bs, self_v, f);
+        }
+        if let Some(bs) = high_bounds {
+          b.high_tree.intersect_all(&bs, self_v, f);
+        }
+      },
+    }
+  }
+
   // like insert, but before recursing downward, we recurse up the parents
   // until the bounds provided are inside the tree.
   fn insert_from(&mut self, bounds: Aabb3<f32>, v: V) {
@@ -1,12 +1,199 @@
-use cgmath::{Aabb3, Point, Vector, Vector3};
+use cgmath::{Aabb3, Point, Point3, Vector, EuclideanVector, Vector3};
 use octree::Octree;
 use common::entity::EntityId;
+use common::terrain_block::Triangle;
+use std::collections::HashSet;
 use std::collections::HashMap;
+use std::f32;
+
+/// Maximum number of surfaces a single move can slide along.
+const MAX_SLIDES: u32 = 4;
+/// How far away from surfaces sliding objects are kept, so they don't end up touching (and
+/// therefore snagging on) whatever they hit.
+const CONTACT_GAP: f32 = 0.001;
+
+/// Where a moving object first touches something.
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+  /// Fraction of the attempted movement that can happen before touching.
+  pub toi: f32,
+  /// Unit normal of the surface that was hit, pointing back toward the moving object.
+  pub normal: Vector3<f32>,
+}
+
+/// The result of moving an object and sliding it along whatever it hits.
+pub struct Slide {
+  pub bounds: Aabb3<f32>,
+  /// How far the object actually moved.
+  pub moved: Vector3<f32>,
+  /// The normals of every surface hit along the way.
+  pub contacts: Vec<Vector3<f32>>,
+}
+
+impl Slide {
+  /// Remove the parts of `velocity` that push into the surfaces that were hit.
+  pub fn clip_velocity(&self, mut velocity: Vector3<f32>) -> Vector3<f32> {
+    for normal in self.contacts.iter() {
+      let into = velocity.dot(normal);
+      if into < 0.0 {
+        velocity = velocity - normal.mul_s(into);
+      }
+    }
+    velocity
+  }
+}
+
+fn translate(bounds: &Aabb3<f32>, v: &Vector3<f32>) -> Aabb3<f32> {
+  Aabb3::new(bounds.min.add_v(v), bounds.max.add_v(v))
+}
+
+fn union(b1: &Aabb3<f32>, b2: &Aabb3<f32>) -> Aabb3<f32> {
+  Aabb3::new(
+    Point3::new(b1.min.x.min(b2.min.x), b1.min.y.min(b2.min.y), b1.min.z.min(b2.min.z)),
+    Point3::new(b1.max.x.max(b2.max.x), b1.max.y.max(b2.max.y), b1.max.z.max(b2.max.z)),
+  )
+}
+
+/// Sweep `moving` by `v` and find when (as a fraction of `v`) it first touches `obstacle`.
+/// Objects that already overlap are ignored, so things can always move out of each other.
+pub fn sweep_aabb(moving: &Aabb3<f32>, v: &Vector3<f32>, obstacle: &Aabb3<f32>) -> Option<Contact> {
+  let a_min = [moving.min.x, moving.min.y, moving.min.z];
+  let a_max = [moving.max.x, moving.max.y, moving.max.z];
+  let b_min = [obstacle.min.x, obstacle.min.y, obstacle.min.z];
+  let b_max = [obstacle.max.x, obstacle.max.y, obstacle.max.z];
+
+  let mut entry = -f32::INFINITY;
+  let mut exit = f32::INFINITY;
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+  for d in 0..3 {
+    if v[d] == 0.0 {
+      if a_max[d] <= b_min[d] || b_max[d] <= a_min[d] {
+        // Separated along an axis we're not moving along.
+        return None;
+      }
+    } else {
+      let (t_entry, t_exit) =
+        if v[d] > 0.0 {
+          ((b_min[d] - a_max[d]) / v[d], (b_max[d] - a_min[d]) / v[d])
+        } else {
+          ((b_max[d] - a_min[d]) / v[d], (b_min[d] - a_max[d]) / v[d])
+        };
+      if t_entry > entry {
+        entry = t_entry;
+        normal = Vector3::new(0.0, 0.0, 0.0);
+        normal[d] = if v[d] > 0.0 { -1.0 } else { 1.0 };
+      }
+      if t_exit < exit {
+        exit = t_exit;
+      }
+    }
+  }
+
+  if entry >= exit || entry < 0.0 || entry > 1.0 {
+    None
+  } else {
+    Some(Contact {
+      toi: entry,
+      normal: normal,
+    })
+  }
+}
+
+/// Like `sweep_aabb`, but against a triangle. This is a swept separating axis test: the box and
+/// triangle first touch when the last of the candidate axes stops separating them.
+pub fn sweep_triangle(
+  moving: &Aabb3<f32>,
+  v: &Vector3<f32>,
+  triangle: &Triangle<Point3<f32>>,
+) -> Option<Contact> {
+  let center = Point3::new(
+    (moving.min.x + moving.max.x) / 2.0,
+    (moving.min.y + moving.max.y) / 2.0,
+    (moving.min.z + moving.max.z) / 2.0,
+  );
+  let half_extents = moving.max.sub_p(&moving.min).div_s(2.0);
+
+  let box_axes = [
+    Vector3::new(1.0, 0.0, 0.0),
+    Vector3::new(0.0, 1.0, 0.0),
+    Vector3::new(0.0, 0.0, 1.0),
+  ];
+  let edges = [
+    triangle.v2.sub_p(&triangle.v1),
+    triangle.v3.sub_p(&triangle.v2),
+    triangle.v1.sub_p(&triangle.v3),
+  ];
+
+  let mut axes = Vec::with_capacity(13);
+  axes.extend(box_axes.iter().cloned());
+  axes.push(edges[0].cross(&edges[1]));
+  for box_axis in box_axes.iter() {
+    for edge in edges.iter() {
+      axes.push(box_axis.cross(edge));
+    }
+  }
+
+  let mut entry = -f32::INFINITY;
+  let mut exit = f32::INFINITY;
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+  for axis in axes.into_iter() {
+    if axis.length2() < 1e-12 {
+      // Degenerate axis (parallel edges, or a zero-area triangle).
+      continue;
+    }
+    let axis = axis.normalize();
+
+    let c = center.to_vec().dot(&axis);
+    let r =
+      half_extents.x * axis.x.abs() +
+      half_extents.y * axis.y.abs() +
+      half_extents.z * axis.z.abs();
+    let (box_min, box_max) = (c - r, c + r);
+
+    let p1 = triangle.v1.to_vec().dot(&axis);
+    let p2 = triangle.v2.to_vec().dot(&axis);
+    let p3 = triangle.v3.to_vec().dot(&axis);
+    let tri_min = p1.min(p2).min(p3);
+    let tri_max = p1.max(p2).max(p3);
+
+    let speed = v.dot(&axis);
+    if speed == 0.0 {
+      if box_max <= tri_min || tri_max <= box_min {
+        return None;
+      }
+    } else {
+      let (t_entry, t_exit) =
+        if speed > 0.0 {
+          ((tri_min - box_max) / speed, (tri_max - box_min) / speed)
+        } else {
+          ((tri_max - box_min) / speed, (tri_min - box_max) / speed)
+        };
+      if t_entry > entry {
+        entry = t_entry;
+        normal = if speed > 0.0 { -axis } else { axis };
+      }
+      if t_exit < exit {
+        exit = t_exit;
+      }
+    }
+  }
+
+  if entry >= exit || entry < 0.0 || entry > 1.0 {
+    None
+  } else {
+    Some(Contact {
+      toi: entry,
+      normal: normal,
+    })
+  }
+}
 
 pub struct Physics {
   pub terrain_octree: Octree<EntityId>,
   pub misc_octree: Octree<EntityId>,
   pub bounds: HashMap<EntityId, Aabb3<f32>>,
+  // The octrees are just the broad phase; terrain is collided against these.
+  pub terrain_triangles: HashMap<EntityId, Triangle<Point3<f32>>>,
 }
 
 impl Physics {
@@ -15,12 +202,14 @@ impl Physics {
       terrain_octree: Octree::new(&world_bounds),
       misc_octree: Octree::new(&world_bounds),
       bounds: HashMap::new(),
+      terrain_triangles: HashMap::new(),
     }
   }
 
-  pub fn insert_terrain(&mut self, id: EntityId, bounds: Aabb3<f32>) {
+  pub fn insert_terrain(&mut self, id: EntityId, bounds: Aabb3<f32>, triangle: Triangle<Point3<f32>>) {
     self.terrain_octree.insert(bounds.clone(), id);
     self.bounds.insert(id, bounds);
+    self.terrain_triangles.insert(id, triangle);
   }
 
   pub fn insert_misc(&mut self, id: EntityId, bounds: Aabb3<f32>) {
@@ -29,19 +218,20 @@ impl Physics {
   }
 
   pub fn remove_terrain(&mut self, id: EntityId) {
-    match self.bounds.get(&id) {
+    match self.bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+        self.terrain_octree.remove(&bounds, id);
       },
     }
+    self.terrain_triangles.remove(&id);
   }
 
   pub fn remove_misc(&mut self, id: EntityId) {
-    match self.bounds.get(&id) {
+    match self.bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
   }
@@ -50,34 +240,150 @@ impl Physics {
     self.bounds.get(&id)
   }
 
-  pub fn reinsert(
-    octree: &mut Octree<EntityId>,
-    id: EntityId,
-    bounds: &mut Aabb3<f32>,
-    new_bounds: Aabb3<f32>,
-  ) -> Option<(Aabb3<f32>, EntityId)> {
-    match octree.intersect(&new_bounds, Some(id)) {
-      None => {
-        octree.reinsert(id, bounds, new_bounds.clone());
-        *bounds = new_bounds;
-        None
-      },
-      collision => collision,
+  /// Move a misc object to `new_bounds`, without checking for collisions.
+  pub fn reinsert_misc(&mut self, id: EntityId, new_bounds: Aabb3<f32>) {
+    let bounds = self.bounds.get_mut(&id).unwrap();
+    self.misc_octree.reinsert(id, bounds, new_bounds.clone());
+    *bounds = new_bounds;
+  }
+
+  /// Find the first thing hit by `bounds` (belonging to `id`) moving by `v`.
+  pub fn sweep(&self, id: EntityId, bounds: &Aabb3<f32>, v: &Vector3<f32>) -> Option<Contact> {
+    let swept = union(bounds, &translate(bounds, v));
+
+    // The octrees can report pieces of objects, and the same object more than once,
+    // so just collect ids here and sweep against the full shapes.
+    let mut terrain = HashSet::new();
+    let mut misc = HashSet::new();
+    self.terrain_octree.intersect_all(&swept, None, &mut |_: &Aabb3<f32>, id: EntityId| { terrain.insert(id); });
+    self.misc_octree.intersect_all(&swept, Some(id), &mut |_: &Aabb3<f32>, id: EntityId| { misc.insert(id); });
+
+    let terrain_contacts =
+      terrain.iter().map(|id| sweep_triangle(bounds, v, self.terrain_triangles.get(id).unwrap()));
+    let misc_contacts =
+      misc.iter().map(|id| sweep_aabb(bounds, v, self.bounds.get(id).unwrap()));
+
+    let mut first: Option<Contact> = None;
+    for contact in terrain_contacts.chain(misc_contacts) {
+      match (contact, first) {
+        (Some(contact), Some(f)) if contact.toi >= f.toi => {},
+        (Some(contact), _) => first = Some(contact),
+        (None, _) => {},
+      }
     }
+    first
   }
 
-  pub fn translate_misc(&mut self, id: EntityId, amount: Vector3<f32>) -> Option<(Aabb3<f32>, EntityId)> {
-    let bounds = self.bounds.get_mut(&id).unwrap();
-    let new_bounds =
-      Aabb3::new(
-        bounds.min.add_v(&amount),
-        bounds.max.add_v(&amount),
-      );
-    let terrain_collision = self.terrain_octree.intersect(&new_bounds, None);
-    if terrain_collision.is_none() {
-      Physics::reinsert(&mut self.misc_octree, id, bounds, new_bounds)
-    } else {
-      terrain_collision
+  /// Compute where `bounds` (belonging to `id`) ends up if it tries to move by `v`,
+  /// sliding along anything it hits. Nothing is actually moved.
+  pub fn slide(&self, id: EntityId, bounds: &Aabb3<f32>, v: Vector3<f32>) -> Slide {
+    let mut bounds = bounds.clone();
+    let mut moved = Vector3::new(0.0, 0.0, 0.0);
+    let mut contacts = Vec::new();
+    let mut remaining = v;
+    for _ in 0..MAX_SLIDES {
+      if remaining.length2() == 0.0 {
+        break;
+      }
+
+      match self.sweep(id, &bounds, &remaining) {
+        None => {
+          bounds = translate(&bounds, &remaining);
+          moved = moved + remaining;
+          break;
+        },
+        Some(contact) => {
+          // Move up to the contact (leaving a small gap), then slide along it with what's left.
+          let step = remaining.mul_s(contact.toi) + contact.normal.mul_s(CONTACT_GAP);
+          bounds = translate(&bounds, &step);
+          moved = moved + step;
+          remaining = remaining.mul_s(1.0 - contact.toi);
+          remaining = remaining - contact.normal.mul_s(remaining.dot(&contact.normal));
+          contacts.push(contact.normal);
+        },
+      }
+    }
+
+    Slide {
+      bounds: bounds,
+      moved: moved,
+      contacts: contacts,
     }
   }
+
+  /// Move a misc object by `v`, sliding along anything it hits.
+  pub fn slide_misc(&mut self, id: EntityId, v: Vector3<f32>) -> Slide {
+    let slide = {
+      let bounds = self.bounds.get(&id).unwrap();
+      self.slide(id, bounds, v)
+    };
+    self.reinsert_misc(id, slide.bounds.clone());
+    slide
+  }
+}
+
+#[test]
+fn sweep_hits_nearest_face() {
+  let moving = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+  let obstacle = Aabb3::new(Point3::new(2.0, -1.0, -1.0), Point3::new(3.0, 2.0, 2.0));
+  let contact = sweep_aabb(&moving, &Vector3::new(4.0, 0.0, 0.0), &obstacle).unwrap();
+  assert_eq!(contact.toi, 0.25);
+  assert_eq!(contact.normal, Vector3::new(-1.0, 0.0, 0.0));
+}
+
+#[test]
+fn sweep_misses() {
+  let moving = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+  let obstacle = Aabb3::new(Point3::new(2.0, 2.0, 2.0), Point3::new(3.0, 3.0, 3.0));
+  // Too short.
+  assert!(sweep_aabb(&moving, &Vector3::new(0.5, 0.5, 0.5), &obstacle).is_none());
+  // Wrong direction.
+  assert!(sweep_aabb(&moving, &Vector3::new(-4.0, -4.0, -4.0), &obstacle).is_none());
+  // Sliding past an edge.
+  assert!(sweep_aabb(&moving, &Vector3::new(4.0, 0.0, 0.0), &obstacle).is_none());
+}
+
+#[test]
+fn sweep_triangle_hits_slope() {
+  let moving = Aabb3::new(Point3::new(-0.5, 1.0, -0.5), Point3::new(0.5, 2.0, 0.5));
+  // A big 45-degree slope through the origin, rising toward +x.
+  let slope = Triangle {
+    v1: Point3::new(-10.0, -10.0, -10.0),
+    v2: Point3::new(-10.0, -10.0, 10.0),
+    v3: Point3::new(10.0, 10.0, 0.0),
+  };
+  let contact = sweep_triangle(&moving, &Vector3::new(0.0, -4.0, 0.0), &slope).unwrap();
+  // The box's low +x edge (x = 0.5) touches the slope at y = 0.5.
+  assert!((contact.toi - 0.125).abs() < 0.0001);
+  let expected = Vector3::new(-1.0, 1.0, 0.0).normalize();
+  assert!(contact.normal.sub_v(&expected).length() < 0.0001);
+}
+
+#[test]
+fn sweep_ignores_overlap() {
+  let moving = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+  let obstacle = Aabb3::new(Point3::new(0.5, 0.5, 0.5), Point3::new(3.0, 3.0, 3.0));
+  assert!(sweep_aabb(&moving, &Vector3::new(1.0, 0.0, 0.0), &obstacle).is_none());
+}
+
+#[test]
+fn reconnecting_leaves_nothing_behind() {
+  use std::default::Default;
+
+  let world = Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0));
+  let mut physics = Physics::new(world);
+  let bounds = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+  // Each connection gets a new id, like players do.
+  let mut id: EntityId = Default::default();
+  for _ in 0..3 {
+    id = id + 1;
+    physics.insert_misc(id, bounds.clone());
+    physics.remove_misc(id);
+  }
+  assert!(physics.bounds.is_empty());
+
+  // Nothing is left in the way, either.
+  let mover = id + 1;
+  let start = Aabb3::new(Point3::new(-4.0, 0.0, 0.0), Point3::new(-3.0, 2.0, 1.0));
+  assert!(physics.sweep(mover, &start, &Vector3::new(8.0, 0.0, 0.0)).is_none());
 }
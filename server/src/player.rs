@@ -1,23 +1,25 @@
 use cgmath;
-use cgmath::{Aabb3, Point, Point3, Matrix, Matrix3, Ray, Ray3, Vector, Vector3};
+use cgmath::{Point, Point3, Matrix, Matrix3, Ray, Ray3, Vector, Vector3};
 use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::sync::Mutex;
 use stopwatch::TimerSet;
 
 use common::block_position::BlockPosition;
+use common::communicate::ClientId;
 use common::entity::EntityId;
 use common::id_allocator::IdAllocator;
 use common::lod::{LOD, LODIndex, OwnerId};
 use common::surroundings_loader::{SurroundingsLoader, LODChange};
 
-use physics::Physics;
+use physics::Slide;
+use player_store::SavedPlayer;
 use server::Server;
 use update_gaia::ServerToGaia;
 use update_world::load_placeholders;
 
-const MAX_JUMP_FUEL: u32 = 4;
 const MAX_STEP_HEIGHT: f32 = 1.0;
+// Surfaces whose normals point at least this far up count as ground rather than walls.
+const MIN_GROUND_NORMAL_Y: f32 = 0.5;
 
 // TODO: Add ObservablePlayer struct as a subset.
 pub struct Player {
@@ -33,6 +35,10 @@ pub struct Player {
   // are we currently trying to jump? (e.g. holding the key).
   pub is_jumping: bool,
   pub entity_id: EntityId,
+  // the client playing as this player.
+  pub client: ClientId,
+  // the name the player connects as, which their saved state is kept under.
+  pub name: String,
 
   // rotation around the y-axis, in radians
   pub lateral_rotation: f32,
@@ -49,6 +55,8 @@ pub struct Player {
 impl Player {
   pub fn new(
     entity_id: EntityId,
+    client: ClientId,
+    name: String,
     owner_allocator: &Mutex<IdAllocator<OwnerId>>,
   ) -> Player {
     let surroundings_owner = owner_allocator.lock().unwrap().allocate();
@@ -61,6 +69,8 @@ impl Player {
       jump_fuel: 0,
       is_jumping: false,
       entity_id: entity_id,
+      client: client,
+      name: name,
       lateral_rotation: 0.0,
       vertical_rotation: 0.0,
 
@@ -71,67 +81,79 @@ impl Player {
     }
   }
 
-  /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
-  /// Returns the actual amount moved by.
-  pub fn translate(
+  /// The state to save when the player leaves.
+  pub fn saved(&self) -> SavedPlayer {
+    SavedPlayer {
+      position: self.position,
+      lateral_rotation: self.lateral_rotation,
+      vertical_rotation: self.vertical_rotation,
+      jump_fuel: self.jump_fuel,
+      is_jumping: self.is_jumping,
+    }
+  }
+
+  /// Pick up where a saved player left off.
+  pub fn restore(&mut self, saved: &SavedPlayer) {
+    self.position = saved.position;
+    self.lateral_rotation = saved.lateral_rotation;
+    self.vertical_rotation = saved.vertical_rotation;
+    self.jump_fuel = saved.jump_fuel;
+    if saved.is_jumping && !self.is_jumping {
+      self.is_jumping = true;
+      // this 0.3 is duplicated in a few places
+      self.accel.y = self.accel.y + 0.3;
+    }
+  }
+
+  /// Translates the player by a vector, sliding along anything in the way.
+  /// If the player runs into something low enough, the player will step up onto it.
+  fn translate(
     &mut self,
-    physics: &Mutex<Physics>,
+    server: &Server,
     v: Vector3<f32>,
   ) {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let bounds = physics.bounds.get_mut(&self.entity_id).unwrap();
-    let init_bounds =
-      Aabb3::new(
-        bounds.min.add_v(&v),
-        bounds.max.add_v(&v),
-      );
-    let mut new_bounds = init_bounds.clone();
-    // The height of the player's "step".
-    let mut step_height = 0.0;
-    let mut collided = false;
-    {
-      loop {
-        match physics.terrain_octree.intersect(&new_bounds, None) {
-          None => {
-            if Physics::reinsert(&mut physics.misc_octree, self.entity_id, bounds, new_bounds).is_some() {
-              collided = true;
-            } else {
-              self.position.add_self_v(&(v + Vector3::new(0.0, step_height, 0.0)));
-            }
-            break;
-          },
-          Some((collision_bounds, _)) => {
-            collided = true;
-            // Step to the top of whatever we hit.
-            step_height = collision_bounds.max.y - init_bounds.min.y;
-            assert!(step_height > 0.0);
-
-            if step_height > MAX_STEP_HEIGHT {
-              // Step is too big; we just ran into something.
-              break;
-            }
-
-            new_bounds =
-              Aabb3::new(
-                init_bounds.min.add_v(&Vector3::new(0.0, step_height, 0.0)),
-                init_bounds.max.add_v(&Vector3::new(0.0, step_height, 0.0)),
-              );
-          },
-        }
+    let mut physics = server.physics.lock().unwrap();
+    let bounds = physics.get_bounds(self.entity_id).unwrap().clone();
+    let mut slide = physics.slide(self.entity_id, &bounds, v);
+
+    let hit_wall = slide.contacts.iter().any(|n| n.y.abs() < MIN_GROUND_NORMAL_Y);
+    if hit_wall {
+      // Try lifting the player by up to a step, moving sideways, then settling back down.
+      let up = physics.slide(self.entity_id, &bounds, Vector3::new(0.0, MAX_STEP_HEIGHT, 0.0));
+      let across = physics.slide(self.entity_id, &up.bounds, Vector3::new(v.x, 0.0, v.z));
+      let down = physics.slide(self.entity_id, &across.bounds, Vector3::new(0.0, v.y - up.moved.y, 0.0));
+
+      let lateral_distance = |m: &Vector3<f32>| m.x * m.x + m.z * m.z;
+      let landed = down.contacts.iter().any(|n| n.y >= MIN_GROUND_NORMAL_Y);
+      if landed && lateral_distance(&across.moved) > lateral_distance(&slide.moved) {
+        let mut contacts = across.contacts;
+        contacts.extend(down.contacts.into_iter());
+        slide = Slide {
+          bounds: down.bounds,
+          moved: up.moved + across.moved + down.moved,
+          contacts: contacts,
+        };
       }
     }
 
-    if collided {
-      if v.y < 0.0 {
-        self.jump_fuel = MAX_JUMP_FUEL;
-      }
+    physics.reinsert_misc(self.entity_id, slide.bounds.clone());
+    self.position.add_self_v(&slide.moved);
 
-      self.speed = self.speed - v;
-    } else {
-      if v.y < 0.0 {
-        self.jump_fuel = 0;
+    if slide.contacts.iter().any(|n| n.y >= MIN_GROUND_NORMAL_Y) {
+      self.jump_fuel = server.max_jump_fuel;
+    } else if v.y < 0.0 {
+      self.jump_fuel = 0;
+    }
+
+    self.speed = slide.clip_velocity(self.speed);
+  }
+
+  /// Let go of all the terrain this player has loaded.
+  pub fn release_surroundings(&self, timers: &TimerSet, server: &Server) {
+    let mut terrain_loader = server.terrain_loader.lock().unwrap();
+    for &owner in [self.surroundings_owner, self.solid_owner].iter() {
+      for position in terrain_loader.lod_map.owned_by(owner).iter() {
+        terrain_loader.unload(timers, &server.physics, position, owner);
       }
     }
   }
@@ -202,15 +224,7 @@ impl Player {
     }
 
     let delta_p = self.speed;
-    if delta_p.x != 0.0 {
-      self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-    }
-    if delta_p.y != 0.0 {
-      self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-    }
-    if delta_p.z != 0.0 {
-      self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-    }
+    self.translate(server, delta_p);
 
     let y_axis = Vector3::new(0.0, 1.0, 0.0);
     let walk_v =
@@ -0,0 +1,261 @@
+//! Saved player state, so players can pick up where they left off.
+//!
+//! Each player is a text file of `key value...` lines in the store's directory, named after the
+//! player. Unknown keys are ignored, so fields can be added without breaking old saves.
+//! Changes are journaled every so often, and compacted into the player files less often.
+
+use cgmath::Point3;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use terrain::journal::Journal;
+use terrain::world::write_atomically;
+
+const MAGIC: &'static str = "playform-player";
+const VERSION: u32 = 1;
+
+/// The longest name a player can have, in characters. Escaped, it still makes a short file name.
+pub const MAX_NAME_LENGTH: usize = 24;
+
+/// Check that a player's name can be stored: it can't be empty or too long, and it can't have
+/// control characters, which would break the journal's `name\nplayer` records.
+pub fn check_name(name: &str) -> Result<(), String> {
+  if name.is_empty() {
+    return Err(String::from("Names can't be empty"))
+  }
+  if name.chars().count() > MAX_NAME_LENGTH {
+    return Err(format!("Names can't be longer than {} characters", MAX_NAME_LENGTH))
+  }
+  if name.chars().any(|c| c.is_control()) {
+    return Err(String::from("Names can't have control characters"))
+  }
+  Ok(())
+}
+
+/// The parts of a player that outlast their connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedPlayer {
+  #[allow(missing_docs)]
+  pub position: Point3<f32>,
+  #[allow(missing_docs)]
+  pub lateral_rotation: f32,
+  #[allow(missing_docs)]
+  pub vertical_rotation: f32,
+  #[allow(missing_docs)]
+  pub jump_fuel: u32,
+  #[allow(missing_docs)]
+  pub is_jumping: bool,
+}
+
+/// A directory of saved players.
+pub struct PlayerStore {
+  dir: PathBuf,
+  journal: Journal,
+  /// The latest journaled state of each player that hasn't been compacted into their file.
+  pending: HashMap<String, SavedPlayer>,
+}
+
+impl PlayerStore {
+  /// Keep players in `dir`, creating it if necessary.
+  /// Anything left in the journal from last time is replayed into the player files.
+  pub fn open(dir: &Path) -> io::Result<PlayerStore> {
+    try!(fs::create_dir_all(dir));
+    let (journal, records) = try!(Journal::open(&dir.join("journal")));
+
+    let mut store =
+      PlayerStore {
+        dir: dir.to_path_buf(),
+        journal: journal,
+        pending: HashMap::new(),
+      };
+
+    for record in records.iter() {
+      let mut record = BufReader::new(&record[..]);
+      let mut name = String::new();
+      try!(record.read_line(&mut name));
+      let name = name.trim_right_matches('\n').to_string();
+      match read_player(record) {
+        Ok(player) => { store.pending.insert(name, player); },
+        Err(e) => warn!("Skipping a malformed journal record for {:?}: {}", name, e),
+      }
+    }
+    store.compact();
+
+    Ok(store)
+  }
+
+  fn path(&self, name: &str) -> PathBuf {
+    // Escape anything that could mean something to the filesystem.
+    let mut file_name = String::new();
+    for c in name.chars() {
+      if c.is_alphanumeric() || c == '-' || c == '_' {
+        file_name.push(c);
+      } else {
+        file_name.push_str(&format!("%{:x};", c as u32));
+      }
+    }
+    file_name.push_str(".player");
+    self.dir.join(file_name)
+  }
+
+  /// Load a player, if they've been saved before.
+  pub fn load(&self, name: &str) -> io::Result<Option<SavedPlayer>> {
+    if let Some(player) = self.pending.get(name) {
+      return Ok(Some(player.clone()))
+    }
+
+    match File::open(&self.path(name)) {
+      Ok(file) => read_player(BufReader::new(file)).map(Some),
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Save a player right away, replacing whatever was saved before.
+  pub fn save(&mut self, name: &str, player: &SavedPlayer) -> io::Result<()> {
+    // Journal it too, so an older journaled state can't be replayed over this one.
+    self.journal(name, player);
+    write_atomically(&self.path(name), |file| write_player(file, player))
+  }
+
+  /// Record a player's current state in the journal, if it's changed.
+  pub fn journal(&mut self, name: &str, player: &SavedPlayer) {
+    self.journal_all(&[(name.to_string(), player.clone())]);
+  }
+
+  /// Record the current states of some (name, player)s in the journal, for those that changed.
+  pub fn journal_all(&mut self, players: &[(String, SavedPlayer)]) {
+    let mut records = Vec::new();
+    for &(ref name, ref player) in players.iter() {
+      if self.pending.get(name) == Some(player) {
+        continue
+      }
+      let mut record = Vec::new();
+      record.extend(name.as_bytes().iter().cloned());
+      record.push(b'\n');
+      write_player(&mut record, player).unwrap();
+      records.push(record);
+      self.pending.insert(name.clone(), player.clone());
+    }
+
+    if records.is_empty() {
+      return
+    }
+    let records: Vec<&[u8]> = records.iter().map(|record| &record[..]).collect();
+    if let Err(e) = self.journal.append_all(&records) {
+      warn!("Couldn't journal players: {}", e);
+    }
+  }
+
+  /// Write every journaled player out to their file, and empty the journal if they all make it.
+  pub fn compact(&mut self) {
+    let mut saved_all = true;
+    for (name, player) in self.pending.iter() {
+      if let Err(e) = write_atomically(&self.path(name), |file| write_player(file, player)) {
+        warn!("Couldn't save player {:?}: {}", name, e);
+        saved_all = false;
+      }
+    }
+
+    if saved_all {
+      match self.journal.clear() {
+        Ok(()) => self.pending.clear(),
+        Err(e) => warn!("Couldn't clear the player journal: {}", e),
+      }
+    }
+  }
+}
+
+fn invalid(description: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::Other, description)
+}
+
+fn write_player<W: Write>(file: &mut W, player: &SavedPlayer) -> io::Result<()> {
+  try!(writeln!(file, "{} {}", MAGIC, VERSION));
+  try!(writeln!(file, "position {} {} {}", player.position.x, player.position.y, player.position.z));
+  try!(writeln!(file, "lateral_rotation {}", player.lateral_rotation));
+  try!(writeln!(file, "vertical_rotation {}", player.vertical_rotation));
+  try!(writeln!(file, "jump_fuel {}", player.jump_fuel));
+  try!(writeln!(file, "is_jumping {}", player.is_jumping));
+  Ok(())
+}
+
+fn read_player<R: BufRead>(file: R) -> io::Result<SavedPlayer> {
+  let mut lines = file.lines();
+
+  let first = try!(lines.next().unwrap_or(Err(invalid("empty player file"))));
+  if first != format!("{} {}", MAGIC, VERSION) {
+    return Err(invalid("unsupported player file"))
+  }
+
+  let mut values = HashMap::new();
+  for line in lines {
+    let line = try!(line);
+    let mut words = line.split(' ');
+    match words.next() {
+      Some(key) => { values.insert(key.to_string(), words.map(|w| w.to_string()).collect::<Vec<_>>()); },
+      None => return Err(invalid("malformed player file")),
+    }
+  }
+
+  macro_rules! field(
+    ($name:expr, $i:expr) => {{
+      let value = try!(values.get($name).and_then(|v| v.get($i)).ok_or(invalid("player file is missing a field")));
+      try!(value.parse().map_err(|_| invalid("malformed player file field")))
+    }}
+  );
+
+  Ok(SavedPlayer {
+    position: Point3::new(field!("position", 0), field!("position", 1), field!("position", 2)),
+    lateral_rotation: field!("lateral_rotation", 0),
+    vertical_rotation: field!("vertical_rotation", 0),
+    jump_fuel: field!("jump_fuel", 0),
+    is_jumping: field!("is_jumping", 0),
+  })
+}
+
+#[test]
+fn names_are_checked() {
+  use std::iter;
+
+  assert!(check_name("a/b").is_ok());
+  assert!(check_name("").is_err());
+  assert!(check_name("a\nposition 0 0 0").is_err());
+  let longest: String = iter::repeat('x').take(MAX_NAME_LENGTH).collect();
+  assert!(check_name(&longest).is_ok());
+  assert!(check_name(&format!("{}x", longest)).is_err());
+}
+
+#[test]
+fn player_round_trip() {
+  use std::env;
+
+  let dir = env::temp_dir().join("playform-player-round-trip");
+  let _ = fs::remove_dir_all(&dir);
+  let mut store = PlayerStore::open(&dir).unwrap();
+  assert_eq!(store.load("a/b").unwrap(), None);
+
+  let player =
+    SavedPlayer {
+      position: Point3::new(1.5, -2.0, 30.25),
+      lateral_rotation: 0.5,
+      vertical_rotation: -0.25,
+      jump_fuel: 3,
+      is_jumping: true,
+    };
+  store.save("a/b", &player).unwrap();
+  assert_eq!(store.load("a/b").unwrap(), Some(player.clone()));
+  assert_eq!(store.load("a_b").unwrap(), None);
+
+  // Journaled states win over saved ones, even across a crash.
+  let mut moved = player.clone();
+  moved.position.y = 12.0;
+  store.journal("a/b", &moved);
+  drop(store);
+  let store = PlayerStore::open(&dir).unwrap();
+  assert_eq!(store.load("a/b").unwrap(), Some(moved));
+}
@@ -0,0 +1,77 @@
+//! Generate the blocks around a point ahead of time, so nobody has to wait for them.
+
+use std::cmp;
+use std::io;
+use std::iter::range_inclusive;
+use std::sync::Mutex;
+use stopwatch::TimerSet;
+use time;
+
+use common::block_position::BlockPosition;
+use common::id_allocator::IdAllocator;
+use common::lod::LODIndex;
+use common::terrain_block;
+
+use terrain::Terrain;
+
+/// How often to report progress.
+const REPORT_INTERVAL_NS: u64 = 5_000_000_000;
+
+/// Generate and cache every block, at every LOD, within `radius` blocks of `center`.
+/// Blocks that can't have any terrain in them are skipped, and so are blocks that are already
+/// cached, so an interrupted run picks up where it left off.
+pub fn pregen(
+  timers: &TimerSet,
+  terrain: &mut Terrain,
+  center: &BlockPosition,
+  radius: i32,
+) -> io::Result<()> {
+  let center = *center.as_pnt();
+  // The surface never leaves this range of heights.
+  let (low, high) = terrain.surface_range();
+  let low_y = cmp::max(center.y - radius, ((low.floor() as i32) >> terrain_block::LG_WIDTH) - 1);
+  let high_y = cmp::min(center.y + radius, ((high.ceil() as i32) >> terrain_block::LG_WIDTH) + 1);
+
+  let width = (2 * radius + 1) as u64;
+  let lods = terrain_block::LG_SAMPLE_SIZE.len() as u64;
+  let total = width * width * cmp::max(high_y - low_y + 1, 0) as u64 * lods;
+  println!("Pregenerating {} blocks around {:?}.", total, center);
+
+  let id_allocator = Mutex::new(IdAllocator::new());
+  let start = time::precise_time_ns();
+  let mut last_report = start;
+  let mut done = 0;
+  let mut generated = 0;
+  for x in range_inclusive(center.x - radius, center.x + radius) {
+  for z in range_inclusive(center.z - radius, center.z + radius) {
+  for y in range_inclusive(low_y, high_y) {
+    let position = BlockPosition::new(x, y, z);
+    for lod in 0..lods {
+      if try!(terrain.pregenerate(timers, &id_allocator, &position, LODIndex(lod as u32))) {
+        generated += 1;
+      }
+      done += 1;
+    }
+
+    let now = time::precise_time_ns();
+    if now - last_report >= REPORT_INTERVAL_NS {
+      last_report = now;
+      let seconds = (now - start) as f64 / 1e9;
+      let rate = generated as f64 / seconds;
+      // Go by every block that's been looked at, not just the ones generated: when resuming, none
+      // may have been generated yet.
+      let remaining = (total - done) as f64 * seconds / done as f64;
+      println!(
+        "{}/{} blocks ({:.1}%), {} generated at {:.0}/s; about {:.0}s left.",
+        done, total, 100.0 * done as f64 / total as f64, generated, rate, remaining
+      );
+    }
+  }}}
+
+  let seconds = (time::precise_time_ns() - start) as f64 / 1e9;
+  println!(
+    "Generated {} blocks ({} were already cached) in {:.1}s.",
+    generated, done - generated, seconds
+  );
+  Ok(())
+}
@@ -5,22 +5,25 @@ use std::sync::mpsc::Sender;
 use std::thread::JoinGuard;
 use time;
 
-use common::communicate::{ServerToClient, ClientId};
+use common::communicate::{ServerToClient, ClientId, WorldInfo};
 use common::entity::EntityId;
 use common::id_allocator::IdAllocator;
 use common::interval_timer::IntervalTimer;
 use common::lod::OwnerId;
 
+use config::Config;
 use init_mobs::init_mobs;
 use mob;
 use physics::Physics;
 use player::Player;
+use player_store::PlayerStore;
 use sun::Sun;
-use terrain;
+use terrain::Terrain;
 use terrain_loader::TerrainLoader;
 
-const UPDATES_PER_SECOND: u64 = 30;
-const SUN_TICK_NS: u64 = 5000000;
+const SAVE_INTERVAL_NS: u64 = 30_000_000_000;
+/// How often players' movements are journaled between saves.
+const JOURNAL_INTERVAL_NS: u64 = 1_000_000_000;
 
 pub struct Client {
   pub sender: Sender<Option<ServerToClient>>,
@@ -40,21 +43,30 @@ pub struct Server {
   pub terrain_loader: Mutex<TerrainLoader>,
 
   pub clients: Mutex<HashMap<ClientId, Client>>,
+  pub player_store: Mutex<PlayerStore>,
+  // What clients are told about the world when they connect.
+  pub world_info: WorldInfo,
+  // How many updates a player's jump keeps accelerating for.
+  pub max_jump_fuel: u32,
 
   pub sun: Mutex<Sun>,
   pub update_timer: Mutex<IntervalTimer>,
+  pub save_timer: Mutex<IntervalTimer>,
+  pub journal_timer: Mutex<IntervalTimer>,
 }
 
 impl Server {
   #[allow(missing_docs)]
-  pub fn new() -> Server {
-    let world_width: u32 = 1 << 11;
-    let world_width = world_width as f32;
+  pub fn new(terrain: Terrain, player_store: PlayerStore, config: &Config) -> Server {
+    let world_width = config.params.width as f32;
+    // Leave room around the surface to dig down and fly up.
+    let (low, high) = terrain.surface_range();
+    let margin = config.params.amplitude as f32;
     let physics =
       Physics::new(
         Aabb3::new(
-          Point3 { x: -world_width, y: -2.0 * terrain::AMPLITUDE as f32, z: -world_width },
-          Point3 { x: world_width, y: 2.0 * terrain::AMPLITUDE as f32, z: world_width },
+          Point3 { x: -world_width, y: low - margin, z: -world_width },
+          Point3 { x: world_width, y: high + margin, z: world_width },
         )
       );
 
@@ -70,21 +82,49 @@ impl Server {
       client_allocator: Mutex::new(IdAllocator::new()),
 
       physics: Mutex::new(physics),
-      terrain_loader: Mutex::new(TerrainLoader::new()),
+      terrain_loader: Mutex::new(TerrainLoader::new(terrain)),
 
       clients: Mutex::new(HashMap::new()),
-      sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+      player_store: Mutex::new(player_store),
+      world_info: config.world_info(),
+      max_jump_fuel: config.max_jump_fuel,
+      sun: Mutex::new(Sun::new(config.sun_tick_ns)),
 
       update_timer: {
         let now = time::precise_time_ns();
         let nanoseconds_per_second = 1000000000;
         Mutex::new(
-          IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
+          IntervalTimer::new(nanoseconds_per_second / config.updates_per_second, now)
         )
-      }
+      },
+      save_timer: {
+        let now = time::precise_time_ns();
+        Mutex::new(IntervalTimer::new(SAVE_INTERVAL_NS, now + SAVE_INTERVAL_NS))
+      },
+      journal_timer: {
+        let now = time::precise_time_ns();
+        Mutex::new(IntervalTimer::new(JOURNAL_INTERVAL_NS, now + JOURNAL_INTERVAL_NS))
+      },
     };
 
     init_mobs(&server);
     server
   }
+
+  /// Save a player's state so they can pick up where they left off.
+  pub fn save_player(&self, player: &Player) {
+    if let Err(e) = self.player_store.lock().unwrap().save(&player.name, &player.saved()) {
+      warn!("Couldn't save player {:?}: {}", player.name, e);
+    }
+  }
+
+  /// Write every player's latest state out to their file.
+  pub fn save_players(&self) {
+    let players = self.players.lock().unwrap();
+    let mut player_store = self.player_store.lock().unwrap();
+    for player in players.values() {
+      player_store.journal(&player.name, &player.saved());
+    }
+    player_store.compact();
+  }
 }
@@ -9,7 +9,7 @@ use common::terrain_block::TerrainBlock;
 
 use in_progress_terrain::InProgressTerrain;
 use physics::Physics;
-use terrain::{Terrain, Seed};
+use terrain::Terrain;
 use update_gaia::{ServerToGaia, LoadReason};
 
 /// Load and unload TerrainBlocks from the game.
@@ -22,9 +22,9 @@ pub struct TerrainLoader {
 }
 
 impl TerrainLoader {
-  pub fn new() -> TerrainLoader {
+  pub fn new(terrain: Terrain) -> TerrainLoader {
     TerrainLoader {
-      terrain: Terrain::new(Seed::new(0)),
+      terrain: terrain,
       in_progress_terrain: InProgressTerrain::new(),
       lod_map: LODMap::new(),
     }
@@ -157,12 +157,36 @@ impl TerrainLoader {
 
     timers.time("terrain_loader.load.physics", || {
       let mut physics = physics.lock().unwrap();
-      for &(ref id, ref bounds) in block.bounds.iter() {
-        physics.insert_terrain(*id, bounds.clone());
+      for (&(ref id, ref bounds), triangle) in block.bounds.iter().zip(block.vertex_coordinates.iter()) {
+        physics.insert_terrain(*id, bounds.clone(), *triangle);
       }
     });
   }
 
+  /// Swap the collision data for a regenerated block, if that's the LOD currently loaded.
+  pub fn swap_block(
+    physics: &mut Physics,
+    lod_map: &LODMap,
+    position: &BlockPosition,
+    lod: LODIndex,
+    old_block: Option<&TerrainBlock>,
+    new_block: &TerrainBlock,
+  ) {
+    if lod_map.loaded_lod(position) != Some(LOD::LodIndex(lod)) {
+      return;
+    }
+
+    old_block.map(|old_block| {
+      for id in old_block.ids.iter() {
+        physics.remove_terrain(*id);
+      }
+    });
+
+    for (&(ref id, ref bounds), triangle) in new_block.bounds.iter().zip(new_block.vertex_coordinates.iter()) {
+      physics.insert_terrain(*id, bounds.clone(), *triangle);
+    }
+  }
+
   pub fn unload(
     &mut self,
     timers: &TimerSet,
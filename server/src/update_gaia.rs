@@ -1,15 +1,21 @@
 /// Creator of the earth.
 
+use cgmath::Point3;
 use std::ops::DerefMut;
 use stopwatch::TimerSet;
 
+use common::brush::Brush;
 use common::communicate::{ClientId, ServerToClient, TerrainBlockSend};
+use common::entity::EntityId;
 use common::lod::{LODIndex, OwnerId};
+use common::material::Material;
 use common::serialize::Copyable;
 use common::block_position::BlockPosition;
+use common::terrain_block::TerrainBlock;
 
 use server::Server;
 use terrain_loader::TerrainLoader;
+use terrain::Terrain;
 use terrain::voxel;
 
 #[derive(Debug, Clone, Copy)]
@@ -21,7 +27,11 @@ pub enum LoadReason {
 #[derive(Debug, Clone, Copy)]
 pub enum ServerToGaia {
   Load(BlockPosition, LODIndex, LoadReason),
-  RemoveVoxel(voxel::Bounds),
+  RemoveVoxel(EntityId, voxel::Bounds),
+  AddVoxel(EntityId, voxel::Bounds, Material),
+  ApplyBrush(EntityId, Brush, Point3<f32>),
+  Undo(EntityId),
+  Redo(EntityId),
 }
 
 // TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
@@ -76,28 +86,84 @@ pub fn update_gaia(
         }
       });
     },
-    ServerToGaia::RemoveVoxel(bounds) => {
-      let mut terrain_loader = server.terrain_loader.lock().unwrap();
-      let id_allocator = &server.id_allocator;
-      terrain_loader.terrain.remove_voxel(
-        timers,
-        id_allocator,
-        &bounds,
-        |block, position, lod| {
-          // TODO: update physics with the new TerrainBlock.
+    ServerToGaia::RemoveVoxel(player, bounds) => {
+      let mut removed = Material::Empty;
+      edit_terrain(server, |terrain, block_changed| {
+        removed = terrain.remove_voxel(timers, &server.id_allocator, player, &bounds, block_changed);
+      });
 
-          let clients = server.clients.lock().unwrap();
-          for client in clients.values() {
-            client.sender.send(Some(
-              ServerToClient::UpdateBlock(TerrainBlockSend {
-                position: Copyable(*position),
-                block: block.clone(),
-                lod: Copyable(lod),
-              })
-            )).unwrap();
-          }
-        },
-      );
-    }
+      if removed.is_solid() {
+        debug!("{:?} dug up {:?}", player, removed);
+        // Only the digger needs to know.
+        let client = server.players.lock().unwrap().get(&player).map(|player| player.client);
+        let clients = server.clients.lock().unwrap();
+        if let Some(client) = client.and_then(|client| clients.get(&client)) {
+          client.sender.send(Some(ServerToClient::VoxelRemoved(Copyable(player), Copyable(removed)))).unwrap();
+        }
+      }
+    },
+    ServerToGaia::AddVoxel(player, bounds, material) => {
+      edit_terrain(server, |terrain, block_changed| {
+        terrain.add_voxel(timers, &server.id_allocator, player, &bounds, material, block_changed);
+      });
+    },
+    ServerToGaia::ApplyBrush(player, brush, center) => {
+      edit_terrain(server, |terrain, block_changed| {
+        terrain.apply_brush(timers, &server.id_allocator, player, &brush, &center, block_changed);
+      });
+    },
+    ServerToGaia::Undo(player) => {
+      edit_terrain(server, |terrain, block_changed| {
+        terrain.undo(timers, &server.id_allocator, player, block_changed);
+      });
+    },
+    ServerToGaia::Redo(player) => {
+      edit_terrain(server, |terrain, block_changed| {
+        terrain.redo(timers, &server.id_allocator, player, block_changed);
+      });
+    },
   };
 }
+
+/// Apply an edit to the terrain, and push every block it changes to physics and the clients.
+fn edit_terrain<Edit>(
+  server: &Server,
+  edit: Edit,
+) where
+  Edit: FnOnce(&mut Terrain, &mut FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex)),
+{
+  let mut updates = Vec::new();
+  {
+    let mut terrain_loader = server.terrain_loader.lock().unwrap();
+    let terrain_loader = terrain_loader.deref_mut();
+    let lod_map = &terrain_loader.lod_map;
+    // Hold the physics lock for the whole edit, so nothing collides against a half-updated world.
+    let mut physics = server.physics.lock().unwrap();
+    edit(
+      &mut terrain_loader.terrain,
+      &mut |old_block, block, position, lod| {
+        TerrainLoader::swap_block(
+          physics.deref_mut(),
+          lod_map,
+          position,
+          lod,
+          old_block,
+          block,
+        );
+
+        updates.push(TerrainBlockSend {
+          position: Copyable(*position),
+          block: block.clone(),
+          lod: Copyable(lod),
+        });
+      },
+    );
+  }
+
+  let clients = server.clients.lock().unwrap();
+  for update in updates.into_iter() {
+    for client in clients.values() {
+      client.sender.send(Some(ServerToClient::UpdateBlock(update.clone()))).unwrap();
+    }
+  }
+}
@@ -1,7 +1,7 @@
-use cgmath::{Point, Vector, Vector3};
-use std::ops::Neg;
+use cgmath::{Point, EuclideanVector, Vector3};
 use std::sync::mpsc::Sender;
 use stopwatch::TimerSet;
+use time;
 
 use common::block_position::BlockPosition;
 use common::communicate::ServerToClient::*;
@@ -28,6 +28,15 @@ pub fn update_world(
         player.update(timers, server, &mut request_block);
       }
 
+      // Journaling syncs to disk, so don't do it every update.
+      if server.journal_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+        let saved: Vec<_> =
+          server.players.lock().unwrap().values()
+          .map(|player| (player.name.clone(), player.saved()))
+          .collect();
+        server.player_store.lock().unwrap().journal_all(&saved);
+      }
+
       let players: Vec<_> = server.players.lock().unwrap().keys().map(|&x| x).collect();
       for (_, client) in server.clients.lock().unwrap().iter() {
         for &id in players.iter() {
@@ -62,17 +71,8 @@ pub fn update_world(
 
         mob.speed = mob.speed - Vector3::new(0.0, 0.1, 0.0 as f32);
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
         let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
-        }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
-        }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
-        }
+        translate_mob(server, mob, &delta_p);
       }
     });
 
@@ -89,18 +89,14 @@ fn translate_mob(
   mob: &mut mob::Mob,
   delta_p: &Vector3<f32>,
 ) {
-  let bounds;
-  {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.entity_id, *delta_p).is_some() {
-      mob.speed.add_self_v(&delta_p.neg());
-      return;
-    } else {
-      bounds = physics.get_bounds(mob.entity_id).unwrap().clone();
-    }
+  let slide = server.physics.lock().unwrap().slide_misc(mob.entity_id, *delta_p);
+  mob.speed = slide.clip_velocity(mob.speed);
+  if slide.moved.length2() == 0.0 {
+    return;
   }
 
-  mob.position.add_self_v(delta_p);
+  mob.position.add_self_v(&slide.moved);
+  let bounds = slide.bounds;
 
   for client in server.clients.lock().unwrap().values() {
     client.sender.send(
@@ -0,0 +1,319 @@
+//! Biomes: regions of the world with their own kind of landscape.
+//!
+//! Low-frequency temperature and moisture noise over x/z gives every column a climate. Each
+//! biome sits at a point in climate space, and its influence on a column falls off with the
+//! distance between them, so neighboring biomes blend smoothly into each other.
+
+use cgmath::{EuclideanVector, Point3, Vector3};
+use noise::{Seed, perlin2};
+
+use common::material::Material;
+
+use caves::{Caves, CavesNear};
+use density::Density;
+use strata::Strata;
+use tree_placer::{TreePlacer, TreesNear};
+
+/// How quickly the climate varies across the world.
+const CLIMATE_FREQUENCY: f64 = 1.0 / 2048.0;
+/// How far apart two climates are before one biome stops mattering next to the other.
+const BLEND_WIDTH: f32 = 0.25;
+
+/// A kind of landscape.
+pub struct Biome {
+  pub name: &'static str,
+  /// Where this biome is in climate space; -1 is cold or dry and 1 is hot or wet.
+  pub temperature: f32,
+  pub moisture: f32,
+  /// How far the land is raised.
+  pub height: f32,
+  /// How much the land is stretched vertically; bigger is hillier.
+  pub vertical_scale: f32,
+  /// What the top layer of ground is made of.
+  pub surface: Material,
+  /// What's under that, down to the stone.
+  pub subsurface: Material,
+  /// How densely trees grow, relative to a forest.
+  pub trees: f32,
+}
+
+/// Every biome.
+pub static BIOMES: [Biome; 5] = [
+  Biome {
+    name: "plains",
+    temperature: 0.1, moisture: 0.0,
+    height: 0.0, vertical_scale: 1.0,
+    surface: Material::Grass, subsurface: Material::Dirt,
+    trees: 0.1,
+  },
+  Biome {
+    name: "forest",
+    temperature: 0.2, moisture: 0.5,
+    height: 4.0, vertical_scale: 1.3,
+    surface: Material::Grass, subsurface: Material::Dirt,
+    trees: 1.0,
+  },
+  Biome {
+    name: "desert",
+    temperature: 0.6, moisture: -0.5,
+    height: -4.0, vertical_scale: 0.5,
+    surface: Material::Sand, subsurface: Material::Sand,
+    trees: 0.0,
+  },
+  Biome {
+    name: "highlands",
+    temperature: -0.2, moisture: -0.4,
+    height: 16.0, vertical_scale: 3.0,
+    surface: Material::Stone, subsurface: Material::Stone,
+    trees: 0.02,
+  },
+  Biome {
+    name: "tundra",
+    temperature: -0.6, moisture: 0.2,
+    height: 2.0, vertical_scale: 1.5,
+    surface: Material::Snow, subsurface: Material::Dirt,
+    trees: 0.05,
+  },
+];
+
+/// The range of heights the land stays within, in a world with a given amplitude, however the
+/// biomes raise and stretch it.
+pub fn surface_range(amplitude: f32) -> (f32, f32) {
+  BIOMES.iter().fold((-amplitude, amplitude), |(low, high), biome| {
+    let reach = biome.vertical_scale * amplitude;
+    (low.min(biome.height - reach), high.max(biome.height + reach))
+  })
+}
+
+/// The biomes' combined influence on a column.
+pub struct Blend {
+  pub height: f32,
+  pub vertical_scale: f32,
+  pub trees: f32,
+  /// The biome with the most influence, which decides what the ground is made of.
+  pub dominant: &'static Biome,
+}
+
+struct Climate {
+  temperature: Seed,
+  moisture: Seed,
+}
+
+/// The world's density function, shaped by biomes, carved out by caves and decorated with trees,
+/// along with what's underground.
+pub struct Biomes {
+  density: Density,
+  // None if this world doesn't have biomes.
+  climate: Option<Climate>,
+  // None if this world doesn't have caves.
+  caves: Option<Caves>,
+  // None if this world doesn't have trees.
+  trees: Option<TreePlacer>,
+  // None if this world's rock is all stone.
+  strata: Option<Strata>,
+}
+
+impl Biomes {
+  /// Shape `density` with biomes, whose climate is seeded by `seed`.
+  pub fn new(density: Density, seed: u32) -> Biomes {
+    Biomes {
+      density: density,
+      climate: Some(Climate {
+        temperature: Seed::new(seed.wrapping_add(1)),
+        moisture: Seed::new(seed.wrapping_add(2)),
+      }),
+      caves: None,
+      trees: None,
+      strata: None,
+    }
+  }
+
+  /// Use `density` as-is, like plains everywhere.
+  pub fn none(density: Density) -> Biomes {
+    Biomes {
+      density: density,
+      climate: None,
+      caves: None,
+      trees: None,
+      strata: None,
+    }
+  }
+
+  /// Carve `caves` out of the terrain.
+  pub fn with_caves(mut self, caves: Caves) -> Biomes {
+    self.caves = Some(caves);
+    self
+  }
+
+  /// Grow trees from `trees` on the terrain.
+  pub fn with_trees(mut self, trees: TreePlacer) -> Biomes {
+    self.trees = Some(trees);
+    self
+  }
+
+  /// Layer the rock underground with `strata`.
+  pub fn with_strata(mut self, strata: Strata) -> Biomes {
+    self.strata = Some(strata);
+    self
+  }
+
+  /// The (temperature, moisture) of a column.
+  pub fn climate_at(&self, x: f32, z: f32) -> (f32, f32) {
+    match self.climate {
+      None => (BIOMES[0].temperature, BIOMES[0].moisture),
+      Some(ref climate) => {
+        let p = [x as f64 * CLIMATE_FREQUENCY, z as f64 * CLIMATE_FREQUENCY];
+        // Perlin noise rarely gets near +/-1, so stretch it out a bit.
+        let stretch = |v: f64| (1.5 * v as f32).max(-1.0).min(1.0);
+        (stretch(perlin2(&climate.temperature, &p)), stretch(perlin2(&climate.moisture, &p)))
+      },
+    }
+  }
+
+  /// How the biomes combine in a column.
+  pub fn blend_at(&self, x: f32, z: f32) -> Blend {
+    let (temperature, moisture) = self.climate_at(x, z);
+
+    let mut blend = Blend {
+      height: 0.0,
+      vertical_scale: 0.0,
+      trees: 0.0,
+      dominant: &BIOMES[0],
+    };
+
+    // Without a climate, plains win outright.
+    if self.climate.is_none() {
+      blend.vertical_scale = 1.0;
+      blend.trees = BIOMES[0].trees;
+      return blend
+    }
+
+    let sqr_distance = |biome: &Biome| {
+      let dt = biome.temperature - temperature;
+      let dm = biome.moisture - moisture;
+      dt * dt + dm * dm
+    };
+
+    let mut min_sqr_distance = sqr_distance(&BIOMES[0]);
+    for biome in BIOMES.iter() {
+      let d = sqr_distance(biome);
+      if d < min_sqr_distance {
+        min_sqr_distance = d;
+        blend.dominant = biome;
+      }
+    }
+
+    // Weigh each biome relative to the nearest one, so the weights can't all underflow.
+    let mut total_weight = 0.0;
+    for biome in BIOMES.iter() {
+      let weight = (-(sqr_distance(biome) - min_sqr_distance) / (BLEND_WIDTH * BLEND_WIDTH)).exp();
+      blend.height = blend.height + weight * biome.height;
+      blend.vertical_scale = blend.vertical_scale + weight * biome.vertical_scale;
+      blend.trees = blend.trees + weight * biome.trees;
+      total_weight = total_weight + weight;
+    }
+
+    blend.height = blend.height / total_weight;
+    blend.vertical_scale = blend.vertical_scale / total_weight;
+    blend.trees = blend.trees / total_weight;
+    blend
+  }
+
+  /// The density of the landscape, before caves are carved out of it, at a point in a column
+  /// with a given blend.
+  pub fn density_in(&self, blend: &Blend, x: f32, y: f32, z: f32) -> f32 {
+    self.density.density_at(x, (y - blend.height) / blend.vertical_scale, z)
+  }
+
+  /// The field in the box from `low` to `high`, with the caves and trees there looked up ahead of
+  /// time, so it can be sampled many times without locking anything.
+  pub fn near(&self, low: &Point3<f32>, high: &Point3<f32>) -> Nearby {
+    Nearby {
+      biomes: self,
+      caves: self.caves.as_ref().map(|caves| caves.near(low, high)),
+      trees: self.trees.as_ref().map(|trees| trees.near(self, low, high)),
+    }
+  }
+
+  /// What the rock is at a point `depth` world units below the surface.
+  pub fn rock_at(&self, x: f32, y: f32, z: f32, depth: f32) -> Material {
+    match self.strata {
+      None => Material::Stone,
+      Some(ref strata) => strata.rock_at(x, y, z, depth),
+    }
+  }
+
+  /// The density of the field at a given x,y,z.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    let p = Point3::new(x, y, z);
+    self.near(&p, &p).density_at(x, y, z)
+  }
+}
+
+/// The field in some bounds, from `Biomes::near`. Sampling it outside those bounds might miss
+/// caves and trees.
+pub struct Nearby<'a> {
+  pub biomes: &'a Biomes,
+  caves: Option<CavesNear<'a>>,
+  trees: Option<TreesNear>,
+}
+
+impl<'a> Nearby<'a> {
+  /// The cave field at a point: negative inside a cave.
+  pub fn caves_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    match self.caves {
+      None => ::std::f32::INFINITY,
+      Some(ref caves) => caves.density_at(x, y, z),
+    }
+  }
+
+  /// The tree field at a point (positive inside a tree), and what the tree is made of there.
+  pub fn trees_at(&self, x: f32, y: f32, z: f32) -> (f32, Material) {
+    match self.trees {
+      None => (::std::f32::NEG_INFINITY, Material::Empty),
+      Some(ref trees) => trees.density_at(x, y, z),
+    }
+  }
+
+  /// The density of the field at a given x,y,z.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    let biomes = self.biomes;
+    let ground = biomes.density_in(&biomes.blend_at(x, z), x, y, z).min(self.caves_at(x, y, z));
+    ground.max(self.trees_at(x, y, z).0)
+  }
+
+  /// The lighting normal of the tile at a given x,y,z.
+  pub fn normal_at(&self, delta: f32, x: f32, y: f32, z: f32) -> Vector3<f32> {
+    let dx = self.density_at(x + delta, y, z) - self.density_at(x - delta, y, z);
+    let dy = self.density_at(x, y + delta, z) - self.density_at(x, y - delta, z);
+    let dz = self.density_at(x, y, z + delta) - self.density_at(x, y, z - delta);
+
+    // Negate because we're "leaving" the surface when density is *decreasing*.
+    -Vector3::new(dx, dy, dz).normalize()
+  }
+}
+
+#[test]
+fn biomes_blend_continuously() {
+  use world::Params;
+
+  let params = Params::new(5);
+  let plain = Biomes::none(Density::parse(&params.density, &params).unwrap());
+  let density = Density::parse(&params.density, &params).unwrap();
+  assert_eq!(plain.density_at(3.0, -7.0, 12.5), density.density_at(3.0, -7.0, 12.5));
+
+  let biomes = Biomes::new(density, params.seed);
+  let (low, high) = surface_range(64.0);
+  let mut previous = biomes.blend_at(0.0, 0.0);
+  let mut x = 0.0;
+  while x < 8192.0 {
+    let blend = biomes.blend_at(x, 0.0);
+    assert!((blend.height - previous.height).abs() < 1.0);
+    assert!((blend.vertical_scale - previous.vertical_scale).abs() < 0.1);
+    assert!(blend.vertical_scale >= 0.5 && blend.vertical_scale <= 3.0);
+    assert!(blend.height - blend.vertical_scale * 64.0 >= low - 1e-3);
+    assert!(blend.height + blend.vertical_scale * 64.0 <= high + 1e-3);
+    previous = blend;
+    x = x + 4.0;
+  }
+}
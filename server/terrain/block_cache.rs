@@ -0,0 +1,191 @@
+//! Generated blocks saved in the world directory, so they don't have to be generated again.
+//!
+//! Each block is a file holding the encoded `TerrainBlock` followed by the voxels it was
+//! generated from, which the server needs to find out what players are pointing at.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use common::block_position::BlockPosition;
+use common::entity::EntityId;
+use common::id_allocator::IdAllocator;
+use common::lod::LODIndex;
+use common::serialize;
+use common::terrain_block;
+use common::terrain_block::TerrainBlock;
+
+use pager;
+use voxel;
+use voxel_tree::{TreeBody, VoxelTree};
+use world::{Bytes, push_u32, write_atomically};
+
+/// Bump this whenever block generation changes, so stale blocks aren't used.
+pub const VERSION: u32 = 4;
+
+const MAGIC: &'static [u8] = b"PFBK";
+
+/// A directory of generated blocks.
+pub struct BlockCache {
+  dir: PathBuf,
+}
+
+impl BlockCache {
+  /// Keep blocks in `dir`, creating it if necessary.
+  pub fn open(dir: &Path) -> io::Result<BlockCache> {
+    for lod in 0..terrain_block::LG_SAMPLE_SIZE.len() {
+      try!(fs::create_dir_all(&dir.join(lod.to_string())));
+    }
+    Ok(BlockCache {
+      dir: dir.to_path_buf(),
+    })
+  }
+
+  fn path(&self, position: &BlockPosition, lod: LODIndex) -> PathBuf {
+    let p = position.as_pnt();
+    self.dir.join(lod.0.to_string()).join(format!("{}_{}_{}.block", p.x, p.y, p.z))
+  }
+
+  /// Has this block been cached?
+  pub fn contains(&self, position: &BlockPosition, lod: LODIndex) -> bool {
+    fs::metadata(&self.path(position, lod)).is_ok()
+  }
+
+  /// Cache a block, along with its voxels from `voxels`.
+  pub fn save(
+    &self,
+    position: &BlockPosition,
+    lod: LODIndex,
+    block: &TerrainBlock,
+    voxels: &mut VoxelTree,
+  ) -> io::Result<()> {
+    let encoded =
+      try!(serialize::encode(block).map_err(|_| invalid("couldn't encode block")));
+    let encoded: &[u8] = encoded.as_ref();
+
+    let mut bytes = Vec::new();
+    bytes.extend(MAGIC.iter().cloned());
+    push_u32(&mut bytes, VERSION);
+    push_u32(&mut bytes, encoded.len() as u32);
+    bytes.extend(encoded.iter().cloned());
+    for bounds in block_voxels(position, lod).iter() {
+      match voxels.get(bounds) {
+        None => bytes.push(0),
+        Some(voxel) => {
+          bytes.push(1);
+          pager::write_voxel(&mut bytes, voxel);
+        },
+      }
+    }
+
+    write_atomically(&self.path(position, lod), |file| file.write_all(&bytes))
+  }
+
+  /// Load a cached block, if there is one, giving its triangles fresh ids.
+  /// Its voxels are put in `voxels`, wherever there isn't one already.
+  pub fn load(
+    &self,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    position: &BlockPosition,
+    lod: LODIndex,
+    voxels: &mut VoxelTree,
+  ) -> Option<TerrainBlock> {
+    let path = self.path(position, lod);
+    let mut bytes = Vec::new();
+    match File::open(&path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+      Ok(_) => {},
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None,
+      Err(e) => {
+        warn!("Couldn't read cached block {:?}: {}", path, e);
+        return None
+      },
+    }
+
+    let mut block = match decode(&bytes, position, lod, voxels) {
+      Ok(block) => block,
+      Err(e) => {
+        // Probably from an older version; regenerate it.
+        info!("Not using cached block {:?}: {}", path, e);
+        return None
+      },
+    };
+
+    let mut id_allocator = id_allocator.lock().unwrap();
+    for i in 0..block.ids.len() {
+      let id = id_allocator.allocate();
+      block.ids[i] = id;
+      block.bounds[i].0 = id;
+    }
+
+    Some(block)
+  }
+
+  /// Forget a cached block, e.g. because it's been edited.
+  pub fn remove(&self, position: &BlockPosition, lod: LODIndex) {
+    match fs::remove_file(&self.path(position, lod)) {
+      Ok(()) => {},
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+      Err(e) => warn!("Couldn't remove cached block {:?}: {}", position, e),
+    }
+  }
+}
+
+fn invalid(description: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::Other, description)
+}
+
+/// The bounds of the voxels inside a block, in a fixed order.
+fn block_voxels(position: &BlockPosition, lod: LODIndex) -> Vec<voxel::Bounds> {
+  let lateral_samples = terrain_block::EDGE_SAMPLES[lod.0 as usize] as i32;
+  let lg_size = terrain_block::LG_SAMPLE_SIZE[lod.0 as usize];
+  let p = position.as_pnt();
+  let mut bounds = Vec::new();
+  for dx in 0..lateral_samples {
+  for dy in 0..lateral_samples {
+  for dz in 0..lateral_samples {
+    bounds.push(voxel::Bounds::new(
+      p.x * lateral_samples + dx,
+      p.y * lateral_samples + dy,
+      p.z * lateral_samples + dz,
+      lg_size,
+    ));
+  }}}
+  bounds
+}
+
+fn decode(
+  bytes: &[u8],
+  position: &BlockPosition,
+  lod: LODIndex,
+  voxels: &mut VoxelTree,
+) -> io::Result<TerrainBlock> {
+  let mut cursor = Bytes(bytes);
+  if try!(cursor.take(MAGIC.len())) != MAGIC {
+    return Err(invalid("not a cached block"))
+  }
+  if try!(cursor.u32()) != VERSION {
+    return Err(invalid("cached with a different version"))
+  }
+  let len = try!(cursor.u32());
+  let block: TerrainBlock =
+    try!(serialize::decode(try!(cursor.take(len as usize))).map_err(|_| invalid("malformed block")));
+
+  let mut rest = cursor.0.iter().cloned();
+  for bounds in block_voxels(position, lod).iter() {
+    let voxel =
+      match rest.next() {
+        Some(0) => continue,
+        Some(1) => try!(pager::read_voxel(&mut rest).ok_or(invalid("malformed voxel"))),
+        _ => return Err(invalid("malformed voxel")),
+      };
+    let branch = voxels.get_mut_or_create(bounds);
+    if *branch == TreeBody::Empty {
+      *branch = TreeBody::Leaf(voxel);
+    }
+  }
+
+  Ok(block)
+}
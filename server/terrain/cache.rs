@@ -0,0 +1,61 @@
+//! A cache that forgets what hasn't been used in a while, without keeping track of when each
+//! entry was last used.
+//!
+//! Entries live in two generations. New entries go in the newer one, and entries found in the
+//! older one are moved up to it. When the newer generation fills up, the older one is dropped and
+//! the newer one takes its place, so whatever's still in use survives.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+
+pub struct Cache<K, V> {
+  capacity: usize,
+  new: HashMap<K, V>,
+  old: HashMap<K, V>,
+}
+
+impl<K: Hash + Eq + Copy, V: Clone> Cache<K, V> {
+  /// Keep up to `capacity` recently-used entries, and up to `capacity` more older ones.
+  pub fn new(capacity: usize) -> Cache<K, V> {
+    Cache {
+      capacity: capacity,
+      new: HashMap::new(),
+      old: HashMap::new(),
+    }
+  }
+
+  /// The value for `key`, calling `generate` to make it if it isn't cached.
+  pub fn get<F: FnOnce() -> V>(&mut self, key: K, generate: F) -> V {
+    if let Some(value) = self.new.get(&key) {
+      return value.clone()
+    }
+
+    let value =
+      match self.old.remove(&key) {
+        Some(value) => value,
+        None => generate(),
+      };
+
+    if self.new.len() >= self.capacity {
+      self.old = mem::replace(&mut self.new, HashMap::new());
+    }
+    self.new.insert(key, value.clone());
+    value
+  }
+}
+
+#[test]
+fn recently_used_entries_survive() {
+  let mut cache = Cache::new(2);
+  assert_eq!(cache.get(1, || "one"), "one");
+  assert_eq!(cache.get(2, || "two"), "two");
+  // Pushes 1 and 2 into the older generation.
+  assert_eq!(cache.get(3, || "three"), "three");
+  // Moves 1 back up.
+  assert_eq!(cache.get(1, || panic!("1 should still be cached")), "one");
+  // Drops 2, but not 1.
+  assert_eq!(cache.get(4, || "four"), "four");
+  assert_eq!(cache.get(1, || panic!("1 should still be cached")), "one");
+  assert_eq!(cache.get(2, || "two again"), "two again");
+}
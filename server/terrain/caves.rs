@@ -0,0 +1,246 @@
+//! Caves: tunnels that worm through the ground, and large caverns deep down.
+//!
+//! Caves are a field of their own, negative inside a cave and positive outside, which is
+//! combined with the landscape's density by taking the minimum. Everything here depends only on
+//! the world position and the seed, so neighboring blocks always agree about where caves are.
+//!
+//! Worms are random walks. Each region of the world starts some number of them, determined
+//! by a random number generator seeded by the world seed and the region's position. Worms are
+//! short enough that they never reach past the regions next to the one they start in.
+//!
+//! Looking worms up takes a lock, so anything sampling caves many times should look up the ones
+//! near where it's sampling once, with `near`, and sample those.
+
+use cgmath::{Point, Point3, Vector, EuclideanVector, Vector3};
+use noise::{Seed, Brownian3, perlin3};
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+use cache::Cache;
+use density::DENSITY_PER_UNIT;
+use rng::Rng;
+
+/// lg of the width of the regions worms start in.
+const LG_REGION_WIDTH: i32 = 6;
+/// Out of 256, the chance that a region starts a worm.
+const WORM_CHANCE: u64 = 96;
+/// Worms don't start above this height, to keep them underground (mostly).
+const MAX_WORM_Y: f32 = 0.0;
+const WORM_STEPS: usize = 24;
+const WORM_STEP_LENGTH: f32 = 2.0;
+const MIN_WORM_RADIUS: f32 = 1.5;
+const MAX_WORM_RADIUS: f32 = 4.0;
+/// How far outside a worm its field stops changing.
+const WORM_MARGIN: f32 = 8.0;
+/// How many regions' worms to remember.
+const MAX_CACHED_REGIONS: usize = 1 << 12;
+
+const CAVERN_FREQUENCY: f64 = 1.0 / 128.0;
+/// Caverns are where the cavern noise is above this.
+const CAVERN_THRESHOLD: f32 = 0.3;
+/// Caverns taper off above this depth.
+const CAVERN_DEPTH: f32 = 64.0;
+
+type Region = (i32, i32, i32);
+
+/// A tunnel, as a path of points with a radius at each one.
+struct Worm {
+  points: Vec<(Point3<f32>, f32)>,
+  low: Point3<f32>,
+  high: Point3<f32>,
+}
+
+impl Worm {
+  fn generate(rng: &mut Rng, start: Point3<f32>) -> Worm {
+    let mut yaw = rng.range(0.0, 2.0 * PI);
+    let mut pitch = rng.range(-0.3, 0.3);
+    let mut radius = rng.range(MIN_WORM_RADIUS, MAX_WORM_RADIUS);
+    let mut p = start;
+
+    let mut worm = Worm {
+      points: Vec::with_capacity(WORM_STEPS + 1),
+      low: start,
+      high: start,
+    };
+
+    for _ in 0..WORM_STEPS + 1 {
+      worm.points.push((p, radius));
+      let reach = radius + WORM_MARGIN;
+      worm.low = Point3::new(worm.low.x.min(p.x - reach), worm.low.y.min(p.y - reach), worm.low.z.min(p.z - reach));
+      worm.high = Point3::new(worm.high.x.max(p.x + reach), worm.high.y.max(p.y + reach), worm.high.z.max(p.z + reach));
+
+      let direction = Vector3::new(pitch.cos() * yaw.cos(), pitch.sin(), pitch.cos() * yaw.sin());
+      p = p.add_v(&direction.mul_s(WORM_STEP_LENGTH));
+      yaw = yaw + rng.range(-0.4, 0.4);
+      pitch = (pitch + rng.range(-0.2, 0.2)).max(-0.6).min(0.6);
+      radius = (radius + rng.range(-0.5, 0.5)).max(MIN_WORM_RADIUS).min(MAX_WORM_RADIUS);
+    }
+
+    worm
+  }
+
+  /// How far outside the worm a point is, up to `WORM_MARGIN`; negative inside it.
+  fn distance_to(&self, p: &Point3<f32>) -> f32 {
+    if !self.overlaps(p, p) {
+      return WORM_MARGIN
+    }
+
+    let mut distance = WORM_MARGIN;
+    for segment in self.points.windows(2) {
+      let (a, ra) = segment[0];
+      let (b, rb) = segment[1];
+      let ab = b.sub_p(&a);
+      let t = (p.sub_p(&a).dot(&ab) / ab.length2()).max(0.0).min(1.0);
+      let closest = a.add_v(&ab.mul_s(t));
+      let d = p.sub_p(&closest).length() - (ra + (rb - ra) * t);
+      distance = distance.min(d);
+    }
+    distance
+  }
+
+  /// Whether any of the box from `low` to `high` is close enough to the worm to be affected by it.
+  fn overlaps(&self, low: &Point3<f32>, high: &Point3<f32>) -> bool {
+    high.x >= self.low.x && high.y >= self.low.y && high.z >= self.low.z
+    && low.x <= self.high.x && low.y <= self.high.y && low.z <= self.high.z
+  }
+}
+
+/// The caves in a world.
+pub struct Caves {
+  seed: u32,
+  cavern: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  cavern_seed: Seed,
+  worms: Mutex<Cache<Region, Arc<Vec<Worm>>>>,
+}
+
+/// The caves in some bounds, with the worms that might reach them already looked up.
+pub struct CavesNear<'a> {
+  caves: &'a Caves,
+  worms: Vec<Arc<Vec<Worm>>>,
+}
+
+impl Caves {
+  pub fn new(seed: u32) -> Caves {
+    let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+    Caves {
+      seed: seed,
+      cavern:
+        Brownian3::new(perlin3, 2)
+        .frequency(CAVERN_FREQUENCY)
+        .persistence(0.5)
+        .lacunarity(2.0),
+      cavern_seed: Seed::new(seed.wrapping_add(3)),
+      worms: Mutex::new(Cache::new(MAX_CACHED_REGIONS)),
+    }
+  }
+
+  fn generate_worms(&self, region: Region) -> Vec<Worm> {
+    let width = (1 << LG_REGION_WIDTH) as f32;
+    let low = Point3::new(region.0 as f32 * width, region.1 as f32 * width, region.2 as f32 * width);
+    let mut rng = Rng::new(self.seed, &[region.0, region.1, region.2]);
+
+    let mut worms = Vec::new();
+    if low.y + width > MAX_WORM_Y || rng.next() % 256 >= WORM_CHANCE {
+      return worms
+    }
+
+    let start =
+      Point3::new(
+        low.x + rng.range(0.0, width),
+        low.y + rng.range(0.0, width),
+        low.z + rng.range(0.0, width),
+      );
+    worms.push(Worm::generate(&mut rng, start));
+    worms
+  }
+
+  /// The caves in the box from `low` to `high`. Sampling them anywhere outside it might miss worms.
+  pub fn near(&self, low: &Point3<f32>, high: &Point3<f32>) -> CavesNear {
+    let region = |p: &Point3<f32>| (
+      (p.x.floor() as i32) >> LG_REGION_WIDTH,
+      (p.y.floor() as i32) >> LG_REGION_WIDTH,
+      (p.z.floor() as i32) >> LG_REGION_WIDTH,
+    );
+    let (low_region, high_region) = (region(low), region(high));
+
+    let mut near = CavesNear {
+      caves: self,
+      worms: Vec::new(),
+    };
+
+    let mut worms = self.worms.lock().unwrap();
+    // Worms reach into the regions next to the one they start in.
+    for x in low_region.0 - 1 .. high_region.0 + 2 {
+    for y in low_region.1 - 1 .. high_region.1 + 2 {
+    for z in low_region.2 - 1 .. high_region.2 + 2 {
+      let in_region = worms.get((x, y, z), || Arc::new(self.generate_worms((x, y, z))));
+      if in_region.iter().any(|worm| worm.overlaps(low, high)) {
+        near.worms.push(in_region);
+      }
+    }}}
+
+    near
+  }
+
+  fn cavern_density_at(&self, p: &Point3<f32>) -> f32 {
+    let n = self.cavern.apply(&self.cavern_seed, &[p.x as f64, p.y as f64, p.z as f64]) as f32;
+    // Raise the threshold (making caverns rarer) as we get closer to the surface.
+    let taper = (p.y + CAVERN_DEPTH).max(0.0) * DENSITY_PER_UNIT;
+    CAVERN_THRESHOLD - n + taper
+  }
+
+  /// The cave field at a point: negative inside a cave, positive outside.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    let p = Point3::new(x, y, z);
+    self.near(&p, &p).density_at(x, y, z)
+  }
+}
+
+impl<'a> CavesNear<'a> {
+  /// The cave field at a point in these bounds: negative inside a cave, positive outside.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    let p = Point3::new(x, y, z);
+    let mut distance = WORM_MARGIN;
+    for worms in self.worms.iter() {
+      for worm in worms.iter() {
+        distance = distance.min(worm.distance_to(&p));
+      }
+    }
+    (distance * DENSITY_PER_UNIT).min(self.caves.cavern_density_at(&p))
+  }
+}
+
+#[test]
+fn caves_are_deterministic() {
+  let caves = Caves::new(9);
+  let mut samples = Vec::new();
+  let mut found_cave = false;
+  let mut x = -64.0;
+  while x < 64.0 {
+    let mut y = -160.0;
+    while y < -32.0 {
+      let mut z = -64.0;
+      while z < 64.0 {
+        let d = caves.density_at(x, y, z);
+        found_cave = found_cave || d < 0.0;
+        samples.push((x, y, z, d));
+        z = z + 4.0;
+      }
+      y = y + 4.0;
+    }
+    x = x + 4.0;
+  }
+  assert!(found_cave);
+
+  // A fresh set of caves, sampled in the opposite order, has to agree.
+  let caves = Caves::new(9);
+  for &(x, y, z, d) in samples.iter().rev() {
+    assert_eq!(caves.density_at(x, y, z), d);
+  }
+
+  // So do the caves looked up over a large area all at once.
+  let near = caves.near(&Point3::new(-64.0, -160.0, -64.0), &Point3::new(64.0, -32.0, 64.0));
+  for &(x, y, z, d) in samples.iter() {
+    assert_eq!(near.density_at(x, y, z), d);
+  }
+}
@@ -0,0 +1,316 @@
+//! Density functions, built from noise, gradients and combinators, and written as s-expressions.
+//!
+//! Terrain is solid wherever the density is non-negative. An expression is one of:
+//!
+//!   * a number, which is constant everywhere;
+//!   * `x`, `y` or `z`, the coordinate itself;
+//!   * `(noise [octaves frequency persistence lacunarity [seed]])`, Brownian perlin noise.
+//!     Missing parameters come from the world's, and `seed` is added to the world's seed;
+//!   * `(add e...)`, `(mul e...)`, `(min e...)` or `(max e...)`;
+//!   * `(clamp low high e)`, with `low` and `high` numbers;
+//!   * `(blend a b t)`, which is `a` where `t <= 0`, `b` where `t >= 1`, and linear in between;
+//!   * `(warp dx dy dz e)`, which is `e` sampled at the point moved by `(dx, dy, dz)`;
+//!   * `(scale sx sy sz e)`, which is `e` sampled at `(x * sx, y * sy, z * sz)`.
+//!
+//! `;` starts a comment that runs to the end of the line.
+//!
+//! Functions are written for land within about `AMPLITUDE` of y = 0, like the default. Worlds
+//! with a different amplitude stretch them vertically, and scale their density to match, so it
+//! still changes by about `DENSITY_PER_UNIT` per world unit near the surface.
+
+use cgmath::{EuclideanVector, Vector3};
+use noise::{Seed, Brownian3, perlin3};
+
+use world::Params;
+use AMPLITUDE;
+
+/// The density function terrain is generated from by default. It's the same as the original
+/// heightmap: noise with the world's parameters, falling off with height.
+pub const DEFAULT: &'static str = "(add (noise) (mul y -0.015625))";
+
+/// Roughly how much the default density function changes per world unit, near the surface.
+/// Other fields that get combined with it (like caves) use the same scale.
+pub const DENSITY_PER_UNIT: f32 = 1.0 / 64.0;
+
+type Noise = Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>;
+
+enum Node {
+  Constant(f32),
+  Coordinate(usize),
+  Noise(Noise, Seed),
+  Add(Vec<Node>),
+  Mul(Vec<Node>),
+  Min(Vec<Node>),
+  Max(Vec<Node>),
+  Clamp(f32, f32, Box<Node>),
+  Blend(Box<Node>, Box<Node>, Box<Node>),
+  Warp(Box<Node>, Box<Node>, Box<Node>, Box<Node>),
+  Scale(f32, f32, f32, Box<Node>),
+}
+
+impl Node {
+  fn eval(&self, p: &[f32; 3]) -> f32 {
+    match *self {
+      Node::Constant(c) => c,
+      Node::Coordinate(i) => p[i],
+      Node::Noise(ref noise, ref seed) => {
+        let coords = [p[0] as f64, p[1] as f64, p[2] as f64];
+        noise.apply(seed, &coords) as f32
+      },
+      Node::Add(ref xs) => xs.iter().fold(0.0, |acc, x| acc + x.eval(p)),
+      Node::Mul(ref xs) => xs.iter().fold(1.0, |acc, x| acc * x.eval(p)),
+      Node::Min(ref xs) => xs[1..].iter().fold(xs[0].eval(p), |acc, x| acc.min(x.eval(p))),
+      Node::Max(ref xs) => xs[1..].iter().fold(xs[0].eval(p), |acc, x| acc.max(x.eval(p))),
+      Node::Clamp(low, high, ref x) => x.eval(p).max(low).min(high),
+      Node::Blend(ref a, ref b, ref t) => {
+        let t = t.eval(p).max(0.0).min(1.0);
+        if t <= 0.0 {
+          a.eval(p)
+        } else if t >= 1.0 {
+          b.eval(p)
+        } else {
+          let a = a.eval(p);
+          a + (b.eval(p) - a) * t
+        }
+      },
+      Node::Warp(ref dx, ref dy, ref dz, ref x) => {
+        let warped = [p[0] + dx.eval(p), p[1] + dy.eval(p), p[2] + dz.eval(p)];
+        x.eval(&warped)
+      },
+      Node::Scale(sx, sy, sz, ref x) => x.eval(&[p[0] * sx, p[1] * sy, p[2] * sz]),
+    }
+  }
+}
+
+enum SExp {
+  Atom(String),
+  List(Vec<SExp>),
+}
+
+fn tokenize(source: &str) -> Vec<String> {
+  let mut tokens = Vec::new();
+  for line in source.lines() {
+    let line = line.splitn(2, ';').next().unwrap();
+    let line = line.replace("(", " ( ").replace(")", " ) ");
+    tokens.extend(line.split(|c: char| c.is_whitespace()).filter(|s| !s.is_empty()).map(|s| s.to_owned()));
+  }
+  tokens
+}
+
+/// `source` on one line, without comments.
+pub fn normalize(source: &str) -> String {
+  tokenize(source).connect(" ").replace("( ", "(").replace(" )", ")")
+}
+
+fn read(tokens: &[String], next: &mut usize) -> Result<SExp, String> {
+  let token =
+    match tokens.get(*next) {
+      None => return Err(String::from("Unexpected end of density function")),
+      Some(token) => token,
+    };
+  *next += 1;
+
+  if token == ")" {
+    return Err(String::from("Unexpected )"))
+  }
+  if token != "(" {
+    return Ok(SExp::Atom(token.clone()))
+  }
+
+  let mut list = Vec::new();
+  loop {
+    match tokens.get(*next) {
+      None => return Err(String::from("Missing )")),
+      Some(token) if token == ")" => {
+        *next += 1;
+        return Ok(SExp::List(list))
+      },
+      Some(_) => list.push(try!(read(tokens, next))),
+    }
+  }
+}
+
+fn number(sexp: &SExp) -> Result<f32, String> {
+  match *sexp {
+    SExp::Atom(ref atom) => atom.parse().map_err(|_| format!("Expected a number, not {:?}", atom)),
+    SExp::List(_) => Err(String::from("Expected a number, not a list")),
+  }
+}
+
+fn build(sexp: &SExp, params: &Params) -> Result<Node, String> {
+  let list =
+    match *sexp {
+      SExp::Atom(ref atom) => {
+        return match &atom[..] {
+          "x" => Ok(Node::Coordinate(0)),
+          "y" => Ok(Node::Coordinate(1)),
+          "z" => Ok(Node::Coordinate(2)),
+          _ => number(sexp).map(Node::Constant),
+        }
+      },
+      SExp::List(ref list) => list,
+    };
+
+  let name =
+    match list.first() {
+      Some(&SExp::Atom(ref name)) => &name[..],
+      _ => return Err(String::from("Expected a function name")),
+    };
+  let args = &list[1..];
+
+  let arity = |n: usize| {
+    if args.len() == n {
+      Ok(())
+    } else {
+      Err(format!("{} takes {} arguments, not {}", name, n, args.len()))
+    }
+  };
+  let nodes = || -> Result<Vec<Node>, String> {
+    if args.is_empty() {
+      return Err(format!("{} needs at least one argument", name))
+    }
+    let mut nodes = Vec::new();
+    for arg in args.iter() {
+      nodes.push(try!(build(arg, params)));
+    }
+    Ok(nodes)
+  };
+  let node = |i: usize| build(&args[i], params).map(Box::new);
+
+  match name {
+    "noise" => {
+      if !(args.len() == 0 || args.len() == 4 || args.len() == 5) {
+        return Err(format!("noise takes 0, 4 or 5 arguments, not {}", args.len()))
+      }
+      let (octaves, frequency, persistence, lacunarity) =
+        if args.is_empty() {
+          (params.octaves, params.frequency, params.persistence, params.lacunarity)
+        } else {
+          let octaves = try!(number(&args[0]));
+          if octaves < 1.0 || octaves.fract() != 0.0 {
+            return Err(format!("noise needs a positive whole number of octaves, not {}", octaves))
+          }
+          (
+            octaves as usize,
+            try!(number(&args[1])) as f64,
+            try!(number(&args[2])) as f64,
+            try!(number(&args[3])) as f64,
+          )
+        };
+      let seed_offset =
+        match args.get(4) {
+          None => 0,
+          Some(arg) => try!(number(arg)) as u32,
+        };
+      let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+      let noise =
+        Brownian3::new(perlin3, octaves)
+        .frequency(frequency)
+        .persistence(persistence)
+        .lacunarity(lacunarity);
+      Ok(Node::Noise(noise, Seed::new(params.seed.wrapping_add(seed_offset))))
+    },
+    "add" => nodes().map(Node::Add),
+    "mul" => nodes().map(Node::Mul),
+    "min" => nodes().map(Node::Min),
+    "max" => nodes().map(Node::Max),
+    "clamp" => {
+      try!(arity(3));
+      Ok(Node::Clamp(try!(number(&args[0])), try!(number(&args[1])), try!(node(2))))
+    },
+    "blend" => {
+      try!(arity(3));
+      Ok(Node::Blend(try!(node(0)), try!(node(1)), try!(node(2))))
+    },
+    "warp" => {
+      try!(arity(4));
+      Ok(Node::Warp(try!(node(0)), try!(node(1)), try!(node(2)), try!(node(3))))
+    },
+    "scale" => {
+      try!(arity(4));
+      Ok(Node::Scale(try!(number(&args[0])), try!(number(&args[1])), try!(number(&args[2])), try!(node(3))))
+    },
+    _ => Err(format!("Unknown density function {:?}", name)),
+  }
+}
+
+/// A density function over the world.
+pub struct Density {
+  root: Node,
+  /// The world's amplitude relative to `AMPLITUDE`.
+  stretch: f32,
+}
+
+impl Density {
+  /// Parse a density function, filling in its defaults from `params`.
+  pub fn parse(source: &str, params: &Params) -> Result<Density, String> {
+    let tokens = tokenize(source);
+    let mut next = 0;
+    let sexp = try!(read(&tokens, &mut next));
+    if let Some(token) = tokens.get(next) {
+      return Err(format!("Unexpected {:?} after the density function", token))
+    }
+    let root = try!(build(&sexp, params));
+    Ok(Density {
+      root: root,
+      stretch: (params.amplitude / AMPLITUDE) as f32,
+    })
+  }
+
+  /// The density of the field at a given x,y,z.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> f32 {
+    self.root.eval(&[x, y / self.stretch, z]) * self.stretch
+  }
+
+  /// The lighting normal of the tile at a given x,y,z.
+  pub fn normal_at(&self, delta: f32, x: f32, y: f32, z: f32) -> Vector3<f32> {
+    // Get the density differential in each dimension.
+    // Use that as the approximate normal.
+
+    let dx = self.density_at(x + delta, y, z) - self.density_at(x - delta, y, z);
+    let dy = self.density_at(x, y + delta, z) - self.density_at(x, y - delta, z);
+    let dz = self.density_at(x, y, z + delta) - self.density_at(x, y, z - delta);
+
+    let v = Vector3::new(dx, dy, dz);
+
+    // Negate because we're "leaving" the surface when density is *decreasing*.
+    let v = -v.normalize();
+    v
+  }
+}
+
+#[test]
+fn parse_and_evaluate() {
+  let params = Params::new(3);
+
+  let default = Density::parse(DEFAULT, &params).unwrap();
+  let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+  let noise =
+    Brownian3::new(perlin3, params.octaves)
+    .frequency(params.frequency)
+    .persistence(params.persistence)
+    .lacunarity(params.lacunarity);
+  for &(x, y, z) in [(0.0, 0.0, 0.0), (10.5, -3.0, 7.25), (-100.0, 40.0, 3.0)].iter() {
+    let expected = noise.apply(&Seed::new(3), &[x as f64, y as f64, z as f64]) as f32 - y / 64.0;
+    assert_eq!(default.density_at(x, y, z), expected);
+  }
+
+  let density =
+    Density::parse(
+      "; a floor at y = 2\n(blend (add 2 (mul -1 y)) (clamp 0 1 x) 0.25)",
+      &params,
+    ).unwrap();
+  assert_eq!(density.density_at(0.0, 1.0, 0.0), 1.0 * 0.75 + 0.0 * 0.25);
+
+  // Twice the amplitude puts a floor at y = 16 at y = 32, with the same slope.
+  let mut tall = Params::new(3);
+  tall.amplitude = 2.0 * AMPLITUDE;
+  let density = Density::parse("(add 16 (mul -1 y))", &tall).unwrap();
+  assert_eq!(density.density_at(0.0, 32.0, 0.0), 0.0);
+  assert_eq!(density.density_at(0.0, 31.0, 0.0), 1.0);
+
+  assert!(Density::parse("(add)", &params).is_err());
+  assert!(Density::parse("(add 1 2", &params).is_err());
+  assert!(Density::parse("(sub 1 2)", &params).is_err());
+  assert!(Density::parse("(clamp 0 1 x) x", &params).is_err());
+}
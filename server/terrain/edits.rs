@@ -0,0 +1,344 @@
+//! Changes made to the terrain's density field, e.g. by players.
+//!
+//! Edits are made to samples a unit apart, but coarser LODs only sample the field every few
+//! units, so most edits would never land on their voxels' corners. Instead, each corner of a
+//! coarse voxel stands for the cell of samples within half a voxel of it. The corner counts as
+//! edited if most of the samples in that cell are, and takes whichever edited material is most
+//! common there; otherwise the generated field wins, as it does for most of the cell.
+
+use cgmath::Point3;
+use std::collections::HashMap;
+use std::iter::range_inclusive;
+use std::mem;
+
+use common::material::Material;
+use common::terrain_block;
+
+use journal::Journal;
+use voxel;
+use world;
+use world::{Bytes, World, push_u32};
+
+/// How a sample that goes back to the generated field is written in the journal.
+const UNEDITED: u8 = 0xFF;
+
+struct Region {
+  samples: HashMap<Point3<i32>, Material>,
+  /// Has this region changed since it was last saved?
+  dirty: bool,
+}
+
+/// Samples of the density field that have been explicitly set, keyed by world position.
+/// These override whatever the generated field says at those points.
+/// Edits are kept in regions, which are loaded from the world on disk (if there is one) as needed.
+/// Edits that haven't been saved to their regions yet are kept in the world's journal.
+pub struct Edits {
+  world: Option<World>,
+  journal: Option<Journal>,
+  regions: HashMap<Point3<i32>, Region>,
+  /// How many samples are edited in each cell of each coarse LOD, where `cells[lg_size - 1]`
+  /// holds the cells of voxels `2^lg_size` wide, keyed by their corners divided by `2^lg_size`.
+  cells: Vec<HashMap<Point3<i32>, u32>>,
+  /// The points set by replaying the journal, until they're taken.
+  replayed: Vec<Point3<i32>>,
+}
+
+impl Edits {
+  /// Use the edits in `world`, including any that were journaled but never saved.
+  pub fn new(world: Option<World>) -> Edits {
+    let (journal, records) =
+      match world.as_ref().map(|world| Journal::open(&world.path().join("edits.journal"))) {
+        None => (None, Vec::new()),
+        Some(Ok((journal, records))) => (Some(journal), records),
+        Some(Err(e)) => {
+          warn!("Couldn't open the edit journal; edits won't survive a crash: {}", e);
+          (None, Vec::new())
+        },
+      };
+
+    let mut edits =
+      Edits {
+        world: world,
+        journal: journal,
+        regions: HashMap::new(),
+        cells: (0 .. terrain_block::LG_WIDTH).map(|_| HashMap::new()).collect(),
+        replayed: Vec::new(),
+      };
+
+    if !records.is_empty() {
+      info!("Replaying {} journaled edits", records.len());
+    }
+    for record in records.iter() {
+      match decode_samples(record) {
+        None => warn!("Skipping a malformed journal record"),
+        Some(samples) => {
+          for &(p, material) in samples.iter() {
+            edits.set(p, material);
+            edits.replayed.push(p);
+          }
+        },
+      }
+    }
+
+    edits
+  }
+
+  /// The points that were set by replaying the journal when these edits were loaded.
+  pub fn take_replayed(&mut self) -> Vec<Point3<i32>> {
+    mem::replace(&mut self.replayed, Vec::new())
+  }
+
+  /// Append a batch of samples that are about to be `set` to the journal.
+  pub fn journal(&mut self, samples: &[(Point3<i32>, Option<Material>)]) {
+    let journal =
+      match self.journal {
+        None => return,
+        Some(ref mut journal) => journal,
+      };
+
+    let mut record = Vec::with_capacity(4 + 13 * samples.len());
+    push_u32(&mut record, samples.len() as u32);
+    for &(p, material) in samples.iter() {
+      push_u32(&mut record, p.x as u32);
+      push_u32(&mut record, p.y as u32);
+      push_u32(&mut record, p.z as u32);
+      record.push(material.map(|m| m as u8).unwrap_or(UNEDITED));
+    }
+
+    if let Err(e) = journal.append(&record) {
+      warn!("Couldn't journal edits: {}", e);
+    }
+  }
+
+  /// What's at `p`? `None` if `p` hasn't been edited.
+  /// The region containing `p` should already have been loaded with `load`.
+  pub fn get(&self, p: &Point3<i32>) -> Option<Material> {
+    self.regions.get(&world::region_of(p))
+      .and_then(|region| region.samples.get(p))
+      .map(|&material| material)
+  }
+
+  /// Set what's at `p`, or go back to the generated field if `material` is `None`.
+  /// Returns the previous edit, if there was one.
+  pub fn set(&mut self, p: Point3<i32>, material: Option<Material>) -> Option<Material> {
+    let before = {
+      let region = self.load_region(world::region_of(&p));
+      region.dirty = true;
+      match material {
+        None => region.samples.remove(&p),
+        Some(material) => region.samples.insert(p, material),
+      }
+    };
+    match (before.is_some(), material.is_some()) {
+      (false, true) => self.count_cells(&p, 1),
+      (true, false) => self.count_cells(&p, -1),
+      _ => {},
+    }
+    before
+  }
+
+  /// The edit a voxel's corner sees, if it's one that can be edited and most of its cell has
+  /// been. The edits around it should already have been loaded, to half a voxel past the corner.
+  pub fn corner_edit(&self, bounds: &voxel::Bounds, dx: i32, dy: i32, dz: i32) -> Option<Material> {
+    let p = match corner(bounds, dx, dy, dz) { None => return None, Some(p) => p };
+    let lg_size = bounds.lg_size;
+    if lg_size == 0 {
+      return self.get(&p)
+    }
+
+    // Unedited samples stay whatever was generated, so when they're the majority, so is that.
+    let edited = *self.cells[lg_size as usize - 1].get(&cell_of(&p, lg_size)).unwrap_or(&0);
+    let samples = 1 << (3 * lg_size);
+    if 2 * edited <= samples {
+      return None
+    }
+
+    // Tally the edited samples in the cell, in a fixed order so ties always go the same way.
+    let half = 1 << (lg_size - 1);
+    let mut counts: Vec<(Material, u32)> = Vec::new();
+    for x in p.x - half .. p.x + half {
+    for y in p.y - half .. p.y + half {
+    for z in p.z - half .. p.z + half {
+      if let Some(material) = self.get(&Point3::new(x, y, z)) {
+        match counts.iter().position(|&(m, _)| m == material) {
+          Some(i) => counts[i].1 += 1,
+          None => counts.push((material, 1)),
+        }
+      }
+    }}}
+
+    let mut most_common = None;
+    for &(material, count) in counts.iter() {
+      match most_common {
+        Some((_, best)) if best >= count => {},
+        _ => most_common = Some((material, count)),
+      }
+    }
+    most_common.map(|(material, _)| material)
+  }
+
+  /// Add `delta` to the number of edited samples in every coarse cell containing `p`.
+  fn count_cells(&mut self, p: &Point3<i32>, delta: i32) {
+    for (i, cells) in self.cells.iter_mut().enumerate() {
+      let lg_size = i + 1;
+      let key = cell_of(p, lg_size as i16);
+      let count = *cells.get(&key).unwrap_or(&0) as i32 + delta;
+      if count > 0 {
+        cells.insert(key, count as u32);
+      } else {
+        cells.remove(&key);
+      }
+    }
+  }
+
+  /// Make sure the edits for every point between `low` and `high` (inclusive) are loaded.
+  pub fn load(&mut self, low: &Point3<i32>, high: &Point3<i32>) {
+    let low = world::region_of(low);
+    let high = world::region_of(high);
+    for x in range_inclusive(low.x, high.x) {
+    for y in range_inclusive(low.y, high.y) {
+    for z in range_inclusive(low.z, high.z) {
+      self.load_region(Point3::new(x, y, z));
+    }}}
+  }
+
+  fn load_region<'a>(&'a mut self, position: Point3<i32>) -> &'a mut Region {
+    if !self.regions.contains_key(&position) {
+      let region = self.read_region(&position);
+      for p in region.samples.keys() {
+        for (i, cells) in self.cells.iter_mut().enumerate() {
+          *cells.entry(cell_of(p, i as i16 + 1)).or_insert(0) += 1;
+        }
+      }
+      self.regions.insert(position, region);
+    }
+    self.regions.get_mut(&position).unwrap()
+  }
+
+  fn read_region(&self, position: &Point3<i32>) -> Region {
+    let samples =
+      match self.world {
+        None => HashMap::new(),
+        Some(ref world) => {
+          match world.load_region(position) {
+            Ok(samples) => samples,
+            Err(e) => {
+              warn!("Couldn't load region {:?}: {}", position, e);
+              HashMap::new()
+            },
+          }
+        },
+      };
+    Region {
+      samples: samples,
+      dirty: false,
+    }
+  }
+
+  /// Write every region that's changed since it was loaded or saved to disk.
+  /// If they all make it, the journal is no longer needed and is emptied.
+  pub fn save(&mut self) {
+    let world =
+      match self.world {
+        None => return,
+        Some(ref world) => world,
+      };
+
+    let mut saved_all = true;
+    for (position, region) in self.regions.iter_mut() {
+      if !region.dirty {
+        continue
+      }
+      match world.save_region(position, &region.samples) {
+        Ok(()) => region.dirty = false,
+        Err(e) => {
+          warn!("Couldn't save region {:?}: {}", position, e);
+          saved_all = false;
+        },
+      }
+    }
+
+    if saved_all {
+      if let Some(ref mut journal) = self.journal {
+        if let Err(e) = journal.clear() {
+          warn!("Couldn't clear the edit journal: {}", e);
+        }
+      }
+    }
+  }
+}
+
+fn decode_samples(record: &[u8]) -> Option<Vec<(Point3<i32>, Option<Material>)>> {
+  let mut bytes = Bytes(record);
+  let count = match bytes.u32() { Ok(count) => count, Err(_) => return None };
+  let mut samples = Vec::with_capacity(count as usize);
+  for _ in 0..count {
+    let x = match bytes.u32() { Ok(x) => x as i32, Err(_) => return None };
+    let y = match bytes.u32() { Ok(y) => y as i32, Err(_) => return None };
+    let z = match bytes.u32() { Ok(z) => z as i32, Err(_) => return None };
+    let material =
+      match bytes.take(1) {
+        Err(_) => return None,
+        Ok(m) if m[0] == UNEDITED => None,
+        Ok(m) => match Material::from_u8(m[0]) { None => return None, Some(m) => Some(m) },
+      };
+    samples.push((Point3::new(x, y, z), material));
+  }
+  Some(samples)
+}
+
+/// The coarse cell of voxels `2^lg_size` wide that a sample is in, as the nearest of their
+/// corners divided by `2^lg_size`.
+pub fn cell_of(p: &Point3<i32>, lg_size: i16) -> Point3<i32> {
+  let half = (1 << lg_size) >> 1;
+  Point3::new((p.x + half) >> lg_size, (p.y + half) >> lg_size, (p.z + half) >> lg_size)
+}
+
+/// The world position of one of a voxel's corners, if it's one that can be edited.
+/// Only integer positions can be edited, so this is `None` for sub-unit voxels.
+pub fn corner(bounds: &voxel::Bounds, dx: i32, dy: i32, dz: i32) -> Option<Point3<i32>> {
+  if bounds.lg_size < 0 {
+    return None
+  }
+
+  Some(Point3::new(
+    (bounds.x + dx) << bounds.lg_size,
+    (bounds.y + dy) << bounds.lg_size,
+    (bounds.z + dz) << bounds.lg_size,
+  ))
+}
+
+#[test]
+fn coarse_corners_follow_most_of_their_cells() {
+  let mut edits = Edits::new(None);
+  edits.load(&Point3::new(-8, -8, -8), &Point3::new(8, 8, 8));
+
+  // One dug sample only shows up at the finest LOD.
+  edits.set(Point3::new(1, 3, 2), Some(Material::Empty));
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(1, 3, 2, 0), 0, 0, 0), Some(Material::Empty));
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 0), 1, 1, 1), None);
+  // A voxel 8 wide has a corner at (0, 0, 0), whose cell is everything within 4 of it.
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 3), 0, 0, 0), None);
+
+  // Digging out the whole cell shows up, and only at the corners whose cells it covers.
+  for x in -4..4 {
+  for y in -4..4 {
+  for z in -4..4 {
+    edits.set(Point3::new(x, y, z), Some(Material::Empty));
+  }}}
+  edits.set(Point3::new(0, 0, 0), Some(Material::Wood));
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 3), 0, 0, 0), Some(Material::Empty));
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 3), 1, 0, 0), None);
+  // A voxel 2 wide has a corner at (4, 0, 0), whose cell is from (3, -1, -1) to (4, 0, 0): half
+  // of it is dug out, which isn't most of it.
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(2, 0, 0, 1), 0, 0, 0), None);
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 1), 0, 0, 0), Some(Material::Empty));
+
+  // Going back to the generated field un-edits the cells too.
+  for x in -4..4 {
+  for y in -4..4 {
+  for z in -4..4 {
+    edits.set(Point3::new(x, y, z), None);
+  }}}
+  assert_eq!(edits.corner_edit(&voxel::Bounds::new(0, 0, 0, 3), 0, 0, 0), None);
+}
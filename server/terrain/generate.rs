@@ -1,4 +1,4 @@
-use cgmath::{Point, Point3, Vector, Vector3};
+use cgmath::{Point, Point3, Vector, EuclideanVector, Vector3};
 use cgmath::Aabb3;
 use std::cmp::{min, max, partial_min, partial_max};
 use std::collections::hash_map;
@@ -10,146 +10,241 @@ use common::block_position::BlockPosition;
 use common::entity::EntityId;
 use common::id_allocator::IdAllocator;
 use common::lod::LODIndex;
+use common::material::Material;
 use common::terrain_block;
 use common::terrain_block::{TerrainBlock, tri};
 
-use heightmap::HeightMap;
+use edits::Edits;
+use biome::{Biomes, Nearby};
+use materials;
+use qef::Qef;
 use voxel;
 use voxel::{Fracu8, Fraci8, Voxel, SurfaceVoxel, Vertex, Normal};
 use voxel_tree;
 use voxel_tree::VoxelTree;
 
+fn pack_normal(normal: Vector3<f32>) -> Normal {
+  let normal = normal.mul_s(127.0);
+
+  // Okay, so we scale the normal by 127, and use 127 to represent 1.0.
+  // Then we store it in a `Fraci8`, which scales by 128 and represents a
+  // fraction in [0,1). That seems wrong, but this is normal data, so scaling
+  // doesn't matter. Sketch factor is over 9000, but it's not wrong.
+
+  let normal = Vector3::new(normal.x as i32, normal.y as i32, normal.z as i32);
+  let normal =
+    Vector3::new(
+      max(-127, min(127, normal.x)) as i8,
+      max(-127, min(127, normal.y)) as i8,
+      max(-127, min(127, normal.z)) as i8,
+    );
+
+  Normal {
+    x: Fraci8::of(normal.x),
+    y: Fraci8::of(normal.y),
+    z: Fraci8::of(normal.z),
+  }
+}
+
+/// The material of the inside of a voxel: that of its highest solid corner,
+/// so surfaces take on the material nearest the top.
+fn solid_material(corners: &[[[Material; 2]; 2]; 2]) -> Material {
+  for &y in [1, 0].iter() {
+  for x in 0..2 {
+  for z in 0..2 {
+    if corners[x][y][z].is_solid() {
+      return corners[x][y][z]
+    }
+  }}}
+  Material::Empty
+}
+
+/// How far to pad the bounds of terrain triangles on every side.
+const BOUNDS_PADDING: f32 = 1.0 / 64.0;
+
+/// How many times to refine each edge crossing of the generated field.
+const CROSSING_STEPS: usize = 2;
+
+/// How far outside its voxels the field gets sampled (e.g. to find normals).
+const SAMPLE_PADDING: f32 = 1.0;
+
+/// The generated field around the voxels from `low` to `high` (inclusive), which are `lg_size`
+/// wide, so it can be sampled without locking.
+fn nearby<'a>(biomes: &'a Biomes, low: &Point3<i32>, high: &Point3<i32>, lg_size: i16) -> Nearby<'a> {
+  let size = voxel::Bounds::new(0, 0, 0, lg_size).size();
+  let to_world = |v: i32, padding: f32| v as f32 * size + padding;
+  biomes.near(
+    &Point3::new(to_world(low.x, -SAMPLE_PADDING), to_world(low.y, -SAMPLE_PADDING), to_world(low.z, -SAMPLE_PADDING)),
+    &Point3::new(to_world(high.x + 1, SAMPLE_PADDING), to_world(high.y + 1, SAMPLE_PADDING), to_world(high.z + 1, SAMPLE_PADDING)),
+  )
+}
+
+/// Where the generated field crosses the surface between a point inside it and one outside it.
+fn find_crossing(field: &Nearby, inside: &Point3<f32>, outside: &Point3<f32>) -> Point3<f32> {
+  let (mut a, mut b) = (*inside, *outside);
+  let (mut da, mut db) = (field.density_at(a.x, a.y, a.z), field.density_at(b.x, b.y, b.z));
+  let mut p = a;
+  for _ in 0..CROSSING_STEPS + 1 {
+    // Guess where the crossing would be if the field were linear, then narrow in on it.
+    let t = if da > db { (da / (da - db)).max(0.0).min(1.0) } else { 0.5 };
+    p = a.add_v(&b.sub_p(&a).mul_s(t));
+    let d = field.density_at(p.x, p.y, p.z);
+    if d >= 0.0 {
+      a = p;
+      da = d;
+    } else {
+      b = p;
+      db = d;
+    }
+  }
+  p
+}
+
+/// The normal of an edited voxel, pointing from its inside corners toward its outside ones.
+fn corner_normal(
+  field: &Nearby,
+  voxel: &voxel::Bounds,
+  corners: &[[[Material; 2]; 2]; 2],
+  vertex: &Vertex,
+) -> Vector3<f32> {
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+  for x in 0..2 {
+  for y in 0..2 {
+  for z in 0..2 {
+    let offset = Vector3::new(x as f32 - 0.5, y as f32 - 0.5, z as f32 - 0.5);
+    normal = if corners[x][y][z].is_solid() { normal - offset } else { normal + offset };
+  }}}
+
+  if normal.length2() > 0.0 {
+    normal.normalize()
+  } else {
+    // The corners are too symmetric to pick a direction; fall back to the generated field.
+    let vertex = vertex.to_world_vertex(voxel);
+    field.normal_at(0.01, vertex.x, vertex.y, vertex.z)
+  }
+}
+
+/// A coordinate within a voxel, from 0 to 1, clamped into the voxel.
+fn to_fracu8(x: f32) -> Fracu8 {
+  Fracu8::of((x * 256.0).max(0.0).min(255.0) as u8)
+}
+
 pub fn generate_voxel(
   timers: &TimerSet,
-  heightmap: &HeightMap,
+  biomes: &Biomes,
+  edits: &Edits,
   voxel: &voxel::Bounds,
 ) -> Voxel
 {
-  timers.time("generate_voxel", || {
-    let field_contains = |x, y, z| {
-      heightmap.density_at(x, y, z) >= 0.0
-    };
+  let position = Point3::new(voxel.x, voxel.y, voxel.z);
+  generate_voxel_in(timers, &nearby(biomes, &position, &position, voxel.lg_size), edits, voxel)
+}
 
+/// Generate a voxel from a field that's been looked up around it.
+fn generate_voxel_in(
+  timers: &TimerSet,
+  field: &Nearby,
+  edits: &Edits,
+  voxel: &voxel::Bounds,
+) -> Voxel
+{
+  timers.time("generate_voxel", || {
     let get_normal = |x, y, z| {
-      heightmap.normal_at(0.01, x, y, z)
+      field.normal_at(0.01, x, y, z)
     };
 
     let size = voxel.size();
-    let (x1, y1, z1) = (voxel.x as f32 * size, voxel.y as f32 * size, voxel.z as f32 * size);
-    let delta = size;
-    let (x2, y2, z2) = (x1 + delta, y1 + delta, z1 + delta);
-    // corners[x][y][z]
-    let corners = [
-      [
-        [ field_contains(x1, y1, z1), field_contains(x1, y1, z2) ],
-        [ field_contains(x1, y2, z1), field_contains(x1, y2, z2) ],
-      ],
-      [
-        [ field_contains(x2, y1, z1), field_contains(x2, y1, z2) ],
-        [ field_contains(x2, y2, z1), field_contains(x2, y2, z2) ],
-      ],
-    ];
-
-    let corner;
+    let low = Point3::new(voxel.x as f32 * size, voxel.y as f32 * size, voxel.z as f32 * size);
+    let corner_position = |x: usize, y: usize, z: usize| {
+      low.add_v(&Vector3::new(x as f32, y as f32, z as f32).mul_s(size))
+    };
+
+    // Edited samples take precedence over the generated field.
+    // edited[x][y][z] and corners[x][y][z]
+    let mut edited: [[[Option<Material>; 2]; 2]; 2] = [[[None; 2]; 2]; 2];
+    let mut corners = [[[Material::Empty; 2]; 2]; 2];
+    let mut any_edited = false;
     let mut any_inside = false;
     let mut all_inside = true;
-
-    {
-      let mut get_corner = |x1:usize, y1:usize, z1:usize| {
-        let corner = corners[x1][y1][z1];
-        any_inside = any_inside || corner;
-        all_inside = all_inside && corner;
-        corner
-      };
-
-      corner = get_corner(0,0,0);
-      let _ = get_corner(0,0,1);
-      let _ = get_corner(0,1,0);
-      let _ = get_corner(0,1,1);
-      let _ = get_corner(1,0,0);
-      let _ = get_corner(1,0,1);
-      let _ = get_corner(1,1,0);
-      let _ = get_corner(1,1,1);
-    }
+    for x in 0..2 {
+    for y in 0..2 {
+    for z in 0..2 {
+      edited[x][y][z] = edits.corner_edit(voxel, x as i32, y as i32, z as i32);
+      corners[x][y][z] =
+        match edited[x][y][z] {
+          Some(material) => {
+            any_edited = true;
+            material
+          },
+          None => {
+            let p = corner_position(x, y, z);
+            materials::generate(field, p.x, p.y, p.z)
+          },
+        };
+      let inside = corners[x][y][z].is_solid();
+      any_inside = any_inside || inside;
+      all_inside = all_inside && inside;
+    }}}
 
     let all_corners_same = any_inside == all_inside;
     if all_corners_same {
-      return Voxel::Volume(all_inside)
+      return Voxel::Volume(solid_material(&corners))
     }
 
-    let mut vertex: Vector3<u32> = Vector3::new(0, 0, 0);
-    let mut n = 0;
-    for (&x, corners) in [0, 0xFF].iter().zip(corners.iter()) {
-    for (&y, corners) in [0, 0xFF].iter().zip(corners.iter()) {
-    for (&z, &corner) in [0, 0xFF].iter().zip(corners.iter()) {
-      if corner {
-        vertex.add_self_v(&Vector3::new(x, y, z));
-        n += 1;
+    // Dual contouring: find where the surface crosses each edge of the voxel, and the normal
+    // there, and put the vertex where those planes come closest to meeting.
+    let mut qef = Qef::new();
+    for x in 0..2 {
+    for y in 0..2 {
+    for z in 0..2 {
+      let inside = corners[x][y][z].is_solid();
+      for &(dx, dy, dz) in [(1, 0, 0), (0, 1, 0), (0, 0, 1)].iter() {
+        let (x2, y2, z2) = (x + dx, y + dy, z + dz);
+        if x2 > 1 || y2 > 1 || z2 > 1 || corners[x2][y2][z2].is_solid() == inside {
+          continue
+        }
+
+        if edited[x][y][z].is_none() && edited[x2][y2][z2].is_none() {
+          let (p1, p2) = (corner_position(x, y, z), corner_position(x2, y2, z2));
+          let crossing =
+            if inside {
+              find_crossing(field, &p1, &p2)
+            } else {
+              find_crossing(field, &p2, &p1)
+            };
+          let local = Point3::from_vec(&crossing.sub_p(&low).div_s(size));
+          qef.add(&local, &get_normal(crossing.x, crossing.y, crossing.z));
+        } else {
+          // Edits only say which side of the surface a corner is on, so the surface is taken to
+          // cross halfway along the edge, square to it. That keeps dug edges and corners sharp.
+          let midpoint = Point3::new((x + x2) as f32, (y + y2) as f32, (z + z2) as f32).div_s(2.0);
+          let axis = Vector3::new(dx as f32, dy as f32, dz as f32);
+          qef.add(&midpoint, &if inside { axis } else { -axis });
+        }
       }
     }}}
 
-    {
-      // Sample in extra areas to help weight the vertex to the appropriate place.
-      let mut sample_extra = |lg_s: u8| {
-        let fs = 1.0 / ((1 << lg_s) as f32);
-        let mfs = 1.0 - fs;
-        let s = 0x100 >> lg_s;
-        let ms = 0x100 - s;
-        for &(wx, x) in
-          [((voxel.x as f32 + fs) * size, s), ((voxel.x as f32 + mfs) * size, ms)].iter() {
-        for &(wy, y) in
-          [((voxel.y as f32 + fs) * size, s), ((voxel.y as f32 + mfs) * size, ms)].iter() {
-        for &(wz, z) in
-          [((voxel.z as f32 + fs) * size, s), ((voxel.z as f32 + mfs) * size, ms)].iter() {
-          if field_contains(wx, wy, wz) {
-            vertex.add_self_v(&Vector3::new(x, y, z).mul_s(lg_s as u32));
-            n += lg_s as u32;
-          }
-        }}}
-      };
-
-      sample_extra(2);
-    }
-
-    let vertex = vertex.div_s(n);
+    let vertex = qef.solve();
     let vertex =
       Vertex {
-        x: Fracu8::of(vertex.x as u8),
-        y: Fracu8::of(vertex.y as u8),
-        z: Fracu8::of(vertex.z as u8),
+        x: to_fracu8(vertex.x),
+        y: to_fracu8(vertex.y),
+        z: to_fracu8(vertex.z),
       };
 
-    let normal;
-    {
-      // Okay, this is silly to have right after we construct the vertex.
-      let vertex = vertex.to_world_vertex(voxel);
-      normal = get_normal(vertex.x, vertex.y, vertex.z).mul_s(127.0);
-    }
-
-    // Okay, so we scale the normal by 127, and use 127 to represent 1.0.
-    // Then we store it in a `Fraci8`, which scales by 128 and represents a
-    // fraction in [0,1). That seems wrong, but this is normal data, so scaling
-    // doesn't matter. Sketch factor is over 9000, but it's not wrong.
-
-    let normal = Vector3::new(normal.x as i32, normal.y as i32, normal.z as i32);
-    let normal =
-      Vector3::new(
-        max(-127, min(127, normal.x)) as i8,
-        max(-127, min(127, normal.y)) as i8,
-        max(-127, min(127, normal.z)) as i8,
-      );
-
     let normal =
-      Normal {
-        x: Fraci8::of(normal.x),
-        y: Fraci8::of(normal.y),
-        z: Fraci8::of(normal.z),
+      if any_edited {
+        corner_normal(field, voxel, &corners, &vertex)
+      } else {
+        let vertex = vertex.to_world_vertex(voxel);
+        get_normal(vertex.x, vertex.y, vertex.z)
       };
 
     Voxel::Surface(SurfaceVoxel {
       inner_vertex: vertex,
-      normal: normal,
-      corner_inside_surface: corner,
+      normal: pack_normal(normal),
+      corner_inside_surface: corners[0][0][0].is_solid(),
+      material: solid_material(&corners),
     })
   })
 }
@@ -183,11 +278,11 @@ fn make_bounds(
   let maxz = maxz.and_then(|m| partial_max(m, v3.z));
   let maxz = maxz.unwrap();
 
+  // Pad the bounds, so flat triangles don't get flat boxes: those vanish from the physics
+  // octree's (strict) overlap tests when they lie exactly on one of its split planes.
   Aabb3::new(
-    // TODO: Remove this - 1.0. It's a temporary hack until voxel collisions work,
-    // to avoid zero-height Aabb3s.
-    Point3::new(minx, miny - 1.0, minz),
-    Point3::new(maxx, maxy, maxz),
+    Point3::new(minx - BOUNDS_PADDING, miny - BOUNDS_PADDING, minz - BOUNDS_PADDING),
+    Point3::new(maxx + BOUNDS_PADDING, maxy + BOUNDS_PADDING, maxz + BOUNDS_PADDING),
   )
 }
 
@@ -196,7 +291,8 @@ fn make_bounds(
 pub fn generate_block(
   timers: &TimerSet,
   id_allocator: &Mutex<IdAllocator<EntityId>>,
-  heightmap: &HeightMap,
+  biomes: &Biomes,
+  edits: &Edits,
   voxels: &mut VoxelTree,
   position: &BlockPosition,
   lod_index: LODIndex,
@@ -211,34 +307,43 @@ pub fn generate_block(
       voxel::Bounds::new(v.x, v.y, v.z, lg_size)
     };
 
+    let lg_ratio = terrain_block::LG_WIDTH - lg_size;
+    let block_position = position.as_pnt();
+    let voxel_position =
+      Point3::new(
+        block_position.x << lg_ratio,
+        block_position.y << lg_ratio,
+        block_position.z << lg_ratio,
+      );
+
+    // Look up the field once for the whole block, including the voxels just past its edges.
+    let field =
+      nearby(
+        biomes,
+        &voxel_position.add_v(&Vector3::new(-1, -1, -1)),
+        &voxel_position.add_v(&Vector3::new(lateral_samples, lateral_samples, lateral_samples)),
+        lg_size,
+      );
+
     let mut get_voxel = |bounds: &voxel::Bounds| {
       let branch = voxels.get_mut_or_create(bounds);
       let r;
       match branch {
         &mut voxel_tree::TreeBody::Leaf(v) => r = v,
         &mut voxel_tree::TreeBody::Empty => {
-          r = generate_voxel(timers, heightmap, bounds);
+          r = generate_voxel_in(timers, &field, edits, bounds);
           *branch = voxel_tree::TreeBody::Leaf(r);
         },
-        &mut voxel_tree::TreeBody::Branch(_) => {
+        &mut voxel_tree::TreeBody::Branch(_) | &mut voxel_tree::TreeBody::PagedOut => {
           // Overwrite existing for now.
           // TODO: Don't do ^that.
-          r = generate_voxel(timers, heightmap, bounds);
+          r = generate_voxel_in(timers, &field, edits, bounds);
           *branch = voxel_tree::TreeBody::Leaf(r);
         },
       };
       r
     };
 
-    let lg_ratio = terrain_block::LG_WIDTH - lg_size;
-    let block_position = position.as_pnt();
-    let voxel_position =
-      Point3::new(
-        block_position.x << lg_ratio,
-        block_position.y << lg_ratio,
-        block_position.z << lg_ratio,
-      );
-
     let mut coords = Vec::new();
     let mut normals = Vec::new();
     let mut indices = HashMap::new();
@@ -268,7 +373,7 @@ pub fn generate_block(
         let neighbor_inside_surface;
         match get_voxel(&bounds_at(&voxel_position.add_v(&d_neighbor))) {
           Voxel::Surface(v) => neighbor_inside_surface = v.corner_inside_surface,
-          Voxel::Volume(inside) => neighbor_inside_surface = inside,
+          Voxel::Volume(material) => neighbor_inside_surface = material.is_solid(),
         }
         if voxel.corner_inside_surface == neighbor_inside_surface {
           // This edge doesn't cross the surface, and doesn't generate polys.
@@ -325,18 +430,19 @@ pub fn generate_block(
         coords.push(v_center);
         normals.push(n_center);
 
+        let material = voxel.material;
         if voxel.corner_inside_surface {
           // The polys are visible from positive infinity.
-          polys.push([i2, i1, i_center]);
-          polys.push([i3, i2, i_center]);
-          polys.push([i4, i3, i_center]);
-          polys.push([i1, i4, i_center]);
+          polys.push(([i2, i1, i_center], material));
+          polys.push(([i3, i2, i_center], material));
+          polys.push(([i4, i3, i_center], material));
+          polys.push(([i1, i4, i_center], material));
         } else {
           // The polys are visible from negative infinity.
-          polys.push([i1, i2, i_center]);
-          polys.push([i2, i3, i_center]);
-          polys.push([i3, i4, i_center]);
-          polys.push([i4, i1, i_center]);
+          polys.push(([i1, i2, i_center], material));
+          polys.push(([i2, i3, i_center], material));
+          polys.push(([i3, i4, i_center], material));
+          polys.push(([i4, i1, i_center], material));
         }
       };
 
@@ -359,9 +465,10 @@ pub fn generate_block(
       );
     }}}
 
-    for poly in polys.iter() {
+    for &(ref poly, material) in polys.iter() {
       block.vertex_coordinates.push(tri(coords[poly[0]], coords[poly[1]], coords[poly[2]]));
       block.normals.push(tri(normals[poly[0]], normals[poly[1]], normals[poly[2]]));
+      block.materials.push(material);
 
       let id = id_allocator.lock().unwrap().allocate();
       block.ids.push(id);
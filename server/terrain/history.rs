@@ -0,0 +1,110 @@
+//! Per-player undo and redo for terrain edits.
+
+use cgmath::Point3;
+use std::collections::HashMap;
+
+use common::entity::EntityId;
+use common::material::Material;
+
+/// How many edits each player can undo.
+pub const MAX_UNDO: usize = 64;
+
+/// Samples of the field, with `None` meaning "whatever's generated there".
+pub type Samples = Vec<(Point3<i32>, Option<Material>)>;
+
+/// One edit operation.
+pub struct Change {
+  /// The samples the edit changed, with their values beforehand.
+  pub before: Samples,
+  /// The samples the edit changed, with their values afterward.
+  pub after: Samples,
+}
+
+impl Change {
+  /// The samples to set to undo this change.
+  pub fn undo(&self) -> Samples {
+    // If a sample was set more than once, its earliest value is the one we want to end up with.
+    self.before.iter().rev().map(|&s| s).collect()
+  }
+
+  /// The samples to set to redo this change.
+  pub fn redo(&self) -> Samples {
+    self.after.clone()
+  }
+}
+
+/// The changes each player has made, and the ones they've undone.
+pub struct History {
+  undo: HashMap<EntityId, Vec<Change>>,
+  redo: HashMap<EntityId, Vec<Change>>,
+}
+
+impl History {
+  pub fn new() -> History {
+    History {
+      undo: HashMap::new(),
+      redo: HashMap::new(),
+    }
+  }
+
+  /// Record a new change by `player`. This forgets anything they could have redone.
+  pub fn push(&mut self, player: EntityId, change: Change) {
+    self.redo.remove(&player);
+    History::push_onto(&mut self.undo, player, change);
+  }
+
+  /// Move `player`'s latest change onto their redo stack, and return the samples that undo it.
+  pub fn undo(&mut self, player: EntityId) -> Option<Samples> {
+    History::shift(&mut self.undo, &mut self.redo, player).map(|change| change.undo())
+  }
+
+  /// Move `player`'s latest undone change back onto their undo stack, and return the samples
+  /// that redo it.
+  pub fn redo(&mut self, player: EntityId) -> Option<Samples> {
+    History::shift(&mut self.redo, &mut self.undo, player).map(|change| change.redo())
+  }
+
+  /// Drop everything `player` could undo or redo, e.g. when they leave.
+  pub fn forget(&mut self, player: EntityId) {
+    self.undo.remove(&player);
+    self.redo.remove(&player);
+  }
+
+  fn push_onto(stacks: &mut HashMap<EntityId, Vec<Change>>, player: EntityId, change: Change) {
+    let stack = stacks.entry(player).or_insert_with(|| Vec::new());
+    if stack.len() >= MAX_UNDO {
+      stack.remove(0);
+    }
+    stack.push(change);
+  }
+
+  fn shift<'a>(
+    from: &mut HashMap<EntityId, Vec<Change>>,
+    to: &'a mut HashMap<EntityId, Vec<Change>>,
+    player: EntityId,
+  ) -> Option<&'a Change> {
+    let change =
+      match from.get_mut(&player).and_then(|stack| stack.pop()) {
+        None => return None,
+        Some(change) => change,
+      };
+    History::push_onto(to, player, change);
+    to.get(&player).and_then(|stack| stack.last())
+  }
+}
+
+#[test]
+fn undo_then_redo() {
+  let player = EntityId::default();
+  let p = Point3::new(1, 2, 3);
+  let mut history = History::new();
+  history.push(player, Change {
+    before: vec!((p, None)),
+    after: vec!((p, Some(Material::Stone))),
+  });
+
+  assert_eq!(history.undo(player), Some(vec!((p, None))));
+  assert_eq!(history.undo(player), None);
+  assert_eq!(history.redo(player), Some(vec!((p, Some(Material::Stone)))));
+  assert_eq!(history.redo(player), None);
+}
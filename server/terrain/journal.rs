@@ -0,0 +1,148 @@
+//! An append-only log of changes that haven't been written to their proper place on disk yet,
+//! so they can be replayed if the server dies before then.
+//!
+//! A journal is a header followed by records, each of which is a little-endian `u32` length, a
+//! `u32` checksum of the contents, and then the contents. Records are written and synced to disk
+//! as they're appended, so they survive the process dying, or the whole machine. A crash partway
+//! through writing a record leaves a torn or corrupt tail, which is dropped the next time it's
+//! opened.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use world::{Bytes, push_u32};
+
+const MAGIC: &'static [u8] = b"PFJN";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8;
+
+/// A journal file, open for appending.
+pub struct Journal {
+  file: File,
+}
+
+impl Journal {
+  /// Open (or create) the journal at `path`.
+  /// Returns it along with the contents of every intact record in it, oldest first.
+  pub fn open(path: &Path) -> io::Result<(Journal, Vec<Vec<u8>>)> {
+    let mut file = try!(OpenOptions::new().read(true).write(true).create(true).open(path));
+
+    let mut bytes = Vec::new();
+    try!(file.read_to_end(&mut bytes));
+
+    let mut records = Vec::new();
+    if bytes.is_empty() {
+      let mut header = Vec::new();
+      header.extend(MAGIC.iter().cloned());
+      push_u32(&mut header, VERSION);
+      try!(file.write_all(&header));
+      try!(file.sync_all());
+    } else {
+      let mut cursor = Bytes(&bytes);
+      if try!(cursor.take(MAGIC.len())) != MAGIC || try!(cursor.u32()) != VERSION {
+        return Err(io::Error::new(io::ErrorKind::Other, "not a journal, or an unsupported version"))
+      }
+
+      let mut valid_len = HEADER_LEN;
+      while let Some(record) = read_record(&mut cursor) {
+        valid_len += 8 + record.len() as u64;
+        records.push(record);
+      }
+
+      if valid_len < bytes.len() as u64 {
+        warn!(
+          "Dropping {} bytes of incomplete or corrupt records from the end of {:?}",
+          bytes.len() as u64 - valid_len,
+          path
+        );
+        try!(file.set_len(valid_len));
+      }
+    }
+
+    try!(file.seek(SeekFrom::End(0)));
+    Ok((Journal { file: file }, records))
+  }
+
+  /// Add a record to the end of the journal.
+  pub fn append(&mut self, record: &[u8]) -> io::Result<()> {
+    self.append_all(&[record])
+  }
+
+  /// Add some records to the end of the journal, syncing once for all of them.
+  pub fn append_all(&mut self, records: &[&[u8]]) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    for record in records.iter() {
+      push_u32(&mut bytes, record.len() as u32);
+      push_u32(&mut bytes, checksum(record));
+      bytes.extend(record.iter().cloned());
+    }
+    // One write, so a record is never interleaved with anything.
+    try!(self.file.write_all(&bytes));
+    self.file.sync_data()
+  }
+
+  /// Throw away every record, once they've all been written out somewhere more permanent.
+  pub fn clear(&mut self) -> io::Result<()> {
+    try!(self.file.set_len(HEADER_LEN));
+    try!(self.file.seek(SeekFrom::End(0)));
+    self.file.sync_all()
+  }
+}
+
+fn read_record(cursor: &mut Bytes) -> Option<Vec<u8>> {
+  let len = match cursor.u32() { Ok(len) => len, Err(_) => return None };
+  let sum = match cursor.u32() { Ok(sum) => sum, Err(_) => return None };
+  let record = match cursor.take(len as usize) { Ok(record) => record, Err(_) => return None };
+  if checksum(record) == sum {
+    Some(record.to_vec())
+  } else {
+    None
+  }
+}
+
+/// 32-bit FNV-1a.
+fn checksum(bytes: &[u8]) -> u32 {
+  let mut hash: u32 = 0x811c9dc5;
+  for &b in bytes.iter() {
+    hash = hash ^ (b as u32);
+    hash = hash.wrapping_mul(0x01000193);
+  }
+  hash
+}
+
+#[test]
+fn torn_tail_is_dropped() {
+  use std::env;
+  use std::fs;
+
+  let path = env::temp_dir().join("playform-torn-tail-is-dropped.journal");
+  let _ = fs::remove_file(&path);
+
+  {
+    let (mut journal, records) = Journal::open(&path).unwrap();
+    assert!(records.is_empty());
+    journal.append(b"first").unwrap();
+    journal.append(b"second").unwrap();
+  }
+
+  {
+    // Tear the last record.
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 2).unwrap();
+
+    let (mut journal, records) = Journal::open(&path).unwrap();
+    assert_eq!(records, vec!(b"first".to_vec()));
+    journal.append(b"third").unwrap();
+  }
+
+  {
+    let (mut journal, records) = Journal::open(&path).unwrap();
+    assert_eq!(records, vec!(b"first".to_vec(), b"third".to_vec()));
+    journal.clear().unwrap();
+  }
+
+  let (_, records) = Journal::open(&path).unwrap();
+  assert!(records.is_empty());
+}
@@ -0,0 +1,54 @@
+//! Rules for what generated terrain is made of.
+
+use common::material::Material;
+
+use biome::{Biome, Nearby};
+
+/// Roughly how many world units each unit of density is below the surface.
+const DEPTH_PER_DENSITY: f32 = 64.0;
+/// How deep the grass goes.
+const GRASS_DEPTH: f32 = 1.5;
+/// How deep the dirt goes.
+const DIRT_DEPTH: f32 = 6.0;
+/// Near-surface terrain below this height is sand.
+const SAND_HEIGHT: f32 = -16.0;
+
+/// The generated material at a point in a biome, based on the density of the field there.
+pub fn material_at(biome: &Biome, density: f32, y: f32) -> Material {
+  if density < 0.0 {
+    return Material::Empty
+  }
+
+  let depth = density * DEPTH_PER_DENSITY;
+  if depth > DIRT_DEPTH {
+    Material::Stone
+  } else if y < SAND_HEIGHT {
+    Material::Sand
+  } else if depth < GRASS_DEPTH {
+    biome.surface
+  } else {
+    biome.subsurface
+  }
+}
+
+/// The generated material at a point in a field.
+pub fn generate(field: &Nearby, x: f32, y: f32, z: f32) -> Material {
+  let biomes = field.biomes;
+  let (tree, material) = field.trees_at(x, y, z);
+  if tree >= 0.0 {
+    return material
+  }
+  // Cave walls are made of whatever the ground around them is, so layering goes by the
+  // density before caves are carved out.
+  if field.caves_at(x, y, z) < 0.0 {
+    return Material::Empty
+  }
+  let blend = biomes.blend_at(x, z);
+  let density = biomes.density_in(&blend, x, y, z);
+  let depth = density * DEPTH_PER_DENSITY;
+  if depth > DIRT_DEPTH {
+    biomes.rock_at(x, y, z, depth)
+  } else {
+    material_at(blend.dominant, density, y)
+  }
+}
@@ -10,38 +10,68 @@ extern crate noise;
 extern crate num;
 extern crate stopwatch;
 
+pub mod biome;
+pub mod block_cache;
+mod cache;
+pub mod caves;
+pub mod density;
+mod edits;
 mod generate;
-mod heightmap;
-mod raycast;
+mod history;
+pub mod journal;
+mod materials;
+mod pager;
+mod qef;
+pub mod raycast;
+mod rng;
+pub mod strata;
+pub mod tree_placer;
 
 pub mod voxel;
 pub mod voxel_tree;
+pub mod world;
 
 pub use noise::Seed;
 
+use cgmath::{Point, Point3, Vector, Vector3};
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
+use std::io;
 use std::iter::range_inclusive;
 use std::sync::Mutex;
 use stopwatch::TimerSet;
 
 use common::block_position::BlockPosition;
+use common::brush::{Brush, Operation};
 use common::entity::EntityId;
 use common::id_allocator::IdAllocator;
 use common::lod::LODIndex;
+use common::material::Material;
 use common::terrain_block;
 use common::terrain_block::TerrainBlock;
 
-use heightmap::HeightMap;
-use voxel::{Voxel, Fracu8, Fraci8};
+use biome::Biomes;
+use block_cache::BlockCache;
+use caves::Caves;
+use density::Density;
+use edits::Edits;
+use history::History;
+use pager::Pager;
+use strata::Strata;
+use tree_placer::TreePlacer;
 use voxel_tree::VoxelTree;
+use world::World;
 
 pub const AMPLITUDE: f64 = 64.0;
+pub const WIDTH: u32 = 1 << 11;
 pub const FREQUENCY: f64 = 1.0 / 64.0;
 pub const PERSISTENCE: f64 = 1.0 / 16.0;
 pub const LACUNARITY: f64 = 8.0;
 pub const OCTAVES: usize = 2;
 
+/// How many page-sized regions of voxels to keep in memory before paging the coldest ones out.
+pub const MAX_RESIDENT_REGIONS: usize = 1 << 12;
+
 pub struct MipMesh {
   pub lods: Vec<Option<TerrainBlock>>,
 }
@@ -79,18 +109,121 @@ impl MipMeshMap {
 
 /// This struct contains and lazily generates the world's terrain.
 pub struct Terrain {
-  pub heightmap: HeightMap,
+  // The world's parameters.
+  pub params: world::Params,
+  // What the terrain is generated from.
+  pub biomes: Biomes,
+  // Changes made to the generated field.
+  pub edits: Edits,
+  // What each player has changed, so they can undo it.
+  pub history: History,
   // all the blocks that have ever been created.
   pub all_blocks: MipMeshMap,
   pub voxels: VoxelTree,
+  // Blocks generated ahead of time, if there's a world to keep them in.
+  pub block_cache: Option<BlockCache>,
+}
+
+/// The samples of the field a voxel touches, at the resolution of the most detailed voxels, or
+/// `None` if the voxel is smaller than that.
+fn voxel_samples(bounds: &voxel::Bounds) -> Option<Vec<Point3<i32>>> {
+  let lg_step = terrain_block::LG_SAMPLE_SIZE[0];
+  if bounds.lg_size < lg_step {
+    return None
+  }
+  let low = edits::corner(bounds, 0, 0, 0).unwrap();
+  let steps = 1 << (bounds.lg_size - lg_step);
+  let mut samples = Vec::new();
+  for dx in range_inclusive(0, steps) {
+  for dy in range_inclusive(0, steps) {
+  for dz in range_inclusive(0, steps) {
+    let d = Vector3::new(dx << lg_step, dy << lg_step, dz << lg_step);
+    samples.push(low.add_v(&d));
+  }}}
+  Some(samples)
 }
 
 impl Terrain {
-  pub fn new(terrain_seed: Seed) -> Terrain {
+  /// Generate terrain from `params`, keeping edits in `world` if there is one.
+  pub fn new(params: &world::Params, world: Option<World>) -> Terrain {
+    let voxels =
+      match world.as_ref().map(|world| Pager::new(world.path().join("pages"))) {
+        None => VoxelTree::new(),
+        Some(Ok(pager)) => VoxelTree::with_pager(pager),
+        Some(Err(e)) => {
+          warn!("Couldn't make a directory to page voxels out to: {}", e);
+          VoxelTree::new()
+        },
+      };
+
+    let block_cache =
+      match world.as_ref().map(|world| BlockCache::open(&world.path().join("blocks"))) {
+        None => None,
+        Some(Ok(cache)) => Some(cache),
+        Some(Err(e)) => {
+          warn!("Couldn't open the block cache: {}", e);
+          None
+        },
+      };
+
+    let mut edits = Edits::new(world);
+
+    // Edits replayed from the journal never made it to the cached blocks around them.
+    if let Some(ref cache) = block_cache {
+      for p in edits.take_replayed().iter() {
+        let block = Point3::new(
+          p.x >> terrain_block::LG_WIDTH,
+          p.y >> terrain_block::LG_WIDTH,
+          p.z >> terrain_block::LG_WIDTH,
+        );
+        // Voxels are at most a block wide, and blocks use the voxels just past their edges.
+        for x in range_inclusive(block.x - 2, block.x + 2) {
+        for y in range_inclusive(block.y - 2, block.y + 2) {
+        for z in range_inclusive(block.z - 2, block.z + 2) {
+          for lod in 0..terrain_block::LG_SAMPLE_SIZE.len() {
+            cache.remove(&BlockPosition::new(x, y, z), LODIndex(lod as u32));
+          }
+        }}}
+      }
+    }
+
     Terrain {
-      heightmap: HeightMap::new(terrain_seed, OCTAVES, FREQUENCY, PERSISTENCE, LACUNARITY),
+      params: params.clone(),
+      biomes: {
+        let density =
+          match Density::parse(&params.density, params) {
+            Ok(density) => density,
+            Err(e) => panic!("Invalid density function {:?}: {}", params.density, e),
+          };
+        let biomes =
+          if params.biomes {
+            Biomes::new(density, params.seed)
+          } else {
+            Biomes::none(density)
+          };
+        let biomes =
+          if params.caves {
+            biomes.with_caves(Caves::new(params.seed))
+          } else {
+            biomes
+          };
+        let biomes =
+          if params.trees {
+            biomes.with_trees(TreePlacer::new(params.seed))
+          } else {
+            biomes
+          };
+        if params.strata {
+          biomes.with_strata(Strata::new(params.seed, &params.ores))
+        } else {
+          biomes
+        }
+      },
+      edits: edits,
+      history: History::new(),
       all_blocks: MipMeshMap::new(),
-      voxels: VoxelTree::new(),
+      voxels: voxels,
+      block_cache: block_cache,
     }
   }
 
@@ -103,144 +236,481 @@ impl Terrain {
     lod_index: LODIndex,
   ) -> &'a TerrainBlock
   {
+    let generated = self.all_blocks.get_mut(position).get_mut(lod_index.0 as usize).is_some();
+    if !generated {
+      self.load_edits_near(position);
+    }
+
     let mip_mesh = self.all_blocks.get_mut(position);
     let mesh = mip_mesh.get_mut(lod_index.0 as usize);
     if mesh.is_none() {
+      let cached =
+        match self.block_cache {
+          None => None,
+          Some(ref cache) => cache.load(id_allocator, position, lod_index, &mut self.voxels),
+        };
       *mesh = Some(
-        generate::generate_block(
-          timers,
-          id_allocator,
-          &self.heightmap,
-          &mut self.voxels,
-          position,
-          lod_index,
-        )
+        match cached {
+          Some(block) => block,
+          None =>
+            generate::generate_block(
+              timers,
+              id_allocator,
+              &self.biomes,
+              &self.edits,
+              &mut self.voxels,
+              position,
+              lod_index,
+            ),
+        }
       );
+      self.voxels.page_out_cold(MAX_RESIDENT_REGIONS);
     }
     mesh.as_ref().unwrap()
   }
 
+  /// Generate a block and save it to the block cache, unless it's already there.
+  /// The block isn't kept in memory. Returns whether it had to be generated.
+  pub fn pregenerate(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    position: &BlockPosition,
+    lod_index: LODIndex,
+  ) -> io::Result<bool> {
+    match self.block_cache {
+      None => return Err(io::Error::new(io::ErrorKind::Other, "there's no block cache")),
+      Some(ref cache) => {
+        if cache.contains(position, lod_index) {
+          return Ok(false)
+        }
+      },
+    }
+
+    self.load(timers, id_allocator, position, lod_index);
+    let block = self.all_blocks.get_mut(position).get_mut(lod_index.0 as usize).take().unwrap();
+    try!(self.block_cache.as_ref().unwrap().save(position, lod_index, &block, &mut self.voxels));
+    Ok(true)
+  }
+
+  /// Write any unsaved edits to the world on disk.
+  pub fn save(&mut self) {
+    self.edits.save();
+  }
+
+  /// Make sure the edits that any voxel in or next to a block could use are loaded.
+  fn load_edits_near(&mut self, position: &BlockPosition) {
+    let p = position.as_pnt();
+    let w = terrain_block::WIDTH;
+    self.edits.load(
+      &Point3::new((p.x - 1) * w, (p.y - 1) * w, (p.z - 1) * w),
+      &Point3::new((p.x + 2) * w, (p.y + 2) * w, (p.z + 2) * w),
+    );
+  }
+
+  /// Make a voxel empty, and return what it was made of.
+  /// `block_changed` is called with the old version of each affected block (if there was one)
+  /// and the new version.
   pub fn remove_voxel<F>(
     &mut self,
     timers: &TimerSet,
     id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
     bounds: &voxel::Bounds,
-    mut block_changed: F,
+    block_changed: F,
+  ) -> Material
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    debug!("{:?} remove {:?}", player, bounds);
+    let material = self.voxel_material(bounds);
+    self.set_voxel(timers, id_allocator, player, bounds, Material::Empty, block_changed);
+    material
+  }
+
+  /// Fill a voxel with some material.
+  /// `block_changed` is called the same way as in `remove_voxel`.
+  pub fn add_voxel<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    bounds: &voxel::Bounds,
+    material: Material,
+    block_changed: F,
   )
-    where F: FnMut(&TerrainBlock, &BlockPosition, LODIndex),
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
   {
-   debug!("remove {:?}", bounds);
+    debug!("{:?} add {:?} {:?}", player, material, bounds);
+    self.set_voxel(timers, id_allocator, player, bounds, material, block_changed);
+  }
 
-    match self.voxels.get_mut(bounds) {
-      None => {
-        return;
-      },
-      Some(voxel) => {
-        *voxel = Voxel::Volume(false);
-      },
-    }
+  fn set_voxel<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    bounds: &voxel::Bounds,
+    material: Material,
+    block_changed: F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    // Fill in every sample the voxel touches, so the edit shows up at every level of detail.
+    let samples =
+      match voxel_samples(bounds) {
+        None => {
+          warn!("Can't edit voxel {:?} smaller than the edit resolution", bounds);
+          return;
+        },
+        Some(samples) => samples.into_iter().map(|p| (p, Some(material))).collect(),
+      };
 
-    // Ensure all the neighbors are populated.
-    // TODO: Search for all these voxels in a single tree search.
-    for &dx in [-1, 0, 1].iter() {
-    for &dy in [-1, 0, 1].iter() {
-    for &dz in [-1, 0, 1].iter() {
-      let (x, y, z) = (bounds.x + dx, bounds.y + dy, bounds.z + dz);
-      debug!("adjacent {:?} {:?} {:?}", x, y, z);
-
-      let bounds = voxel::Bounds::new(x, y, z, bounds.lg_size);
-      let voxel = self.voxels.get_mut_or_create(&bounds);
-      debug!("voxel {:?}", *voxel);
-
-      match voxel {
-        &mut voxel_tree::TreeBody::Leaf(_) => {},
-        _ => {
-          *voxel =
-            voxel_tree::TreeBody::Leaf(generate::generate_voxel(
-              timers,
-              &self.heightmap,
-              &bounds,
-            ));
+    self.edit_as(timers, id_allocator, player, samples, block_changed);
+  }
 
-          debug!("voxel changing to {:?}", *voxel);
-        },
+  /// What a voxel is made of: the most common solid material among the samples it touches,
+  /// or `Empty` if none of them are solid.
+  fn voxel_material(&mut self, bounds: &voxel::Bounds) -> Material {
+    let samples =
+      match voxel_samples(bounds) {
+        None => return Material::Empty,
+        Some(samples) => samples,
+      };
+    self.edits.load(samples.first().unwrap(), samples.last().unwrap());
+
+    let mut counts = HashMap::new();
+    for p in samples.iter() {
+      let material = self.field_material(p);
+      if material.is_solid() {
+        *counts.entry(material).or_insert(0) += 1;
       }
-      match voxel {
-        &mut voxel_tree::TreeBody::Leaf(Voxel::Volume(true)) => {
-          let half = Fracu8::of(0x80);
-          let surface =
-            voxel::SurfaceVoxel {
-              inner_vertex: voxel::Vertex { x: half, y: half, z: half },
-              // TODO: Make real normals.
-              normal: voxel::Normal { x: Fraci8::of(0), y: Fraci8::of(0x7F), z: Fraci8::of(0) },
-              corner_inside_surface: true,
-            };
-          *voxel = voxel_tree::TreeBody::Leaf(Voxel::Surface(surface));
-
-          debug!("voxel detailing to {:?}", *voxel);
-        },
-        _ => {},
+    }
+    counts.into_iter()
+      .max_by(|&(material, count)| (count, material as u8))
+      .map_or(Material::Empty, |(material, _)| material)
+  }
+
+  /// Apply a brush centered at `center`, editing every sample of the field inside its shape.
+  /// Brushes whose shapes aren't `Shape::is_valid` are ignored.
+  /// `block_changed` is called the same way as in `remove_voxel`.
+  pub fn apply_brush<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    brush: &Brush,
+    center: &Point3<f32>,
+    block_changed: F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    debug!("{:?} apply {:?} at {:?}", player, brush, center);
+
+    // The edit's cost grows with the shape's volume, so don't trust it to be reasonable.
+    if !brush.shape.is_valid() {
+      warn!("Ignoring {:?}'s brush, which is malformed or too big: {:?}", player, brush);
+      return
+    }
+
+    // Edit at the resolution of the most detailed voxels.
+    let lg_size = terrain_block::LG_SAMPLE_SIZE[0];
+    let spacing = 1 << lg_size;
+
+    let bounds = brush.shape.bounds(center);
+    let low =
+      Point3::new(
+        (bounds.min.x.floor() as i32) >> lg_size,
+        (bounds.min.y.floor() as i32) >> lg_size,
+        (bounds.min.z.floor() as i32) >> lg_size,
+      );
+    let high =
+      Point3::new(
+        (bounds.max.x.ceil() as i32) >> lg_size,
+        (bounds.max.y.ceil() as i32) >> lg_size,
+        (bounds.max.z.ceil() as i32) >> lg_size,
+      );
+
+    self.edits.load(
+      &Point3::new((low.x - 1) << lg_size, (low.y - 1) << lg_size, (low.z - 1) << lg_size),
+      &Point3::new((high.x + 1) << lg_size, (high.y + 1) << lg_size, (high.z + 1) << lg_size),
+    );
+
+    // Work out all the new samples before changing any of them, so smoothing sees the old field.
+    let mut samples = Vec::new();
+    for x in range_inclusive(low.x, high.x) {
+    for y in range_inclusive(low.y, high.y) {
+    for z in range_inclusive(low.z, high.z) {
+      let p = Point3::new(x << lg_size, y << lg_size, z << lg_size);
+      let offset = Vector3::new(p.x as f32, p.y as f32, p.z as f32).sub_v(&center.to_vec());
+      if !brush.shape.contains(&offset) {
+        continue
       }
+
+      let material =
+        match brush.operation {
+          Operation::Add => brush.material,
+          Operation::Subtract => Material::Empty,
+          Operation::Smooth => {
+            // Go with the majority of the neighborhood.
+            let current = self.field_material(&p);
+            let mut count = 0;
+            let mut neighbor_material = Material::Empty;
+            for &dx in [-spacing, 0, spacing].iter() {
+            for &dy in [-spacing, 0, spacing].iter() {
+            for &dz in [-spacing, 0, spacing].iter() {
+              let material = self.field_material(&p.add_v(&Vector3::new(dx, dy, dz)));
+              if material.is_solid() {
+                count += 1;
+                neighbor_material = material;
+              }
+            }}}
+            if count <= 13 {
+              Material::Empty
+            } else if current.is_solid() {
+              current
+            } else {
+              neighbor_material
+            }
+          },
+        };
+      samples.push((p, Some(material)));
     }}}
 
-    // Remove all the corners.
-    // TODO: Search for all these voxels in a single tree search.
-    for &dx in [0, 1].iter() {
-    for &dy in [0, 1].iter() {
-    for &dz in [0, 1].iter() {
-      let (x, y, z) = (bounds.x + dx, bounds.y + dy, bounds.z + dz);
-      debug!("corner {:?} {:?} {:?}", x, y, z);
-
-      let bounds = voxel::Bounds::new(x, y, z, bounds.lg_size);
-      let voxel = self.voxels.get_mut(&bounds);
-
-      debug!("voxel {:?}", voxel.as_ref().map(|&&mut v| v));
-      match voxel {
-        Some(&mut Voxel::Surface(ref mut voxel)) => {
-          voxel.corner_inside_surface = false;
-        },
-        _ => {},
+    self.edit_as(timers, id_allocator, player, samples, block_changed);
+  }
+
+  /// Undo `player`'s latest edit, if there is one.
+  /// `block_changed` is called the same way as in `remove_voxel`.
+  pub fn undo<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    block_changed: F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    match self.history.undo(player) {
+      None => debug!("{:?} has nothing to undo", player),
+      Some(samples) => {
+        self.edit_field(timers, id_allocator, &samples, block_changed);
+      },
+    }
+  }
+
+  /// Redo `player`'s latest undone edit, if there is one.
+  /// `block_changed` is called the same way as in `remove_voxel`.
+  pub fn redo<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    block_changed: F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    match self.history.redo(player) {
+      None => debug!("{:?} has nothing to redo", player),
+      Some(samples) => {
+        self.edit_field(timers, id_allocator, &samples, block_changed);
+      },
+    }
+  }
+
+  /// Edit the field on behalf of `player`, and remember how to undo it.
+  fn edit_as<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    player: EntityId,
+    samples: history::Samples,
+    block_changed: F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    let before = self.edit_field(timers, id_allocator, &samples, block_changed);
+    self.history.push(player, history::Change {
+      before: before,
+      after: samples,
+    });
+  }
+
+  /// The range of heights the unedited surface, and anything growing on it, stays within (as
+  /// long as the density function, like the default one, stays within the amplitude).
+  pub fn surface_range(&self) -> (f32, f32) {
+    let amplitude = self.params.amplitude as f32;
+    let (low, high) =
+      if self.params.biomes {
+        biome::surface_range(amplitude)
+      } else {
+        (-amplitude, amplitude)
+      };
+    let trees = if self.params.trees { tree_placer::MAX_HEIGHT } else { 0.0 };
+    (low, high + trees)
+  }
+
+  /// The highest solid point in the column at (x, z), searching down from `high_y` to `low_y`,
+  /// along with what it's made of. Edits are taken into account.
+  pub fn surface(&mut self, x: i32, z: i32, low_y: i32, high_y: i32) -> Option<(i32, Material)> {
+    self.edits.load(&Point3::new(x, low_y, z), &Point3::new(x, high_y, z));
+    let field =
+      self.biomes.near(
+        &Point3::new(x as f32, low_y as f32, z as f32),
+        &Point3::new(x as f32, high_y as f32, z as f32),
+      );
+    let mut y = high_y;
+    while y >= low_y {
+      let p = Point3::new(x, y, z);
+      let material =
+        match self.edits.get(&p) {
+          Some(material) => material,
+          None => materials::generate(&field, x as f32, y as f32, z as f32),
+        };
+      if material.is_solid() {
+        return Some((y, material))
       }
-    }}}
+      y -= 1;
+    }
+    None
+  }
 
-    // TODO: Consider regenerating the TerrainBlocks for the adjacent voxels too.
+  /// The height just above the top of the column at (x, z), for putting things on the ground.
+  pub fn ground_height(&mut self, x: i32, z: i32) -> f32 {
+    let (low, high) = self.surface_range();
+    match self.surface(x, z, low.floor() as i32, high.ceil() as i32) {
+      Some((y, _)) => y as f32 + 1.0,
+      None => high,
+    }
+  }
+
+  /// The material at a point (in world coordinates), taking edits into account.
+  fn field_material(&self, p: &Point3<i32>) -> Material {
+    match self.edits.get(p) {
+      Some(material) => material,
+      None => {
+        let p = Point3::new(p.x as f32, p.y as f32, p.z as f32);
+        materials::generate(&self.biomes.near(&p, &p), p.x, p.y, p.z)
+      },
+    }
+  }
+
+  /// Set samples of the density field (in world coordinates), then regenerate the voxels
+  /// that touch them and any already-generated blocks made of those voxels, at every LOD.
+  /// Returns the samples' previous edits.
+  /// Every edit goes through here, so this is also where edits are journaled.
+  fn edit_field<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    samples: &[(Point3<i32>, Option<Material>)],
+    mut block_changed: F,
+  ) -> history::Samples
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    // Write ahead, so the edit survives a crash before the next save.
+    self.edits.journal(samples);
+
+    let mut before = Vec::with_capacity(samples.len());
+    for &(sample, material) in samples.iter() {
+      before.push((sample, self.edits.set(sample, material)));
+    }
+
+    // The voxel tree only holds one size of voxel in any given place, so go from coarsest to
+    // finest; that way, the voxels left in the tree around the edit are the most detailed ones.
+    for lod in (0 .. terrain_block::LG_SAMPLE_SIZE.len()).rev() {
+      let lod_index = LODIndex(lod as u32);
+      let lg_size = terrain_block::LG_SAMPLE_SIZE[lod];
+      self.edit_lod(timers, id_allocator, lg_size, lod_index, samples, &mut block_changed);
+    }
+
+    before
+  }
+
+  /// Regenerate the voxels of size `2^lg_size` that have one of `samples` as a corner,
+  /// and the blocks at `lod_index` made of them.
+  fn edit_lod<F>(
+    &mut self,
+    timers: &TimerSet,
+    id_allocator: &Mutex<IdAllocator<EntityId>>,
+    lg_size: i16,
+    lod_index: LODIndex,
+    samples: &[(Point3<i32>, Option<Material>)],
+    block_changed: &mut F,
+  )
+    where F: FnMut(Option<&TerrainBlock>, &TerrainBlock, &BlockPosition, LODIndex),
+  {
+    // Each sample is seen by the corner of this LOD's voxels that it's nearest to.
+    let mut dirty_voxels = HashSet::new();
+    for &(sample, _) in samples.iter() {
+      let voxel = edits::cell_of(&sample, lg_size);
+      for &dx in [-1, 0].iter() {
+      for &dy in [-1, 0].iter() {
+      for &dz in [-1, 0].iter() {
+        dirty_voxels.insert(voxel.add_v(&Vector3::new(dx, dy, dz)));
+      }}}
+    }
+
+    if dirty_voxels.is_empty() {
+      return
+    }
+
+    // TODO: Search for all these voxels in a single tree search.
+    let half = Vector3::new(1, 1, 1).mul_s((1 << lg_size) >> 1);
+    for voxel in dirty_voxels.iter() {
+      let bounds = voxel::Bounds::new(voxel.x, voxel.y, voxel.z, lg_size);
+      // Corners see the edits up to half a voxel away.
+      self.edits.load(
+        &edits::corner(&bounds, 0, 0, 0).unwrap().sub_v(&half),
+        &edits::corner(&bounds, 1, 1, 1).unwrap().add_v(&half),
+      );
+      let voxel = generate::generate_voxel(timers, &self.biomes, &self.edits, &bounds);
+      debug!("voxel changing to {:?}", voxel);
+      *self.voxels.get_mut_or_create(&bounds) = voxel_tree::TreeBody::Leaf(voxel);
+    }
 
     // lg(number of voxels in a block)
-    let lg_scale = terrain_block::LG_WIDTH - bounds.lg_size;
+    let lg_scale = terrain_block::LG_WIDTH - lg_size;
 
+    // Blocks also use the voxels just past their edges, so a voxel can affect the blocks next to it.
     let mut positions = HashSet::new();
-    for &dx in [-1, 0, 1].iter() {
-    for &dy in [-1, 0, 1].iter() {
-    for &dz in [-1, 0, 1].iter() {
-      let (x, y, z) = (bounds.x + dx, bounds.y + dy, bounds.z + dz);
-      let bounds = voxel::Bounds::new(x, y, z, bounds.lg_size);
-      let position =
-        BlockPosition::new(bounds.x >> lg_scale, bounds.y >> lg_scale, bounds.z >> lg_scale);
-      positions.insert(position);
-    }}}
+    for voxel in dirty_voxels.iter() {
+      for &dx in [-1, 0, 1].iter() {
+      for &dy in [-1, 0, 1].iter() {
+      for &dz in [-1, 0, 1].iter() {
+        let (x, y, z) = (voxel.x + dx, voxel.y + dy, voxel.z + dz);
+        positions.insert(BlockPosition::new(x >> lg_scale, y >> lg_scale, z >> lg_scale));
+      }}}
+    }
 
     for position in positions.into_iter() {
-      let lod_index =
-        terrain_block::LG_SAMPLE_SIZE.iter()
-        .position(|&x| x == bounds.lg_size)
-        .unwrap()
-      ;
-      let lod_index = LODIndex(lod_index as u32);
-
-      let mip_mesh = self.all_blocks.get_mut(&position);
-      let mesh = mip_mesh.get_mut(lod_index.0 as usize);
+      if let Some(ref cache) = self.block_cache {
+        cache.remove(&position, lod_index);
+      }
+
+      let old_block = {
+        let mesh = self.all_blocks.get_mut(&position).get_mut(lod_index.0 as usize);
+        if mesh.is_none() {
+          // It'll see the edits whenever it does get generated.
+          continue;
+        }
+        mesh.take()
+      };
+
+      self.load_edits_near(&position);
+      let mesh = self.all_blocks.get_mut(&position).get_mut(lod_index.0 as usize);
       *mesh = Some(
         generate::generate_block(
           timers,
           id_allocator,
-          &self.heightmap,
+          &self.biomes,
+          &self.edits,
           &mut self.voxels,
           &position,
           lod_index,
         )
       );
 
-      block_changed(mesh.as_ref().unwrap(), &position, lod_index);
+      block_changed(old_block.as_ref(), mesh.as_ref().unwrap(), &position, lod_index);
     }
   }
 }
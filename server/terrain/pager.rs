@@ -0,0 +1,160 @@
+//! Files for holding `VoxelTree` subtrees that have been paged out of memory.
+//! Voxels can always be regenerated, so these only last as long as the process.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use common::material::Material;
+
+use voxel;
+use voxel::{Fraci8, Fracu8, Normal, SurfaceVoxel, Vertex, Voxel};
+use voxel_tree::{Branches, TreeBody};
+
+/// A directory of paged-out subtrees.
+#[derive(Debug)]
+pub struct Pager {
+  dir: PathBuf,
+}
+
+impl Pager {
+  /// Page subtrees out to `dir`, which is emptied first.
+  pub fn new(dir: PathBuf) -> io::Result<Pager> {
+    match fs::remove_dir_all(&dir) {
+      Ok(()) => {},
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+      Err(e) => return Err(e),
+    }
+    try!(fs::create_dir_all(&dir));
+    Ok(Pager {
+      dir: dir,
+    })
+  }
+
+  fn path(&self, bounds: &voxel::Bounds) -> PathBuf {
+    self.dir.join(format!("{}_{}_{}_{}.voxels", bounds.x, bounds.y, bounds.z, bounds.lg_size))
+  }
+
+  /// Write out the subtree at `bounds`.
+  pub fn page_out(&self, bounds: &voxel::Bounds, body: &TreeBody) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    write_body(&mut bytes, body);
+    let mut file = try!(File::create(&self.path(bounds)));
+    file.write_all(&bytes)
+  }
+
+  /// Read back, and forget, the subtree at `bounds`.
+  pub fn page_in(&self, bounds: &voxel::Bounds) -> io::Result<TreeBody> {
+    let path = self.path(bounds);
+    let mut bytes = Vec::new();
+    {
+      let mut file = try!(File::open(&path));
+      try!(file.read_to_end(&mut bytes));
+    }
+    try!(fs::remove_file(&path));
+
+    let mut bytes = bytes.iter().cloned();
+    match read_body(&mut bytes) {
+      Some(body) => Ok(body),
+      None => Err(io::Error::new(io::ErrorKind::Other, "corrupt voxel page")),
+    }
+  }
+}
+
+fn write_body(bytes: &mut Vec<u8>, body: &TreeBody) {
+  match *body {
+    TreeBody::Empty => bytes.push(0),
+    TreeBody::Leaf(ref voxel) => {
+      bytes.push(1);
+      write_voxel(bytes, voxel);
+    },
+    TreeBody::Branch(ref branches) => {
+      bytes.push(2);
+      for x in 0..2 {
+      for y in 0..2 {
+      for z in 0..2 {
+        write_body(bytes, branches.get(x, y, z));
+      }}}
+    },
+    TreeBody::PagedOut => {
+      // Only subtrees of one size are paged out, so they never nest.
+      panic!("Tried to page out a subtree that's already paged out");
+    },
+  }
+}
+
+/// Append a voxel's binary encoding to `bytes`.
+pub fn write_voxel(bytes: &mut Vec<u8>, voxel: &Voxel) {
+  match *voxel {
+    Voxel::Volume(material) => {
+      bytes.push(0);
+      bytes.push(material as u8);
+    },
+    Voxel::Surface(ref surface) => {
+      bytes.push(1);
+      bytes.push(surface.inner_vertex.x.numerator);
+      bytes.push(surface.inner_vertex.y.numerator);
+      bytes.push(surface.inner_vertex.z.numerator);
+      bytes.push(surface.normal.x.numerator as u8);
+      bytes.push(surface.normal.y.numerator as u8);
+      bytes.push(surface.normal.z.numerator as u8);
+      bytes.push(surface.corner_inside_surface as u8);
+      bytes.push(surface.material as u8);
+    },
+  }
+}
+
+fn read_body<I: Iterator<Item=u8>>(bytes: &mut I) -> Option<TreeBody> {
+  match bytes.next() {
+    Some(0) => Some(TreeBody::Empty),
+    Some(1) => read_voxel(bytes).map(TreeBody::Leaf),
+    Some(2) => {
+      let mut branches = Branches::empty();
+      // Same order as `write_body`.
+      for x in 0..2 {
+      for y in 0..2 {
+      for z in 0..2 {
+        match read_body(bytes) {
+          None => return None,
+          Some(body) => *branches.get_mut(x, y, z) = body,
+        }
+      }}}
+      Some(TreeBody::Branch(Box::new(branches)))
+    },
+    _ => None,
+  }
+}
+
+/// Read a voxel written by `write_voxel`; `None` if it's malformed.
+pub fn read_voxel<I: Iterator<Item=u8>>(bytes: &mut I) -> Option<Voxel> {
+  macro_rules! byte(() => { match bytes.next() { None => return None, Some(b) => b } });
+  match byte!() {
+    0 => Material::from_u8(byte!()).map(Voxel::Volume),
+    1 => {
+      let vertex =
+        Vertex {
+          x: Fracu8::of(byte!()),
+          y: Fracu8::of(byte!()),
+          z: Fracu8::of(byte!()),
+        };
+      let normal =
+        Normal {
+          x: Fraci8::of(byte!() as i8),
+          y: Fraci8::of(byte!() as i8),
+          z: Fraci8::of(byte!() as i8),
+        };
+      let corner_inside_surface = byte!() != 0;
+      Material::from_u8(byte!()).map(|material| {
+        Voxel::Surface(SurfaceVoxel {
+          inner_vertex: vertex,
+          normal: normal,
+          corner_inside_surface: corner_inside_surface,
+          material: material,
+        })
+      })
+    },
+    _ => None,
+  }
+}
@@ -0,0 +1,145 @@
+//! Quadratic error functions, which dual contouring uses to place each surface voxel's vertex.
+//!
+//! Everywhere the surface crosses an edge of a voxel gives a point on the surface and the normal
+//! there, i.e. a plane the surface (locally) lies in. The vertex goes wherever the sum of squared
+//! distances to those planes is smallest, so where planes meet at a ridge or a corner, the vertex
+//! lands right on it. Directions the planes don't pin down (e.g. along a ridge, or everywhere
+//! parallel to a flat surface) are left at the average of the points.
+
+use cgmath::{EuclideanVector, Point, Point3, Vector, Vector3};
+
+/// Eigenvalues this much smaller than the largest one are treated as zero.
+const SINGULAR_TOLERANCE: f32 = 0.1;
+/// How many rounds of Jacobi rotations to diagonalize with. 3x3 matrices converge very quickly.
+const JACOBI_SWEEPS: usize = 6;
+
+/// The planes a vertex should be close to.
+pub struct Qef {
+  // The normal equations AᵀAx = Aᵀb, where each row of A is a plane's normal n,
+  // and the corresponding entry of b is n·p for a point p on the plane.
+  ata: [[f32; 3]; 3],
+  atb: [f32; 3],
+  point_sum: Vector3<f32>,
+  points: u32,
+}
+
+impl Qef {
+  pub fn new() -> Qef {
+    Qef {
+      ata: [[0.0; 3]; 3],
+      atb: [0.0; 3],
+      point_sum: Vector3::new(0.0, 0.0, 0.0),
+      points: 0,
+    }
+  }
+
+  /// Add the plane through `point` with normal `normal`. Normals that aren't finite (e.g. from
+  /// where the field is flat) only count toward the average point.
+  pub fn add(&mut self, point: &Point3<f32>, normal: &Vector3<f32>) {
+    self.point_sum.add_self_v(&point.to_vec());
+    self.points += 1;
+
+    if !(normal.x.is_finite() && normal.y.is_finite() && normal.z.is_finite()) {
+      return
+    }
+    let normal = normal.normalize();
+    let n = [normal.x, normal.y, normal.z];
+    let d = normal.dot(&point.to_vec());
+    for i in 0..3 {
+      for j in 0..3 {
+        self.ata[i][j] = self.ata[i][j] + n[i] * n[j];
+      }
+      self.atb[i] = self.atb[i] + n[i] * d;
+    }
+  }
+
+  /// The average of the points added.
+  pub fn mass_point(&self) -> Point3<f32> {
+    Point3::from_vec(&self.point_sum.div_s(self.points as f32))
+  }
+
+  /// The point closest to all the planes; the least-squares solution nearest the mass point.
+  pub fn solve(&self) -> Point3<f32> {
+    let mass_point = self.mass_point();
+    let c = [mass_point.x, mass_point.y, mass_point.z];
+
+    // Solve for the offset from the mass point, so whatever the planes don't constrain stays put.
+    let mut r = [0.0; 3];
+    for i in 0..3 {
+      r[i] = self.atb[i] - (self.ata[i][0] * c[0] + self.ata[i][1] * c[1] + self.ata[i][2] * c[2]);
+    }
+
+    // Apply the pseudo-inverse of AᵀA, dropping the directions it barely scales.
+    let (values, vectors) = eigen(self.ata);
+    let largest = values.iter().fold(0.0, |acc: f32, v| acc.max(v.abs()));
+    let mut offset = [0.0; 3];
+    for k in 0..3 {
+      if values[k].abs() <= SINGULAR_TOLERANCE * largest {
+        continue
+      }
+      let s = (vectors[0][k] * r[0] + vectors[1][k] * r[1] + vectors[2][k] * r[2]) / values[k];
+      for i in 0..3 {
+        offset[i] = offset[i] + vectors[i][k] * s;
+      }
+    }
+
+    mass_point.add_v(&Vector3::new(offset[0], offset[1], offset[2]))
+  }
+}
+
+/// The eigenvalues and eigenvectors (as columns) of a symmetric matrix, by Jacobi rotations.
+fn eigen(mut a: [[f32; 3]; 3]) -> ([f32; 3], [[f32; 3]; 3]) {
+  let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+  for _ in 0..JACOBI_SWEEPS {
+    for &(p, q) in [(0, 1), (0, 2), (1, 2)].iter() {
+      if a[p][q].abs() < 1e-12 {
+        continue
+      }
+
+      // Rotate in the p-q plane by whatever angle zeroes a[p][q].
+      let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+      let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+      let c = 1.0 / (t * t + 1.0).sqrt();
+      let s = t * c;
+
+      for k in 0..3 {
+        let (akp, akq) = (a[k][p], a[k][q]);
+        a[k][p] = c * akp - s * akq;
+        a[k][q] = s * akp + c * akq;
+      }
+      for k in 0..3 {
+        let (apk, aqk) = (a[p][k], a[q][k]);
+        a[p][k] = c * apk - s * aqk;
+        a[q][k] = s * apk + c * aqk;
+      }
+      for k in 0..3 {
+        let (vkp, vkq) = (v[k][p], v[k][q]);
+        v[k][p] = c * vkp - s * vkq;
+        v[k][q] = s * vkp + c * vkq;
+      }
+    }
+  }
+  ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[test]
+fn corners_and_planes() {
+  let close = |a: Point3<f32>, b: Point3<f32>| a.sub_p(&b).length() < 1e-4;
+
+  // Three faces of a box meet at a corner, which none of the points are on.
+  let mut qef = Qef::new();
+  qef.add(&Point3::new(0.3, 0.1, 0.9), &Vector3::new(1.0, 0.0, 0.0));
+  qef.add(&Point3::new(0.3, 0.8, 0.4), &Vector3::new(1.0, 0.0, 0.0));
+  qef.add(&Point3::new(0.0, 0.6, 0.5), &Vector3::new(0.0, 1.0, 0.0));
+  qef.add(&Point3::new(0.9, 0.6, 0.0), &Vector3::new(0.0, 1.0, 0.0));
+  qef.add(&Point3::new(1.0, 0.2, 0.2), &Vector3::new(0.0, 0.0, -1.0));
+  assert!(close(qef.solve(), Point3::new(0.3, 0.6, 0.2)));
+
+  // A tilted plane only pins the vertex down in one direction.
+  let normal = Vector3::new(1.0, 2.0, 0.0);
+  let mut qef = Qef::new();
+  qef.add(&Point3::new(0.4, 0.5, 0.0), &normal);
+  qef.add(&Point3::new(0.8, 0.3, 1.0), &normal);
+  qef.add(&Point3::new(0.0, 0.7, 0.5), &Vector3::new(::std::f32::NAN, 0.0, 0.0));
+  assert!(close(qef.solve(), Point3::new(0.4, 0.5, 0.5)));
+}
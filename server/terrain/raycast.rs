@@ -1,4 +1,6 @@
-use cgmath::Ray3;
+//! Walk a ray through a `VoxelTree`.
+
+use cgmath::{Point, Point3, Ray3, Vector, Vector3};
 use std::cmp::Ordering;
 
 use voxel;
@@ -18,6 +20,56 @@ impl Ord for TOI {
   }
 }
 
+/// A face of a voxel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Face {
+  LowX,
+  LowY,
+  LowZ,
+  HighX,
+  HighY,
+  HighZ,
+}
+
+impl Face {
+  fn from_side(side: usize) -> Face {
+    match side {
+      0 => Face::LowX,
+      1 => Face::LowY,
+      2 => Face::LowZ,
+      3 => Face::HighX,
+      4 => Face::HighY,
+      5 => Face::HighZ,
+      _ => panic!("Voxels don't have a side {}", side),
+    }
+  }
+
+  /// The unit vector pointing out of this face.
+  pub fn normal(&self) -> Vector3<i32> {
+    match *self {
+      Face::LowX  => Vector3::new(-1,  0,  0),
+      Face::LowY  => Vector3::new( 0, -1,  0),
+      Face::LowZ  => Vector3::new( 0,  0, -1),
+      Face::HighX => Vector3::new( 1,  0,  0),
+      Face::HighY => Vector3::new( 0,  1,  0),
+      Face::HighZ => Vector3::new( 0,  0,  1),
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Where a ray hit a voxel.
+pub struct Hit {
+  /// The voxel that was hit.
+  pub bounds: voxel::Bounds,
+  /// When the ray entered the voxel, in multiples of the ray's direction.
+  pub toi: f32,
+  /// Where the ray entered the voxel.
+  pub point: Point3<f32>,
+  /// The face the ray entered through, or `None` if the ray started inside the voxel.
+  pub face: Option<Face>,
+}
+
 #[derive(Debug, Copy, Clone)]
 /// Information about a ray entering a voxel.
 pub struct Entry {
@@ -56,6 +108,7 @@ pub struct Exit {
 pub fn cast_ray_branches<'a, MakeBounds, Act, R>(
   this: &'a Branches,
   ray: &Ray3<f32>,
+  max_toi: f32,
   mut entry: Option<Entry>,
   mut coords: [usize; 3],
   make_bounds: &mut MakeBounds,
@@ -63,15 +116,19 @@ pub fn cast_ray_branches<'a, MakeBounds, Act, R>(
 ) -> Result<R, Exit>
   where
     MakeBounds: FnMut([usize; 3]) -> voxel::Bounds,
-    Act: FnMut(voxel::Bounds, &'a Voxel) -> Option<R>,
+    Act: FnMut(&Hit, &'a Voxel) -> Option<R>,
 {
   loop {
     let child = this.get(coords[0], coords[1], coords[2]);
     let bounds = make_bounds(coords);
 
-    match cast_ray(child, ray, bounds, entry, act) {
+    match cast_ray(child, ray, max_toi, bounds, entry, act) {
       Ok(r) => return Ok(r),
       Err(exit) => {
+        if exit.toi.0 > max_toi {
+          // Anything else we'd hit is out of range.
+          return Err(exit)
+        }
         let dim = exit.side % 3;
         if ray.direction[dim] < 0.0 {
           if coords[dim] == 0 {
@@ -94,19 +151,29 @@ pub fn cast_ray_branches<'a, MakeBounds, Act, R>(
 pub fn cast_ray<'a, Act, R>(
   this: &'a TreeBody,
   ray: &Ray3<f32>,
+  max_toi: f32,
   bounds: voxel::Bounds,
   entry: Option<Entry>,
   act: &mut Act,
 ) -> Result<R, Exit>
   where
-    Act: FnMut(voxel::Bounds, &'a Voxel) -> Option<R>
+    Act: FnMut(&Hit, &'a Voxel) -> Option<R>
 {
   match this {
-    &TreeBody::Empty => {
+    &TreeBody::Empty | &TreeBody::PagedOut => {
       // We pass through empty voxels; fall through.
+      // `VoxelTree::cast_ray` pages in anything along the ray first, so we shouldn't see `PagedOut`.
     },
     &TreeBody::Leaf(ref leaf) => {
-      if let Some(r) = act(bounds, leaf) {
+      let toi = entry.map(|entry| entry.toi.0).unwrap_or(0.0);
+      let hit =
+        Hit {
+          bounds: bounds,
+          toi: toi,
+          point: ray.origin.add_v(&ray.direction.mul_s(toi)),
+          face: entry.map(|entry| Face::from_side(entry.side)),
+        };
+      if let Some(r) = act(&hit, leaf) {
         return Ok(r)
       }
 
@@ -146,6 +213,7 @@ pub fn cast_ray<'a, Act, R>(
       return cast_ray_branches(
         b,
         ray,
+        max_toi,
         entry,
         coords,
         &mut make_bounds,
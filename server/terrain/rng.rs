@@ -0,0 +1,34 @@
+//! A small, fast random number generator (splitmix64) for placing things in the world.
+//! It only needs to be deterministic: the same seed and position always give the same numbers.
+
+pub struct Rng(u64);
+
+impl Rng {
+  /// A generator for a position (in whatever units the caller likes) in a world with `seed`.
+  pub fn new(seed: u32, position: &[i32]) -> Rng {
+    let mut state = seed as u64;
+    for &c in position.iter() {
+      state = (state ^ c as u32 as u64).wrapping_mul(0x9E3779B97F4A7C15);
+      state = state ^ (state >> 29);
+    }
+    Rng(state)
+  }
+
+  pub fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+  }
+
+  /// A number in [0, 1).
+  pub fn float(&mut self) -> f32 {
+    (self.next() >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  /// A number in [low, high).
+  pub fn range(&mut self, low: f32, high: f32) -> f32 {
+    low + (high - low) * self.float()
+  }
+}
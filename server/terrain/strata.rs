@@ -0,0 +1,138 @@
+//! What the ground is made of below the dirt: layers of rock by depth, with veins of ore in them.
+//!
+//! Depth is measured down from the surface, so the layers follow the shape of the land, and the
+//! boundaries between them wobble with noise so they aren't perfectly even. Each ore has 3D noise
+//! of its own, and is wherever that noise is above the ore's rarity, deeper than the ore starts.
+//! Everything here depends only on the world position and the seed.
+
+use noise::{Seed, perlin2, perlin3};
+
+use common::material::Material;
+
+/// The rock layers, each with the depth it goes down to. Below the last is granite.
+const LAYERS: [(Material, f32); 2] = [
+  (Material::Stone, 32.0),
+  (Material::Slate, 96.0),
+];
+/// How far up or down the boundaries between layers can move.
+const LAYER_WOBBLE: f32 = 8.0;
+const LAYER_FREQUENCY: f64 = 1.0 / 64.0;
+
+/// How an ore is spread through the rock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vein {
+  /// Ore is wherever its noise is above this, so 0 is as common as it gets, and 1 is never.
+  pub rarity: f32,
+  /// Roughly how wide the veins are, in world units.
+  pub size: f32,
+}
+
+/// A kind of ore.
+pub struct Ore {
+  /// What it's called in the world header and the settings.
+  pub name: &'static str,
+  pub material: Material,
+  /// How far below the surface it starts showing up.
+  pub min_depth: f32,
+  /// How it's spread by default.
+  pub vein: Vein,
+}
+
+/// Every ore, from the most common to the rarest.
+pub static ORES: [Ore; 3] = [
+  Ore {
+    name: "coal",
+    material: Material::Coal,
+    min_depth: 8.0,
+    vein: Vein { rarity: 0.3, size: 6.0 },
+  },
+  Ore {
+    name: "iron",
+    material: Material::Iron,
+    min_depth: 24.0,
+    vein: Vein { rarity: 0.4, size: 4.0 },
+  },
+  Ore {
+    name: "gold",
+    material: Material::Gold,
+    min_depth: 64.0,
+    vein: Vein { rarity: 0.5, size: 3.0 },
+  },
+];
+
+/// The default veins of every ore, in the same order as `ORES`.
+pub fn default_veins() -> [Vein; 3] {
+  [ORES[0].vein, ORES[1].vein, ORES[2].vein]
+}
+
+/// The rock layers and ores in a world.
+pub struct Strata {
+  layer_seed: Seed,
+  // The veins and noise seeds of each ore in `ORES`.
+  ores: Vec<(Vein, Seed)>,
+}
+
+impl Strata {
+  /// Layer the rock, with `veins` of each ore in `ORES`.
+  pub fn new(seed: u32, veins: &[Vein; 3]) -> Strata {
+    Strata {
+      layer_seed: Seed::new(seed.wrapping_add(4)),
+      ores:
+        veins.iter().enumerate()
+        .map(|(i, &vein)| (vein, Seed::new(seed.wrapping_add(5 + i as u32))))
+        .collect(),
+    }
+  }
+
+  /// What the rock is at a point `depth` world units below the surface.
+  pub fn rock_at(&self, x: f32, y: f32, z: f32, depth: f32) -> Material {
+    // Rarer ores win where veins overlap.
+    for (ore, &(vein, ref seed)) in ORES.iter().zip(self.ores.iter()).rev() {
+      if depth < ore.min_depth || vein.rarity >= 1.0 {
+        continue
+      }
+      let size = vein.size as f64;
+      let n = perlin3(seed, &[x as f64 / size, y as f64 / size, z as f64 / size]) as f32;
+      if n > vein.rarity {
+        return ore.material
+      }
+    }
+
+    let wobble = perlin2(&self.layer_seed, &[x as f64 * LAYER_FREQUENCY, z as f64 * LAYER_FREQUENCY]) as f32;
+    let depth = depth + wobble * LAYER_WOBBLE;
+    for &(material, bottom) in LAYERS.iter() {
+      if depth < bottom {
+        return material
+      }
+    }
+    Material::Granite
+  }
+}
+
+#[test]
+fn ores_are_deep_and_deterministic() {
+  let strata = Strata::new(11, &default_veins());
+  let mut samples = Vec::new();
+  let mut found_gold = false;
+  for x in 0..16 {
+  for y in 0..16 {
+  for z in 0..16 {
+    let (x, y, z) = (x as f32 * 1.5, y as f32 * 1.5, z as f32 * 1.5);
+    // Too shallow for any ore.
+    assert_eq!(strata.rock_at(x, y, z, 6.0), Material::Stone);
+    let deep = strata.rock_at(x, y - 200.0, z, 200.0 - y);
+    found_gold = found_gold || deep == Material::Gold;
+    samples.push((x, y, z, deep));
+  }}}
+  assert!(found_gold);
+
+  let strata = Strata::new(11, &default_veins());
+  for &(x, y, z, material) in samples.iter().rev() {
+    assert_eq!(strata.rock_at(x, y - 200.0, z, 200.0 - y), material);
+  }
+
+  // Ores can be turned off.
+  let none = Vein { rarity: 1.0, size: 4.0 };
+  let strata = Strata::new(11, &[none, none, none]);
+  assert_eq!(strata.rock_at(0.5, -200.0, 0.5, 200.0), Material::Granite);
+}
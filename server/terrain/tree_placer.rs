@@ -1,225 +1,314 @@
 // Thanks to http://procworld.blogspot.com/2011/02/space-colonization.html
 // for the basic idea used to generate these trees!
 
-use common::entity::EntityId;
-use common::id_allocator::IdAllocator;
-use common::lod::LODIndex;
-use common::terrain_block::{TerrainBlock, BLOCK_WIDTH, LOD_QUALITY, tri};
-use cgmath::{Point, Point2, Point3, EuclideanVector, Vector, Vector3};
-use cgmath::Aabb3;
-use rand::{Rng, SeedableRng, IsaacRng};
-use std::cmp::{partial_min, partial_max};
+//! Trees, as a field of their own that's added to the terrain's density, so that they're made
+//! of voxels like everything else: they show up at every LOD, and can be dug and raycast.
+//!
+//! The world is divided into cells, and each cell may grow one tree, depending on a random
+//! number generator seeded by the world seed and the cell's position, and on how many trees the
+//! biome there has. Trees are small enough that they never reach past the cells next to theirs.
+//!
+//! Looking trees up takes a lock, so anything sampling trees many times should look up the ones
+//! near where it's sampling once, with `near`, and sample those.
+
+use cgmath::{Point, Point3, EuclideanVector, Vector, Vector3};
 use std::collections::VecDeque;
-use std::num::Float;
-use std::sync::Mutex;
-
-const TREE_NODES: [f32; 4] = [1.0/16.0, 1.0/16.0, 1.0/64.0, 1.0/128.0];
-const MAX_BRANCH_LENGTH: [f32; 4] = [4.0, 4.0, 8.0, 16.0];
-const LEAF_RADIUS: [f32; 4] = [1.5, 1.5, 8.0, 16.0];
-
-#[inline(always)]
-fn fmod(mut dividend: f64, divisor: f64) -> f64 {
-  dividend -= divisor * (dividend / divisor).floor();
-  if dividend < 0.0 || dividend >= divisor{
-    // clamp
-    dividend = 0.0;
-  }
-  dividend
-}
+use std::sync::{Arc, Mutex};
+
+use common::material::Material;
+
+use biome::Biomes;
+use cache::Cache;
+use density::DENSITY_PER_UNIT;
+use materials;
+use rng::Rng;
+
+/// lg of the width of the cells trees grow in.
+const LG_CELL_WIDTH: i32 = 4;
+/// The chance of a tree in a cell of a biome with `trees` = 1.
+const TREE_CHANCE: f32 = 0.5;
+/// Crown nodes per cubic world unit.
+const TREE_NODES: f32 = 1.0 / 32.0;
+const MAX_BRANCH_LENGTH: f32 = 3.0;
+const MIN_BRANCH_RADIUS: f32 = 0.25;
+const LEAF_RADIUS: f32 = 2.0;
+/// How far outside a tree its field stops changing.
+const MARGIN: f32 = 4.0;
+/// How far above the ground trees can reach.
+pub const MAX_HEIGHT: f32 = 24.0;
+/// The range of heights to look for the ground in.
+const GROUND_SEARCH: (f32, f32) = (-256.0, 256.0);
+/// How many cells' trees to remember.
+const MAX_CACHED_CELLS: usize = 1 << 14;
 
 fn sqr_distance(p1: &Point3<f32>, p2: &Point3<f32>) -> f32 {
   let d = p1.sub_p(p2);
   d.x*d.x + d.y*d.y + d.z*d.z
 }
 
-/// Use one-octave perlin noise local maxima to place trees.
+/// A tapered cylinder of wood.
+struct Branch {
+  low: Point3<f32>,
+  low_radius: f32,
+  high: Point3<f32>,
+  high_radius: f32,
+}
+
+impl Branch {
+  /// How far inside the branch a point is; negative outside it.
+  fn depth(&self, p: &Point3<f32>) -> f32 {
+    let axis = self.high.sub_p(&self.low);
+    let t = (p.sub_p(&self.low).dot(&axis) / axis.length2()).max(0.0).min(1.0);
+    let closest = self.low.add_v(&axis.mul_s(t));
+    self.low_radius + (self.high_radius - self.low_radius) * t - p.sub_p(&closest).length()
+  }
+}
+
+struct Tree {
+  branches: Vec<Branch>,
+  leaves: Vec<Point3<f32>>,
+  low: Point3<f32>,
+  high: Point3<f32>,
+}
+
+impl Tree {
+  fn generate(rng: &mut Rng, mut center: Point3<f32>) -> Tree {
+    let mut tree = Tree {
+      branches: Vec::new(),
+      leaves: Vec::new(),
+      low: center,
+      high: center,
+    };
+
+    let mass = 0.1 + 0.9 * rng.float();
+    let sqr_mass = mass * mass;
+    let trunk_radius = 0.75 + sqr_mass;
+    let trunk_height = 4.0 + sqr_mass * 8.0;
+
+    // Sink the trunk a little so it doesn't float on slopes.
+    let trunk_top = center.add_v(&Vector3::new(0.0, trunk_height, 0.0));
+    tree.branches.push(Branch {
+      low: center.add_v(&Vector3::new(0.0, -2.0, 0.0)),
+      low_radius: trunk_radius,
+      high: trunk_top,
+      high_radius: trunk_radius,
+    });
+    center = trunk_top;
+
+    let crown_radius = 2.0 + sqr_mass * 6.0;
+    let crown_height = 3.0 + sqr_mass * 6.0;
+    let crown_width = crown_radius * 2.0;
+
+    let mut points: Vec<Point3<f32>> = {
+      let n_points = (crown_width * crown_width * crown_height * TREE_NODES) as u32;
+      (0 .. n_points)
+      .map(|_| {
+        Point3::new(
+          rng.range(-crown_radius, crown_radius),
+          rng.range(0.0, crown_height),
+          rng.range(-crown_radius, crown_radius),
+        )
+      })
+      .map(|p| p.add_v(&center.to_vec()))
+      .collect()
+    };
+
+    let mut fringe = VecDeque::new();
+    fringe.push_back((center, trunk_radius));
+
+    while let Some((center, thickness)) = fringe.pop_front() {
+      let mut i = 0;
+      let mut any_branches = false;
+
+      while i < points.len() {
+        if sqr_distance(&center, &points[i]) <= MAX_BRANCH_LENGTH * MAX_BRANCH_LENGTH {
+          let next_thickness = (thickness * 0.6).max(MIN_BRANCH_RADIUS);
+          tree.branches.push(Branch {
+            low: center,
+            low_radius: thickness,
+            high: points[i],
+            high_radius: next_thickness,
+          });
+          fringe.push_back((points[i], next_thickness));
+          points.swap_remove(i);
+          any_branches = true;
+        } else {
+          i += 1;
+        }
+      }
+
+      if !any_branches {
+        // A node with no branches gets leaves.
+        tree.leaves.push(center);
+      }
+    }
+
+    {
+      let (mut low, mut high) = (tree.low, tree.high);
+      let mut extend = |p: &Point3<f32>, radius: f32| {
+        let reach = radius + MARGIN;
+        low = Point3::new(low.x.min(p.x - reach), low.y.min(p.y - reach), low.z.min(p.z - reach));
+        high = Point3::new(high.x.max(p.x + reach), high.y.max(p.y + reach), high.z.max(p.z + reach));
+      };
+      for branch in tree.branches.iter() {
+        extend(&branch.low, branch.low_radius);
+        extend(&branch.high, branch.high_radius);
+      }
+      for leaf in tree.leaves.iter() {
+        extend(leaf, LEAF_RADIUS);
+      }
+      tree.low = low;
+      tree.high = high;
+    }
+
+    tree
+  }
+
+  /// Whether any of the box from `low` to `high` is close enough to the tree to be affected by it.
+  fn overlaps(&self, low: &Point3<f32>, high: &Point3<f32>) -> bool {
+    high.x >= self.low.x && high.y >= self.low.y && high.z >= self.low.z
+    && low.x <= self.high.x && low.y <= self.high.y && low.z <= self.high.z
+  }
+
+  /// How far inside the tree a point is, down to -`MARGIN`, and what it's inside of.
+  fn depth(&self, p: &Point3<f32>) -> (f32, Material) {
+    let mut deepest = (-MARGIN, Material::Empty);
+    if !self.overlaps(p, p) {
+      return deepest
+    }
+
+    for branch in self.branches.iter() {
+      let depth = branch.depth(p);
+      if depth > deepest.0 {
+        deepest = (depth, Material::Wood);
+      }
+    }
+    for leaf in self.leaves.iter() {
+      let depth = LEAF_RADIUS - sqr_distance(leaf, p).sqrt();
+      if depth > deepest.0 {
+        deepest = (depth, Material::Leaves);
+      }
+    }
+    deepest
+  }
+}
+
+/// Decides where trees grow, and what shape they are.
 pub struct TreePlacer {
   seed: u32,
+  trees: Mutex<Cache<(i32, i32), Option<Arc<Tree>>>>,
+}
+
+/// The trees that might reach into some bounds.
+pub struct TreesNear {
+  trees: Vec<Arc<Tree>>,
 }
 
 impl TreePlacer {
   pub fn new(seed: u32) -> TreePlacer {
     TreePlacer {
       seed: seed,
+      trees: Mutex::new(Cache::new(MAX_CACHED_CELLS)),
     }
   }
 
-  fn rng_at(&self, center: &Point3<f32>, mut seed: Vec<u32>) -> IsaacRng {
-    let center = center.mul_s((LOD_QUALITY[0] as f32) / (BLOCK_WIDTH as f32));
-    seed.push_all(&[self.seed, center.x as u32, center.z as u32]);
-    SeedableRng::from_seed(seed.as_slice())
-  }
-
-  pub fn should_place_tree(&self, center: &Point3<f32>) -> bool {
-    let mut rng = self.rng_at(center, vec!(0));
-    rng.next_u32() > 0xFF7FFFFF
-  }
+  /// The tree growing in a cell, if there is one.
+  fn generate_tree(&self, biomes: &Biomes, cell: (i32, i32)) -> Option<Tree> {
+    let width = (1 << LG_CELL_WIDTH) as f32;
+    let mut rng = Rng::new(self.seed, &[cell.0, cell.1]);
+    let x = (cell.0 as f32 + rng.float()) * width;
+    let z = (cell.1 as f32 + rng.float()) * width;
 
-  pub fn place_tree(
-    &self,
-    mut center: Point3<f32>,
-    id_allocator: &Mutex<IdAllocator<EntityId>>,
-    block: &mut TerrainBlock,
-    lod_index: LODIndex,
-  ) {
-    let lod_index = lod_index.0 as usize;
-    let normals = [
-      Vector3::new(-1.0, -1.0, -1.0).normalize(),
-      Vector3::new(-1.0, -1.0,  1.0).normalize(),
-      Vector3::new( 1.0, -1.0,  1.0).normalize(),
-      Vector3::new( 1.0, -1.0, -1.0).normalize(),
-      Vector3::new(-1.0,  1.0, -1.0).normalize(),
-      Vector3::new(-1.0,  1.0,  1.0).normalize(),
-      Vector3::new( 1.0,  1.0,  1.0).normalize(),
-      Vector3::new( 1.0,  1.0, -1.0).normalize(),
-    ];
-
-    let wood_coords = Point2::new(0.0, 3.0);
-    let leaf_coords = Point2::new(0.0, 4.0);
-
-    let mut place_side = |
-        corners: &[Point3<f32>],
-        coords: Point2<f32>,
-        idx1: usize,
-        idx2: usize,
-        idx3: usize,
-        idx4: usize,
-      | {
-        let n1 = normals[idx1];
-        let n2 = normals[idx2];
-        let n3 = normals[idx3];
-        let n4 = normals[idx4];
-
-        let v1 = corners[idx1];
-        let v2 = corners[idx2];
-        let v3 = corners[idx3];
-        let v4 = corners[idx4];
-
-        block.vertex_coordinates.push_all(&[tri(v1, v2, v4), tri(v1, v4, v3)]);
-        block.normals.push_all(&[tri(n1, n2, n4), tri(n1, n4, n3)]);
-        block.coords.push_all(&[tri(coords, coords, coords), tri(coords, coords, coords)]);
-
-        let minx = partial_min(v1.x, v2.x).unwrap();
-        let maxx = partial_max(v1.x, v2.x).unwrap();
-        let minz = partial_min(v1.z, v2.z).unwrap();
-        let maxz = partial_max(v1.z, v2.z).unwrap();
-
-        let bounds =
-          Aabb3::new(
-            Point3::new(minx, v1.y, minz),
-            Point3::new(maxx, v3.y, maxz),
-          );
-
-        let id1 = id_allocator.lock().unwrap().allocate();
-        let id2 = id_allocator.lock().unwrap().allocate();
-        block.ids.push_all(&[id1, id2]);
-
-        block.bounds.push((id1, bounds.clone()));
-        block.bounds.push((id2, bounds));
-      };
+    let blend = biomes.blend_at(x, z);
+    if rng.float() >= blend.trees * TREE_CHANCE {
+      return None
+    }
 
-    let mut place_block = |
-        coords: Point2<f32>,
-        low_center: &Point3<f32>, low_radius: f32,
-        high_center: &Point3<f32>, high_radius: f32,
-      | {
-        let corners = [
-          low_center.add_v(&Vector3::new(-low_radius, 0.0, -low_radius)),
-          low_center.add_v(&Vector3::new(-low_radius, 0.0,  low_radius)),
-          low_center.add_v(&Vector3::new( low_radius, 0.0,  low_radius)),
-          low_center.add_v(&Vector3::new( low_radius, 0.0, -low_radius)),
-          high_center.add_v(&Vector3::new(-high_radius, 0.0, -high_radius)),
-          high_center.add_v(&Vector3::new(-high_radius, 0.0,  high_radius)),
-          high_center.add_v(&Vector3::new( high_radius, 0.0,  high_radius)),
-          high_center.add_v(&Vector3::new( high_radius, 0.0, -high_radius)),
-        ];
-
-        place_side(&corners, coords, 0, 1, 4, 5);
-        place_side(&corners, coords, 1, 2, 5, 6);
-        place_side(&corners, coords, 2, 3, 6, 7);
-        place_side(&corners, coords, 3, 0, 7, 4);
-        place_side(&corners, coords, 1, 0, 2, 3);
-        place_side(&corners, coords, 4, 5, 7, 6);
-      };
+    // Find the ground, ignoring caves.
+    let mut y = GROUND_SEARCH.1;
+    while biomes.density_in(&blend, x, y, z) < 0.0 {
+      y = y - 1.0;
+      if y < GROUND_SEARCH.0 {
+        return None
+      }
+    }
 
-    let mut rng = self.rng_at(&center, vec!(1));
-    let mass = (rng.next_u32() as f32) / (0x10000 as f32) / (0x10000 as f32);
-    let mass = 0.1 + mass * 0.9;
-    let mass = partial_min(partial_max(0.0, mass).unwrap(), 1.0).unwrap();
+    // Trees don't grow on beaches.
+    if materials::material_at(blend.dominant, biomes.density_in(&blend, x, y, z), y) == Material::Sand {
+      return None
+    }
 
-    let sqr_mass = mass * mass;
-    let trunk_radius = sqr_mass * 2.0;
-    let trunk_height = sqr_mass * 16.0;
+    Some(Tree::generate(&mut rng, Point3::new(x, y, z)))
+  }
 
-    {
-      place_block(
-        wood_coords,
-        &center, trunk_radius,
-        &(center.add_v(&Vector3::new(0.0, trunk_height, 0.0))), trunk_radius,
-      );
-      center = center.add_v(&Vector3::new(0.0, trunk_height, 0.0));
-    }
+  /// The trees in the box from `low` to `high`. Sampling them anywhere outside it might miss
+  /// some.
+  pub fn near(&self, biomes: &Biomes, low: &Point3<f32>, high: &Point3<f32>) -> TreesNear {
+    let cell = |p: &Point3<f32>| ((p.x.floor() as i32) >> LG_CELL_WIDTH, (p.z.floor() as i32) >> LG_CELL_WIDTH);
+    let (low_cell, high_cell) = (cell(low), cell(high));
 
-    {
-      let crown_radius = sqr_mass * 16.0;
-      let crown_height = sqr_mass * 16.0;
-      let crown_width = crown_radius * 2.0;
-
-      let mut points: Vec<Point3<_>> = {
-        let n_points =
-          (crown_width * crown_width * crown_height * TREE_NODES[lod_index]) as u32;
-        (0 .. n_points)
-        .map(|_| {
-          let x = rng.next_u32();
-          let y = rng.next_u32();
-          let z = rng.next_u32();
-          Point3::new(
-            fmod(x as f64, crown_width as f64) as f32 - crown_radius,
-            fmod(y as f64, crown_height as f64) as f32,
-            fmod(z as f64, crown_width as f64) as f32 - crown_radius,
-          )
-        })
-        .map(|p| p.add_v(&center.to_vec()))
-        .collect()
-      };
+    let mut near = TreesNear {
+      trees: Vec::new(),
+    };
 
-      let mut fringe = VecDeque::new();
-      fringe.push_back((center, trunk_radius));
-
-      while let Some((center, thickness)) = fringe.pop_front() {
-        let mut i = 0;
-        let mut any_branches = false;
-
-        let radius = MAX_BRANCH_LENGTH[lod_index];
-        while i < points.len() {
-          if sqr_distance(&center, &points[i]) <= radius * radius {
-            let next_thickness = thickness * 0.6;
-            if center.y < points[i].y {
-              place_block(wood_coords, &center, thickness, &points[i], next_thickness);
-            } else {
-              place_block(wood_coords, &points[i], next_thickness, &center, thickness);
-            }
-            fringe.push_back((points[i], next_thickness));
-            points.swap_remove(i);
-            any_branches = true;
-          } else {
-            i += 1;
-          }
+    let mut trees = self.trees.lock().unwrap();
+    // Trees reach into the cells next to theirs.
+    for x in low_cell.0 - 1 .. high_cell.0 + 2 {
+    for z in low_cell.1 - 1 .. high_cell.1 + 2 {
+      let tree = trees.get((x, z), || self.generate_tree(biomes, (x, z)).map(Arc::new));
+      if let Some(tree) = tree {
+        if tree.overlaps(low, high) {
+          near.trees.push(tree);
         }
+      }
+    }}
 
-        if !any_branches {
-          // A node with no branches gets leaves.
+    near
+  }
 
-          let radius = LEAF_RADIUS[lod_index];
-          let height = 2.0 * radius;
+  /// The tree field at a point, which is positive inside a tree, along with what the tree is
+  /// made of there.
+  pub fn density_at(&self, biomes: &Biomes, x: f32, y: f32, z: f32) -> (f32, Material) {
+    let p = Point3::new(x, y, z);
+    self.near(biomes, &p, &p).density_at(x, y, z)
+  }
+}
 
-          place_block(
-            leaf_coords,
-            &center, radius,
-            &(center.add_v(&Vector3::new(0.0, height, 0.0))), radius,
-          );
-        }
+impl TreesNear {
+  /// The tree field at a point in these bounds, which is positive inside a tree, along with what
+  /// the tree is made of there.
+  pub fn density_at(&self, x: f32, y: f32, z: f32) -> (f32, Material) {
+    let p = Point3::new(x, y, z);
+    let mut deepest = (-MARGIN, Material::Empty);
+    for tree in self.trees.iter() {
+      let depth = tree.depth(&p);
+      if depth.0 > deepest.0 {
+        deepest = depth;
       }
     }
+    (deepest.0 * DENSITY_PER_UNIT, deepest.1)
   }
 }
+
+#[test]
+fn plains_grow_trees_on_the_ground() {
+  use density::Density;
+  use world::Params;
+
+  let params = Params::new(1);
+  let biomes = Biomes::none(Density::parse("(mul y -1)", &params).unwrap());
+  let placer = TreePlacer::new(1);
+
+  // Flat ground at y = 0, and plains everywhere: some cells have trees, rooted at the ground.
+  let mut trees = 0;
+  for x in 0..16 {
+  for z in 0..16 {
+    if let Some(tree) = placer.generate_tree(&biomes, (x, z)) {
+      assert!(tree.branches[0].low.y < 0.0 && tree.branches[0].high.y > 0.0);
+      let base = tree.branches[0].high;
+      assert_eq!(placer.density_at(&biomes, base.x, base.y - 3.0, base.z).1, Material::Wood);
+      trees += 1;
+    }
+  }}
+  assert!(trees > 0);
+}
@@ -1,5 +1,7 @@
 use cgmath::{Point, Point3, EuclideanVector, Vector3};
 
+use common::material::Material;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Bounds {
   /// x-coordinate as a multiple of 2^lg_size.
@@ -45,8 +47,8 @@ impl Bounds {
 // have three low-order bits set to zero).
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Voxel {
-  // The voxel is entirely inside or outside the volume. true is inside.
-  Volume(bool),
+  // The voxel is entirely inside or outside the volume. `Material::Empty` is outside.
+  Volume(Material),
   // The voxel crosses the surface of the volume.
   Surface(SurfaceVoxel),
 }
@@ -64,6 +66,9 @@ pub struct SurfaceVoxel {
 
   /// Is this voxel's low corner inside the field?
   pub corner_inside_surface: bool,
+
+  /// What the inside of the surface is made of.
+  pub material: Material,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -1,13 +1,21 @@
 #![cfg_attr(test, feature(test))]
 
-use cgmath::Ray3;
+use cgmath::{Point, Ray3, Vector};
+use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::ops::{Deref, DerefMut};
 
+use pager::Pager;
 use raycast;
 use voxel;
 use voxel::Voxel;
 
+/// The log_2 of the width of the subtrees that can be paged out.
+pub const LG_PAGE_SIZE: i16 = 6;
+
+/// The position of a page-sized subtree, in multiples of its width.
+type Region = (i32, i32, i32);
+
 #[derive(Debug)]
 pub struct VoxelTree {
   /// The log_2 of the tree's size.
@@ -15,6 +23,14 @@ pub struct VoxelTree {
   /// Force the top level to always be branches;
   /// it saves a branch in the grow logic.
   contents: Branches,
+  /// Where cold subtrees go, if anywhere.
+  pager: Option<Pager>,
+  /// Ticks on every access, to tell which regions have gone cold.
+  clock: u64,
+  /// When each region in memory was last accessed.
+  last_used: HashMap<Region, u64>,
+  /// The regions that are currently paged out.
+  paged_out: HashSet<Region>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -38,6 +54,8 @@ pub enum TreeBody {
   Empty,
   Leaf(Voxel),
   Branch(Box<Branches>),
+  /// A subtree that's been written out to the `VoxelTree`'s `Pager`.
+  PagedOut,
 }
 
 impl Branches {
@@ -60,6 +78,13 @@ impl Branches {
     };
     &this[x][y][z]
   }
+
+  pub fn get_mut<'a>(&'a mut self, x: usize, y: usize, z: usize) -> &'a mut TreeBody {
+    let this: &'a mut [[[TreeBody; 2]; 2]; 2] = unsafe {
+      mem::transmute(self)
+    };
+    &mut this[x][y][z]
+  }
 }
 
 impl VoxelTree {
@@ -67,9 +92,20 @@ impl VoxelTree {
     VoxelTree {
       lg_size: 0,
       contents: Branches::empty(),
+      pager: None,
+      clock: 0,
+      last_used: HashMap::new(),
+      paged_out: HashSet::new(),
     }
   }
 
+  /// Make a tree that can page cold subtrees out to `pager` (see `page_out_cold`).
+  pub fn with_pager(pager: Pager) -> VoxelTree {
+    let mut tree = VoxelTree::new();
+    tree.pager = Some(pager);
+    tree
+  }
+
   /// Is this voxel (non-strictly) within an origin-centered voxel with
   /// size `2^lg_size`?
   pub fn contains_bounds(&self, voxel: &voxel::Bounds) -> bool {
@@ -278,9 +314,128 @@ impl VoxelTree {
     }
   }
 
+  /// Find an existing node in the tree, without creating anything on the way.
+  fn find_node_mut<'a>(&'a mut self, voxel: &voxel::Bounds) -> Option<&'a mut TreeBody> {
+    if !self.contains_bounds(voxel) {
+      return None
+    }
+
+    let get_step = |branch| {
+      match branch {
+        &mut TreeBody::Branch(ref mut branches) => Ok(branches.deref_mut()),
+        _ => Err(()),
+      }
+    };
+
+    self.find_mut(voxel, get_step).ok()
+  }
+
+  /// The page-sized region containing `voxel`, if `voxel` is no bigger than a page.
+  fn region_of(voxel: &voxel::Bounds) -> Option<Region> {
+    if voxel.lg_size > LG_PAGE_SIZE {
+      return None
+    }
+
+    let shift = LG_PAGE_SIZE - voxel.lg_size;
+    Some((voxel.x >> shift, voxel.y >> shift, voxel.z >> shift))
+  }
+
+  fn region_bounds(region: &Region) -> voxel::Bounds {
+    voxel::Bounds::new(region.0, region.1, region.2, LG_PAGE_SIZE)
+  }
+
+  /// Note an access to the region holding `voxel`, paging it back in if necessary.
+  fn touch(&mut self, voxel: &voxel::Bounds) {
+    if self.pager.is_none() {
+      return
+    }
+
+    let region =
+      match VoxelTree::region_of(voxel) {
+        None => return,
+        Some(region) => region,
+      };
+
+    self.clock += 1;
+    self.last_used.insert(region, self.clock);
+
+    if self.paged_out.remove(&region) {
+      self.page_in(&region);
+    }
+  }
+
+  fn page_in(&mut self, region: &Region) {
+    let bounds = VoxelTree::region_bounds(region);
+    let body =
+      match self.pager.as_ref().unwrap().page_in(&bounds) {
+        Ok(body) => body,
+        Err(e) => {
+          // Voxels can be regenerated, so we can get by without these.
+          warn!("Couldn't page in voxels {:?}: {}", bounds, e);
+          TreeBody::Empty
+        },
+      };
+
+    // The subtree may have been overwritten (e.g. by a bigger voxel) while it was paged out.
+    if let Some(node) = self.find_node_mut(&bounds) {
+      if *node == TreeBody::PagedOut {
+        *node = body;
+      }
+    }
+  }
+
+  fn page_out(&mut self, region: &Region) {
+    let bounds = VoxelTree::region_bounds(region);
+    let body =
+      match self.find_node_mut(&bounds) {
+        None => return,
+        Some(node) => {
+          // Anything smaller than a branch isn't worth a file.
+          let is_branch =
+            match *node {
+              TreeBody::Branch(_) => true,
+              _ => false,
+            };
+          if !is_branch {
+            return
+          }
+          mem::replace(node, TreeBody::PagedOut)
+        },
+      };
+
+    match self.pager.as_ref().unwrap().page_out(&bounds, &body) {
+      Ok(()) => {
+        self.paged_out.insert(*region);
+      },
+      Err(e) => {
+        warn!("Couldn't page out voxels {:?}: {}", bounds, e);
+        *self.find_node_mut(&bounds).unwrap() = body;
+      },
+    }
+  }
+
+  /// If more than `max_resident` regions are in memory, page out the least recently used ones.
+  pub fn page_out_cold(&mut self, max_resident: usize) {
+    if self.pager.is_none() || self.last_used.len() <= max_resident {
+      return
+    }
+
+    let mut by_age: Vec<(u64, Region)> =
+      self.last_used.iter().map(|(&region, &time)| (time, region)).collect();
+    by_age.sort();
+
+    // Page out a few extra, so we don't end up back here on the next access.
+    let evict = by_age.len() - max_resident * 3 / 4;
+    for &(_, region) in by_age[..evict].iter() {
+      self.last_used.remove(&region);
+      self.page_out(&region);
+    }
+  }
+
   /// Find a voxel inside this tree.
   /// If it doesn't exist, it will be created as empty.
   pub fn get_mut_or_create<'a>(&'a mut self, voxel: &voxel::Bounds) -> &'a mut TreeBody {
+    self.touch(voxel);
     self.grow_to_hold(voxel);
     let branch: Result<_, ()> =
       self.find_mut(voxel, |branch| { Ok(VoxelTree::get_mut_or_create_step(branch)) });
@@ -319,11 +474,16 @@ impl VoxelTree {
           _ => unreachable!(),
         }
       },
+      TreeBody::PagedOut => {
+        // Regions are paged in before anything inside them is looked up.
+        unreachable!()
+      },
     }
   }
 
   /// Find a voxel inside this tree.
-  pub fn get<'a>(&'a self, voxel: &voxel::Bounds) -> Option<&'a Voxel> {
+  pub fn get<'a>(&'a mut self, voxel: &voxel::Bounds) -> Option<&'a Voxel> {
+    self.touch(voxel);
     if !self.contains_bounds(voxel) {
       return None
     }
@@ -343,32 +503,28 @@ impl VoxelTree {
 
   /// Find a voxel inside this tree.
   pub fn get_mut<'a>(&'a mut self, voxel: &voxel::Bounds) -> Option<&'a mut Voxel> {
-    if !self.contains_bounds(voxel) {
-      return None
-    }
-
-    let get_step = |branch| {
-      match branch {
-        &mut TreeBody::Branch(ref mut branches) => Ok(branches.deref_mut()),
-        _ => Err(()),
-      }
-    };
-
-    match self.find_mut(voxel, get_step) {
-      Ok(&mut TreeBody::Leaf(ref mut t)) => Some(t),
+    self.touch(voxel);
+    match self.find_node_mut(voxel) {
+      Some(&mut TreeBody::Leaf(ref mut t)) => Some(t),
       _ => None,
     }
   }
 
+  /// Walk along `ray`, calling `act` on each leaf voxel it passes through until `act` returns
+  /// `Some`. Voxels entered more than `max_toi` (in multiples of the ray's direction) along the ray
+  /// are ignored.
   pub fn cast_ray<'a, Act, R>(
-    &'a self,
+    &'a mut self,
     ray: &Ray3<f32>,
+    max_toi: f32,
     act: &mut Act,
   ) -> Option<R>
     where
       // TODO: Does this *have* to be callback-based?
-      Act: FnMut(voxel::Bounds, &'a Voxel) -> Option<R>
+      Act: FnMut(&raycast::Hit, &'a Voxel) -> Option<R>
   {
+    self.page_in_along(ray, max_toi);
+
     let coords = [
       if ray.origin.x >= 0.0 {1} else {0},
       if ray.origin.y >= 0.0 {1} else {0},
@@ -389,6 +545,7 @@ impl VoxelTree {
     match raycast::cast_ray_branches(
       &self.contents,
       ray,
+      max_toi,
       None,
       coords,
       &mut make_bounds,
@@ -398,89 +555,165 @@ impl VoxelTree {
       Err(_) => None,
     }
   }
+
+  /// Page in every paged-out region that the first `max_toi` of `ray` might pass through.
+  fn page_in_along(&mut self, ray: &Ray3<f32>, max_toi: f32) {
+    if self.paged_out.is_empty() {
+      return
+    }
+
+    let end = ray.origin.add_v(&ray.direction.mul_s(max_toi));
+    let low = [ray.origin.x.min(end.x), ray.origin.y.min(end.y), ray.origin.z.min(end.z)];
+    let high = [ray.origin.x.max(end.x), ray.origin.y.max(end.y), ray.origin.z.max(end.z)];
+    let size = (1 << LG_PAGE_SIZE) as f32;
+    let overlaps = |low: f32, high: f32, region: i32| {
+      (region + 1) as f32 * size >= low && region as f32 * size <= high
+    };
+
+    let regions: Vec<Region> =
+      self.paged_out.iter()
+      .filter(|region| {
+        // An unbounded ray could go anywhere.
+        !max_toi.is_finite() || (
+          overlaps(low[0], high[0], region.0) &&
+          overlaps(low[1], high[1], region.1) &&
+          overlaps(low[2], high[2], region.2)
+        )
+      })
+      .cloned()
+      .collect();
+    for region in regions.iter() {
+      self.touch(&VoxelTree::region_bounds(region));
+    }
+  }
 }
 
 #[cfg(test)]
 mod tests {
   extern crate test;
 
+  use cgmath::{Point3, Vector3, Ray3};
+
+  use common::material::Material;
+
+  use pager::Pager;
+  use raycast::Face;
   use voxel;
+  use voxel::Voxel;
   use super::{VoxelTree, TreeBody};
 
   #[test]
   fn insert_and_lookup() {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    *tree.get_mut_or_create(voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(1);
-    *tree.get_mut_or_create(voxel::Bounds::new(8, -8, 4, 0)) = TreeBody::Leaf(2);
-    *tree.get_mut_or_create(voxel::Bounds::new(2, 0, 4, 4)) = TreeBody::Leaf(3);
-    *tree.get_mut_or_create(voxel::Bounds::new(9, 0, 16, 2)) = TreeBody::Leaf(4);
-    *tree.get_mut_or_create(voxel::Bounds::new(9, 0, 16, 2)) = TreeBody::Leaf(5);
-
-    assert_eq!(tree.get(voxel::Bounds::new(1, 1, 1, 0)), Some(&1));
-    assert_eq!(tree.get(voxel::Bounds::new(8, -8, 4, 0)), Some(&2));
-    assert_eq!(tree.get(voxel::Bounds::new(9, 0, 16, 2)), Some(&5));
-
-    assert_eq!(tree.get(voxel::Bounds::new(2, 0, 4, 4)), None);
+    let mut tree = VoxelTree::new();
+    *tree.get_mut_or_create(&voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(8, -8, 4, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Empty));
+    *tree.get_mut_or_create(&voxel::Bounds::new(2, 0, 4, 4)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(9, 0, 16, 2)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(9, 0, 16, 2)) = TreeBody::Leaf(Voxel::Volume(Material::Empty));
+
+    assert_eq!(tree.get(&voxel::Bounds::new(1, 1, 1, 0)), Some(&Voxel::Volume(Material::Stone)));
+    assert_eq!(tree.get(&voxel::Bounds::new(8, -8, 4, 0)), Some(&Voxel::Volume(Material::Empty)));
+    assert_eq!(tree.get(&voxel::Bounds::new(9, 0, 16, 2)), Some(&Voxel::Volume(Material::Empty)));
+
+    assert_eq!(tree.get(&voxel::Bounds::new(2, 0, 4, 4)), None);
   }
 
   #[test]
   fn wrong_voxel_size_is_not_found() {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    *tree.get_mut_or_create(voxel::Bounds::new(4, 4, -4, 1)) = TreeBody::Leaf(1);
-    assert_eq!(tree.get(voxel::Bounds::new(4, 4, -4, 0)), None);
-    assert_eq!(tree.get(voxel::Bounds::new(4, 4, -4, 2)), None);
+    let mut tree = VoxelTree::new();
+    *tree.get_mut_or_create(&voxel::Bounds::new(4, 4, -4, 1)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    assert_eq!(tree.get(&voxel::Bounds::new(4, 4, -4, 0)), None);
+    assert_eq!(tree.get(&voxel::Bounds::new(4, 4, -4, 2)), None);
   }
 
   #[test]
   fn grow_is_transparent() {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    *tree.get_mut_or_create(voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(1);
-    tree.grow_to_hold(voxel::Bounds::new(0, 0, 0, 1));
-    tree.grow_to_hold(voxel::Bounds::new(0, 0, 0, 2));
-    tree.grow_to_hold(voxel::Bounds::new(-32, 32, -128, 3));
+    let mut tree = VoxelTree::new();
+    *tree.get_mut_or_create(&voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    tree.grow_to_hold(&voxel::Bounds::new(0, 0, 0, 1));
+    tree.grow_to_hold(&voxel::Bounds::new(0, 0, 0, 2));
+    tree.grow_to_hold(&voxel::Bounds::new(-32, 32, -128, 3));
 
-    assert_eq!(tree.get(voxel::Bounds::new(1, 1, 1, 0)), Some(&1));
+    assert_eq!(tree.get(&voxel::Bounds::new(1, 1, 1, 0)), Some(&Voxel::Volume(Material::Stone)));
   }
 
   #[test]
   fn simple_cast_ray() {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    *tree.get_mut_or_create(voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(1);
-    *tree.get_mut_or_create(voxel::Bounds::new(4, 4, 4, 0)) = TreeBody::Leaf(2);
+    let mut tree = VoxelTree::new();
+    *tree.get_mut_or_create(&voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(4, 4, 4, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
 
+    let ray = Ray3::new(Point3::new(4.5, 3.0, 4.5), Vector3::new(0.1, 0.8, 0.1));
     let actual = tree.cast_ray(
-      [4.5, 3.0, 4.5],
-      [0.1, 0.8, 0.1],
+      &ray,
+      100.0,
       // Return the first voxel we hit.
-      &mut |bounds, v| Some((bounds, v)),
-    );
+      &mut |hit, _| Some(*hit),
+    ).unwrap();
+
+    assert_eq!(actual.bounds, voxel::Bounds::new(4, 4, 4, 0));
+    assert_eq!(actual.face, Some(Face::LowY));
+    assert!((actual.toi - 1.25).abs() < 0.0001);
+    assert!((actual.point.y - 4.0).abs() < 0.0001);
+  }
+
+  #[test]
+  fn cast_ray_stops_at_max_toi() {
+    let mut tree = VoxelTree::new();
+    *tree.get_mut_or_create(&voxel::Bounds::new(4, 4, 4, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+
+    let ray = Ray3::new(Point3::new(4.5, 3.0, 4.5), Vector3::new(0.1, 0.8, 0.1));
+    let actual = tree.cast_ray(&ray, 1.0, &mut |hit, _| Some(*hit));
+
+    assert_eq!(actual, None);
+  }
+
+  #[test]
+  fn paging_is_transparent() {
+    use std::env;
+
+    let pager = Pager::new(env::temp_dir().join("playform-paging-is-transparent")).unwrap();
+    let mut tree = VoxelTree::with_pager(pager);
+    *tree.get_mut_or_create(&voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(200, 4, 4, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Dirt));
+
+    tree.page_out_cold(0);
+    assert_eq!(tree.paged_out.len(), 2);
+
+    assert_eq!(tree.get(&voxel::Bounds::new(1, 1, 1, 0)), Some(&Voxel::Volume(Material::Stone)));
+    assert_eq!(tree.paged_out.len(), 1);
 
-    assert_eq!(actual, Some((voxel::Bounds::new(4, 4, 4, 0), &2)));
+    tree.page_out_cold(0);
+    let ray = Ray3::new(Point3::new(1.5, 3.0, 1.5), Vector3::new(0.0, -1.0, 0.0));
+    let actual = tree.cast_ray(&ray, 10.0, &mut |hit, _| Some(hit.bounds));
+    assert_eq!(actual, Some(voxel::Bounds::new(1, 1, 1, 0)));
+    assert_eq!(tree.get(&voxel::Bounds::new(200, 4, 4, 0)), Some(&Voxel::Volume(Material::Dirt)));
   }
 
   #[bench]
   fn simple_inserts(bencher: &mut test::Bencher) {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    tree.grow_to_hold(voxel::Bounds::new(0, 0, 0, 30));
+    let mut tree = VoxelTree::new();
+    tree.grow_to_hold(&voxel::Bounds::new(0, 0, 0, 30));
     bencher.iter(|| {
-      *tree.get_mut_or_create(voxel::Bounds::new(0, 0, 0, 0)) = TreeBody::Leaf(0);
+      *tree.get_mut_or_create(&voxel::Bounds::new(0, 0, 0, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
     });
     test::black_box(tree);
   }
 
   #[bench]
   fn bench_cast_ray(bencher: &mut test::Bencher) {
-    let mut tree: VoxelTree<i32> = VoxelTree::new();
-    tree.grow_to_hold(voxel::Bounds::new(0, 0, 0, 30));
-    *tree.get_mut_or_create(voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(1);
-    *tree.get_mut_or_create(voxel::Bounds::new(4, 4, 4, 0)) = TreeBody::Leaf(2);
+    let mut tree = VoxelTree::new();
+    tree.grow_to_hold(&voxel::Bounds::new(0, 0, 0, 30));
+    *tree.get_mut_or_create(&voxel::Bounds::new(1, 1, 1, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
+    *tree.get_mut_or_create(&voxel::Bounds::new(4, 4, 4, 0)) = TreeBody::Leaf(Voxel::Volume(Material::Stone));
 
+    let ray = Ray3::new(Point3::new(4.5, 3.0, 4.5), Vector3::new(0.1, 0.8, 0.1));
     bencher.iter(|| {
       let r = tree.cast_ray(
-        [4.5, 3.0, 4.5],
-        [0.1, 0.8, 0.1],
+        &ray,
+        100.0,
         // Return the first voxel we hit.
-        &mut |bounds, v| Some((bounds, v)),
+        &mut |hit, v| Some((*hit, v)),
       );
       test::black_box(r);
     });
@@ -0,0 +1,350 @@
+//! The on-disk format for the parts of the world that aren't procedurally generated.
+//!
+//! A world is a directory holding a `header` file, which records the format version, the
+//! parameters the terrain is generated from and how far the world extends, and a `regions`
+//! directory with one file of edits for each `2^LG_REGION_WIDTH`-wide cube of the world that has
+//! been changed.
+
+use cgmath::Point3;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::material::Material;
+
+use density;
+use strata;
+use strata::Vein;
+use {AMPLITUDE, OCTAVES, FREQUENCY, PERSISTENCE, LACUNARITY, WIDTH};
+
+/// The version of the format; bump this whenever it changes.
+pub const VERSION: u32 = 1;
+/// lg of the width of a region, in world units.
+pub const LG_REGION_WIDTH: i32 = 6;
+
+const HEADER_MAGIC: &'static str = "playform-world";
+const REGION_MAGIC: &'static [u8] = b"PFRG";
+
+/// The parameters the terrain is generated from, and the world's extent.
+/// Saved edits (and players) only make sense on top of the same ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Params {
+  pub seed: u32,
+  /// Roughly how far the land rises and falls from y = 0. Density functions are written for
+  /// `AMPLITUDE`, and stretched vertically to this.
+  pub amplitude: f64,
+  pub octaves: usize,
+  pub frequency: f64,
+  pub persistence: f64,
+  pub lacunarity: f64,
+  /// The density function, on one line (see the `density` module).
+  pub density: String,
+  /// Whether the density function is shaped by biomes.
+  pub biomes: bool,
+  /// Whether caves are carved out of the terrain.
+  pub caves: bool,
+  /// Whether trees grow on the terrain.
+  pub trees: bool,
+  /// Whether the rock underground is layered and has ore in it.
+  pub strata: bool,
+  /// How each ore in `strata::ORES` is spread.
+  pub ores: [Vein; 3],
+  /// How far the world extends from the origin along x and z, in world units.
+  pub width: u32,
+}
+
+impl Params {
+  /// The default generation parameters, with a given seed.
+  pub fn new(seed: u32) -> Params {
+    Params {
+      seed: seed,
+      amplitude: AMPLITUDE,
+      octaves: OCTAVES,
+      frequency: FREQUENCY,
+      persistence: PERSISTENCE,
+      lacunarity: LACUNARITY,
+      density: density::DEFAULT.to_string(),
+      biomes: true,
+      caves: true,
+      trees: true,
+      strata: true,
+      ores: strata::default_veins(),
+      width: WIDTH,
+    }
+  }
+}
+
+/// The region containing a world position.
+pub fn region_of(p: &Point3<i32>) -> Point3<i32> {
+  Point3::new(p.x >> LG_REGION_WIDTH, p.y >> LG_REGION_WIDTH, p.z >> LG_REGION_WIDTH)
+}
+
+/// A world directory.
+pub struct World {
+  path: PathBuf,
+}
+
+impl World {
+  /// Open the world at `path`, creating it with `params` if it doesn't exist yet.
+  /// Returns the world along with the parameters it was created with, which win over `params`.
+  pub fn open(path: &Path, params: &Params) -> io::Result<(World, Params)> {
+    let world =
+      World {
+        path: path.to_path_buf(),
+      };
+    try!(fs::create_dir_all(&world.regions_dir()));
+
+    let header = world.path.join("header");
+    match File::open(&header) {
+      Ok(file) => {
+        let stored = try!(read_header(file));
+        if stored != *params {
+          info!("{:?} was created with {:?}; using those instead of {:?}", path, stored, params);
+        }
+        Ok((world, stored))
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+        try!(write_atomically(&header, |file| write_header(file, params)));
+        Ok((world, params.clone()))
+      },
+      Err(e) => Err(e),
+    }
+  }
+
+  /// The world's directory.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn regions_dir(&self) -> PathBuf {
+    self.path.join("regions")
+  }
+
+  fn region_path(&self, region: &Point3<i32>) -> PathBuf {
+    self.regions_dir().join(format!("{}_{}_{}.edits", region.x, region.y, region.z))
+  }
+
+  /// Read the edits in a region. Regions that have never been saved have no edits.
+  pub fn load_region(&self, region: &Point3<i32>) -> io::Result<HashMap<Point3<i32>, Material>> {
+    let mut bytes = Vec::new();
+    match File::open(&self.region_path(region)) {
+      Ok(mut file) => {
+        try!(file.read_to_end(&mut bytes));
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+        return Ok(HashMap::new())
+      },
+      Err(e) => return Err(e),
+    }
+
+    let mut bytes = Bytes(&bytes);
+    if try!(bytes.take(REGION_MAGIC.len())) != REGION_MAGIC {
+      return Err(invalid("not a region file"))
+    }
+    let version = try!(bytes.u32());
+    if version != VERSION {
+      return Err(invalid("unsupported region version"))
+    }
+
+    let count = try!(bytes.u32());
+    let mut samples = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+      let x = try!(bytes.u32()) as i32;
+      let y = try!(bytes.u32()) as i32;
+      let z = try!(bytes.u32()) as i32;
+      let material = try!(bytes.take(1))[0];
+      let material = try!(Material::from_u8(material).ok_or(invalid("unknown material")));
+      samples.insert(Point3::new(x, y, z), material);
+    }
+    Ok(samples)
+  }
+
+  /// Replace the saved edits in a region.
+  pub fn save_region(
+    &self,
+    region: &Point3<i32>,
+    samples: &HashMap<Point3<i32>, Material>,
+  ) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(12 + 13 * samples.len());
+    bytes.extend(REGION_MAGIC.iter().cloned());
+    push_u32(&mut bytes, VERSION);
+    push_u32(&mut bytes, samples.len() as u32);
+    for (p, &material) in samples.iter() {
+      push_u32(&mut bytes, p.x as u32);
+      push_u32(&mut bytes, p.y as u32);
+      push_u32(&mut bytes, p.z as u32);
+      bytes.push(material as u8);
+    }
+
+    write_atomically(&self.region_path(region), |file| file.write_all(&bytes))
+  }
+}
+
+fn invalid(description: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::Other, description)
+}
+
+/// Write to a temporary file and then move it into place, so a crash can't leave half a file.
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+  where F: FnOnce(&mut File) -> io::Result<()>,
+{
+  let tmp = path.with_extension("tmp");
+  {
+    let mut file = try!(File::create(&tmp));
+    try!(write(&mut file));
+    try!(file.sync_all());
+  }
+  fs::rename(&tmp, path)
+}
+
+fn write_header(file: &mut File, params: &Params) -> io::Result<()> {
+  try!(writeln!(file, "{} {}", HEADER_MAGIC, VERSION));
+  try!(writeln!(file, "seed {}", params.seed));
+  try!(writeln!(file, "amplitude {}", params.amplitude));
+  try!(writeln!(file, "octaves {}", params.octaves));
+  try!(writeln!(file, "frequency {}", params.frequency));
+  try!(writeln!(file, "persistence {}", params.persistence));
+  try!(writeln!(file, "lacunarity {}", params.lacunarity));
+  try!(writeln!(file, "density {}", params.density));
+  try!(writeln!(file, "biomes {}", params.biomes));
+  try!(writeln!(file, "caves {}", params.caves));
+  try!(writeln!(file, "trees {}", params.trees));
+  try!(writeln!(file, "strata {}", params.strata));
+  for (ore, vein) in strata::ORES.iter().zip(params.ores.iter()) {
+    try!(writeln!(file, "{} {} {}", ore.name, vein.rarity, vein.size));
+  }
+  try!(writeln!(file, "width {}", params.width));
+  Ok(())
+}
+
+fn read_header(file: File) -> io::Result<Params> {
+  let mut lines = BufReader::new(file).lines();
+
+  let first = try!(lines.next().unwrap_or(Err(invalid("empty world header"))));
+  if first != format!("{} {}", HEADER_MAGIC, VERSION) {
+    return Err(invalid("unsupported world header"))
+  }
+
+  let mut values = HashMap::new();
+  for line in lines {
+    let line = try!(line);
+    let mut words = line.splitn(2, ' ');
+    match (words.next(), words.next()) {
+      (Some(key), Some(value)) => { values.insert(key.to_string(), value.to_string()); },
+      _ => return Err(invalid("malformed world header")),
+    }
+  }
+
+  macro_rules! field(
+    ($name:expr) => {{
+      let value = try!(values.get($name).ok_or(invalid("world header is missing a field")));
+      try!(value.parse().map_err(|_| invalid("malformed world header field")))
+    }}
+  );
+  macro_rules! optional_field(
+    ($name:expr, $default:expr) => {{
+      match values.get($name) {
+        None => $default,
+        Some(value) => try!(value.parse().map_err(|_| invalid("malformed world header field"))),
+      }
+    }}
+  );
+
+  let mut params = Params {
+    seed: field!("seed"),
+    // Worlds from before these were configurable used the defaults.
+    amplitude: optional_field!("amplitude", AMPLITUDE),
+    octaves: field!("octaves"),
+    frequency: field!("frequency"),
+    persistence: field!("persistence"),
+    lacunarity: field!("lacunarity"),
+    // Worlds from before density functions used the default one.
+    density: values.get("density").cloned().unwrap_or(density::DEFAULT.to_string()),
+    // Nor did they have biomes, caves, trees or strata.
+    biomes: values.get("biomes").map_or(false, |biomes| biomes == "true"),
+    caves: values.get("caves").map_or(false, |caves| caves == "true"),
+    trees: values.get("trees").map_or(false, |trees| trees == "true"),
+    strata: values.get("strata").map_or(false, |strata| strata == "true"),
+    ores: strata::default_veins(),
+    width: optional_field!("width", WIDTH),
+  };
+  let finite_positive = |x: f64| x.is_finite() && x > 0.0;
+  if !finite_positive(params.amplitude) || params.width == 0 {
+    return Err(invalid("world header has a non-positive amplitude or width"))
+  }
+  if params.octaves == 0
+  || !finite_positive(params.frequency)
+  || !finite_positive(params.persistence)
+  || !finite_positive(params.lacunarity) {
+    return Err(invalid("world header has bad noise settings"))
+  }
+  for (ore, vein) in strata::ORES.iter().zip(params.ores.iter_mut()) {
+    if let Some(value) = values.get(ore.name) {
+      let mut words = value.split(' ').map(|word| word.parse::<f32>());
+      match (words.next(), words.next(), words.next()) {
+        (Some(Ok(rarity)), Some(Ok(size)), None) => *vein = Vein { rarity: rarity, size: size },
+        _ => return Err(invalid("malformed ore in world header")),
+      }
+    }
+  }
+  if let Err(e) = density::Density::parse(&params.density, &params) {
+    warn!("Bad density function in world header: {}", e);
+    return Err(invalid("malformed density function in world header"))
+  }
+  Ok(params)
+}
+
+/// Append `x` to `bytes`, little-endian.
+pub fn push_u32(bytes: &mut Vec<u8>, x: u32) {
+  bytes.push(x as u8);
+  bytes.push((x >> 8) as u8);
+  bytes.push((x >> 16) as u8);
+  bytes.push((x >> 24) as u8);
+}
+
+/// A cursor for reading little-endian data out of a byte slice.
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Bytes<'a> {
+  pub fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+    if self.0.len() < n {
+      return Err(invalid("unexpected end of file"))
+    }
+    let (taken, rest) = self.0.split_at(n);
+    self.0 = rest;
+    Ok(taken)
+  }
+
+  pub fn u32(&mut self) -> io::Result<u32> {
+    let b = try!(self.take(4));
+    Ok((b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24))
+  }
+}
+
+#[test]
+fn region_round_trip() {
+  use std::env;
+
+  let path = env::temp_dir().join("playform-region-round-trip");
+  let _ = fs::remove_dir_all(&path);
+  let (world, params) = World::open(&path, &Params::new(7)).unwrap();
+  assert_eq!(params, Params::new(7));
+
+  let mut samples = HashMap::new();
+  samples.insert(Point3::new(-1, 2, -3), Material::Stone);
+  samples.insert(Point3::new(4, -5, 6), Material::Empty);
+  world.save_region(&Point3::new(0, 0, 0), &samples).unwrap();
+
+  let mut other = Params::new(8);
+  other.amplitude = 32.0;
+  other.width = 256;
+  let (world, params) = World::open(&path, &other).unwrap();
+  assert_eq!(params, Params::new(7));
+  assert_eq!(world.load_region(&Point3::new(0, 0, 0)).unwrap(), samples);
+  assert!(world.load_region(&Point3::new(1, 0, 0)).unwrap().is_empty());
+
+  fs::remove_dir_all(&path).unwrap();
+}